@@ -0,0 +1,13 @@
+use std::env;
+use std::process::Command;
+
+// Plugins have to be built with the same compiler as the binary loading them, so the compiler's
+// version is baked into the version they're checked against.
+fn main() {
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let version = Command::new(rustc).arg("--version").output().ok()
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|version| version.trim().to_string())
+        .unwrap_or_else(|| "unknown rustc".to_string());
+    println!("cargo:rustc-env=EVE_RUSTC_VERSION={}", version);
+}
@@ -0,0 +1,27 @@
+// A plugin with a single watcher that does nothing, loaded by the plugin tests.
+
+#[macro_use]
+extern crate eve;
+
+use eve::indexes::WatchDiff;
+use eve::ops::Interner;
+use eve::watchers::Watcher;
+use eve::watchers::plugin::PluginRegistrar;
+
+struct NothingWatcher {
+    name: String,
+}
+
+impl Watcher for NothingWatcher {
+    fn get_name(& self) -> String {
+        self.name.clone()
+    }
+    fn set_name(&mut self, name: &str) {
+        self.name = name.to_string();
+    }
+    fn on_diff(&mut self, _:&mut Interner, _:WatchDiff) {}
+}
+
+eve_plugin!(|registrar: &mut PluginRegistrar| {
+    registrar.register(Box::new(NothingWatcher { name: "plugin/nothing".to_string() }));
+});
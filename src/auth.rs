@@ -0,0 +1,151 @@
+extern crate serde_json;
+
+use ops::{RawChange, Internable, s};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufReader;
+
+//-------------------------------------------------------------------------
+// Identity
+//-------------------------------------------------------------------------
+
+// The program that runs the server's own watchers, e.g. #process/spawn. Clients never get to send
+// it transactions directly, whatever their identity says.
+pub const SERVER_PROGRAM:&'static str = "server";
+
+fn own_client() -> Vec<String> { vec!["self".to_string()] }
+fn any() -> Vec<String> { vec!["*".to_string()] }
+
+// Patterns are either exact, `*` for anything, or end in `*` to match a prefix.
+fn matches(pattern:&str, value:&str) -> bool {
+    if pattern.ends_with("*") {
+        value.starts_with(&pattern[..pattern.len() - 1])
+    } else {
+        pattern == value
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Identity {
+    pub name: String,
+    #[serde(default)]
+    pub roles: Vec<String>,
+    // The client programs this identity may send transactions to. `self` is the connection's own
+    // program along with any program attached to it, e.g. its editor.
+    #[serde(default = "own_client")]
+    pub clients: Vec<String>,
+    // The attributes this identity may add or remove.
+    #[serde(default = "any")]
+    pub attributes: Vec<String>,
+}
+
+impl Identity {
+    pub fn anonymous() -> Identity {
+        Identity { name: "anonymous".to_string(), roles: vec![], clients: own_client(), attributes: any() }
+    }
+
+    pub fn can_send_to(&self, own:&str, client:&str) -> bool {
+        if client == SERVER_PROGRAM { return false; }
+        self.clients.iter().any(|pattern| {
+            if pattern == "self" {
+                client == own || client.starts_with(&format!("{}-", own))
+            } else {
+                matches(pattern, client)
+            }
+        })
+    }
+
+    pub fn can_write(&self, attribute:&str) -> bool {
+        self.attributes.iter().any(|pattern| matches(pattern, attribute))
+    }
+
+    pub fn to_raw_changes(&self, client:&str) -> Vec<RawChange> {
+        let id = s(&format!("{}{}", IDENTITY_PREFIX, client));
+        let mut changes = vec![
+            RawChange { e: id.clone(), a: s("tag"), v: s("client/identity"), n: s("auth"), count: 1 },
+            RawChange { e: id.clone(), a: s("name"), v: s(&self.name), n: s("auth"), count: 1 },
+            RawChange { e: id.clone(), a: s("client"), v: s(client), n: s("auth"), count: 1 },
+        ];
+        for role in self.roles.iter() {
+            changes.push(RawChange { e: id.clone(), a: s("role"), v: s(role), n: s("auth"), count: 1 });
+        }
+        changes
+    }
+}
+
+const IDENTITY_PREFIX:&'static str = "client/identity|";
+
+// Programs decide what a client may do from its #client/identity, so clients never get to touch
+// one, whatever their attribute rules say.
+pub fn is_reserved(change:&RawChange) -> bool {
+    let identity_record = match change.e {
+        Internable::String(ref e) => e.starts_with(IDENTITY_PREFIX),
+        _ => false,
+    };
+    identity_record || (change.a == s("tag") && change.v == s("client/identity"))
+}
+
+//-------------------------------------------------------------------------
+// Verifiers
+//-------------------------------------------------------------------------
+
+pub trait Verifier {
+    fn verify(&self, token:Option<&str>) -> Option<Identity>;
+}
+
+// Used when no authentication is configured, every connection may do anything.
+pub struct AllowAll;
+
+impl Verifier for AllowAll {
+    fn verify(&self, _:Option<&str>) -> Option<Identity> {
+        Some(Identity::anonymous())
+    }
+}
+
+#[derive(Deserialize)]
+struct TokenFile {
+    tokens: HashMap<String, Identity>,
+}
+
+// Checks tokens against a JSON file of the form:
+// {"tokens": {"<token>": {"name": "chris", "roles": ["admin"], "clients": ["self"], "attributes": ["*"]}}}
+pub struct TokenVerifier {
+    tokens: HashMap<String, Identity>,
+}
+
+impl TokenVerifier {
+    pub fn new(tokens:HashMap<String, Identity>) -> TokenVerifier {
+        TokenVerifier { tokens }
+    }
+
+    pub fn from_file(path:&str) -> Result<TokenVerifier, String> {
+        let file = File::open(path).map_err(|why| format!("Unable to open '{}': {}", path, why))?;
+        let parsed:TokenFile = serde_json::from_reader(BufReader::new(file)).map_err(|why| format!("Unable to parse '{}': {}", path, why))?;
+        Ok(TokenVerifier::new(parsed.tokens))
+    }
+}
+
+impl Verifier for TokenVerifier {
+    fn verify(&self, token:Option<&str>) -> Option<Identity> {
+        token.and_then(|token| self.tokens.get(token).cloned())
+    }
+}
+
+//-------------------------------------------------------------------------
+// Handshake
+//-------------------------------------------------------------------------
+
+// Pulls a parameter out of the query string of a request's resource, e.g. `/?token=abc`.
+pub fn query_param(resource:&str, name:&str) -> Option<String> {
+    let query = match resource.find('?') {
+        Some(ix) => &resource[ix + 1..],
+        None => return None,
+    };
+    for pair in query.split('&') {
+        let mut parts = pair.splitn(2, '=');
+        if parts.next() == Some(name) {
+            return Some(parts.next().unwrap_or("").to_string());
+        }
+    }
+    None
+}
@@ -1,5 +1,6 @@
 extern {}
 
+#[macro_use]
 extern crate eve;
 extern crate time;
 
@@ -7,10 +8,16 @@ extern crate clap;
 use clap::{Arg, App};
 
 use eve::paths::EvePaths;
-use eve::ops::{DebugMode, ProgramRunner, Persister};
-use eve::watchers::system::{SystemTimerWatcher, PanicWatcher};
-use eve::watchers::console::{ConsoleWatcher, PrintDiffWatcher};
+use eve::ops::{DebugMode, Program, ProgramRunner, Persister, TransactionLimits};
+use eve::record;
+use eve::logging::{self, LogConfig};
+use eve::compiler::parse_file;
+use eve::watchers::system::{SystemTimerWatcher, SystemScheduleWatcher, SystemStdinWatcher, PanicWatcher, env_changes, args_changes};
+use eve::watchers::console::{ConsoleWatcher, LogWatcher, PrintDiffWatcher};
 use eve::watchers::file::FileWatcher;
+use eve::watchers::process::ProcessWatcher;
+use eve::watchers::plugin::{PluginWatcher, load_plugin};
+use eve::watchers::http::HttpWatcher;
 
 //-------------------------------------------------------------------------
 // Main
@@ -34,7 +41,11 @@ fn main() {
              .takes_value(true))
         .arg(Arg::with_name("EVE_FILES")
              .help("The eve files and folders to load")
-             .required(true)
+             .required_unless("replay")
+             .multiple(true))
+        .arg(Arg::with_name("ARGS")
+             .help("Arguments for the program, given after a --. They show up as #system/args records")
+             .last(true)
              .multiple(true))
         .arg(Arg::with_name("clean")
              .short("C")
@@ -44,11 +55,63 @@ fn main() {
              .short("D")
              .long("debug")
              .value_name("MODE")
-             .help("Enable the specified debug mode. Options: ('compile')"))
+             .help("Enable the specified debug mode. Options: ('compile', 'profile', 'profile-json')"))
+        .arg(Arg::with_name("max-rounds")
+             .long("max-rounds")
+             .value_name("ROUNDS")
+             .help("Aborts any transaction that takes more than this many rounds to reach a fixpoint (1000)")
+             .takes_value(true))
+        .arg(Arg::with_name("max-frames")
+             .long("max-frames")
+             .value_name("FRAMES")
+             .help("Aborts any transaction that commits more than this many times in a row (1000)")
+             .takes_value(true))
+        .arg(Arg::with_name("explain")
+             .long("explain")
+             .value_name("BLOCK")
+             .help("Prints the compiled constraints and pipes for the named block, then exits")
+             .takes_value(true))
+        .arg(Arg::with_name("record")
+             .long("record")
+             .value_name("FILE")
+             .help("Records every transaction the program applies to FILE")
+             .takes_value(true))
+        .arg(Arg::with_name("replay")
+             .long("replay")
+             .value_name("FILE")
+             .help("Replays a recorded FILE into a fresh program, then exits")
+             .takes_value(true))
+        .arg(Arg::with_name("plugin")
+             .long("plugin")
+             .value_name("LIBRARY")
+             .help("Loads the watchers provided by a plugin shared LIBRARY")
+             .takes_value(true)
+             .multiple(true))
+        .args(&logging::log_args())
         .get_matches();
 
+    let log_config = LogConfig::from_matches(&matches);
+    logging::configure(log_config.unwrap_or_else(|why| panic!("{}", why)));
+
     let clean = matches.is_present("clean");
 
+    if let Some(log) = matches.value_of("replay") {
+        let entries = match record::read_log(log) {
+            Ok(entries) => entries,
+            Err(why) => panic!("{}", why),
+        };
+        let count = entries.len();
+        let mut program = Program::new("replay");
+        if !clean {
+            program.attach(Box::new(ConsoleWatcher::new()));
+            program.attach(Box::new(LogWatcher::new("replay")));
+            program.attach(Box::new(PrintDiffWatcher::new()));
+        }
+        record::replay(&mut program, entries);
+        log_info!("replay", "Replayed {} entries from {}", count, log);
+        return;
+    }
+
     let eve_paths = EvePaths::new(clean,
                                   matches.values_of("EVE_FILES").map_or(vec![], |files| files.collect()),
                                   matches.value_of("server-file").map_or(vec![], |file| vec![file]),
@@ -57,19 +120,70 @@ fn main() {
                                   matches.value_of("programs-path"));
 
     let mut runner = ProgramRunner::new("main");
+
+    if let Some(block_name) = matches.value_of("explain") {
+        let mut blocks = vec![];
+        let schemas = runner.program.watch_schemas();
+        if let &Some(path) = &eve_paths.libraries() {
+            blocks.extend(parse_file(&mut runner.program.state.interner, path, false, false, &schemas));
+        }
+        for file in eve_paths.files.iter() {
+            blocks.extend(parse_file(&mut runner.program.state.interner, file, false, false, &schemas));
+        }
+        for block in blocks {
+            runner.program.register_block(block);
+        }
+        match runner.program.explain(block_name) {
+            Some(explanation) => println!("{}", explanation),
+            None => println!("No block named '{}'", block_name),
+        }
+        return;
+    }
     matches.value_of("debug").map(|mode_name| runner.debug(match mode_name {
         "compile" => DebugMode::Compile,
+        "profile" => DebugMode::Profile,
+        "profile-json" => DebugMode::ProfileJson,
         _ => panic!("Unknown debug mode '{:?}'.", mode_name)
     }));
 
+    let mut limits = TransactionLimits::new();
+    if let Some(rounds) = matches.value_of("max-rounds") {
+        limits.max_rounds = rounds.parse().expect("--max-rounds must be a positive integer");
+    }
+    if let Some(frames) = matches.value_of("max-frames") {
+        limits.max_frames = frames.parse().expect("--max-frames must be a positive integer");
+    }
+    runner.limits(limits);
+
+    if let Some(log) = matches.value_of("record") {
+        if let Err(why) = runner.record(log) {
+            panic!("{}", why);
+        }
+    }
+
     let outgoing = runner.program.outgoing.clone();
     if !clean {
         runner.program.attach(Box::new(SystemTimerWatcher::new(outgoing.clone())));
+        runner.program.attach(Box::new(SystemScheduleWatcher::new(outgoing.clone())));
+        runner.program.attach(Box::new(SystemStdinWatcher::new(outgoing.clone())));
         runner.program.attach(Box::new(FileWatcher::new(outgoing.clone())));
+        runner.program.attach(Box::new(HttpWatcher::new(outgoing.clone())));
+        runner.program.attach(Box::new(ProcessWatcher::new(outgoing.clone())));
+        runner.program.attach(Box::new(PluginWatcher::new(outgoing.clone())));
         runner.program.attach(Box::new(ConsoleWatcher::new()));
+        runner.program.attach(Box::new(LogWatcher::new("main")));
         runner.program.attach(Box::new(PrintDiffWatcher::new()));
         runner.program.attach(Box::new(PanicWatcher::new()));
     }
+    for plugin in matches.values_of("plugin").map_or(vec![], |plugins| plugins.collect()) {
+        for watcher in load_plugin(plugin, outgoing.clone()).unwrap_or_else(|why| panic!("{}", why)) {
+            runner.program.attach(watcher);
+        }
+    }
+
+    let args:Vec<String> = matches.values_of("ARGS").map_or(vec![], |args| args.map(|arg| arg.to_owned()).collect());
+    runner.startup(env_changes());
+    runner.startup(args_changes(&args));
 
     if let Some(persist_file) = eve_paths.persist() {
         let mut persister = Persister::new(persist_file);
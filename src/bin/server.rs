@@ -6,11 +6,9 @@ extern crate clap;
 use clap::{Arg, App};
 
 extern crate ws;
-use ws::{listen, Message, Sender as WSSender, Handler, CloseCode};
+use ws::{listen, Message, Sender as WSSender, Handler, Handshake, CloseCode};
 
 #[macro_use]
-extern crate serde_derive;
-
 extern crate serde_json;
 extern crate serde;
 use serde_json::{Error};
@@ -23,29 +21,41 @@ use std::time::Duration;
 
 extern crate time;
 
+extern crate rand;
+use rand::Rng;
+
+#[macro_use]
 extern crate eve;
 use eve::paths::EvePaths;
-use eve::ops::{ProgramRunner, RunLoop, RunLoopMessage, RawChange, Internable, Persister, JSONInternable};
-use eve::watchers::system::{SystemTimerWatcher, PanicWatcher};
+use eve::logging::{self, LogConfig};
+use eve::session::Sessions;
+use eve::auth::{Identity, Verifier, AllowAll, TokenVerifier, query_param, is_reserved, SERVER_PROGRAM};
+use eve::ops::{ProgramRunner, RunLoop, RunLoopMessage, RawChange, Internable, Persister, TransactionLimits, PauseLimits, PauseOverflow};
+use eve::watchers::system::{SystemTimerWatcher, SystemScheduleWatcher, PanicWatcher};
 use eve::watchers::compiler::{CompilerWatcher};
 use eve::watchers::textcompiler::{RawTextCompilerWatcher};
-use eve::watchers::console::{ConsoleWatcher};
+use eve::watchers::console::{ConsoleWatcher, LogWatcher};
 use eve::watchers::file::{FileWatcher};
+use eve::watchers::process::ProcessWatcher;
+use eve::watchers::plugin::{PluginWatcher, load_plugin};
+use eve::watchers::http::{HttpWatcher, HttpRoutes};
 use eve::watchers::editor::EditorWatcher;
 use eve::watchers::remote::{Router, RouterMessage, RemoteWatcher};
-use eve::watchers::websocket::WebsocketClientWatcher;
+use eve::watchers::websocket::{WebsocketClientWatcher, ClientSocket, SocketConfig, OverflowPolicy, ClientMessage, unbatch};
+use eve::wire::{WireFormat, WireDecoder, WireMessage};
 
 extern crate iron;
 extern crate staticfile;
 extern crate mount;
 
-use iron::{Iron, Chain, status, Request, Response, IronResult, IronError, AfterMiddleware};
+use iron::{Iron, Chain, status, Request, Response, IronResult, IronError, AfterMiddleware, Handler as IronHandler};
 use staticfile::Static;
 use mount::Mount;
 use std::thread;
 use std::sync::{Arc, Mutex};
 use std::ops::Deref;
 use std::collections::HashSet;
+use std::io::Read;
 
 extern crate term_painter;
 use self::term_painter::ToStyle;
@@ -55,36 +65,98 @@ use self::term_painter::Color::*;
 // Websocket client handler
 //-------------------------------------------------------------------------
 
-#[derive(Serialize, Deserialize, Debug)]
-pub enum ClientMessage {
-    Block { id:String, code:String },
-    RemoveBlock { id:String },
-    Transaction { client:String, adds: Vec<(JSONInternable, JSONInternable, JSONInternable)>, removes: Vec<(JSONInternable, JSONInternable, JSONInternable)> },
+// A client program whose connection has gone away, kept running for the grace period in case
+// the client comes back.
+pub struct DetachedSession {
+    client_name: String,
+    running: RunLoop,
+    socket: ClientSocket,
 }
 
-pub struct ClientHandler {
+pub type ClientSessions = Sessions<DetachedSession>;
+
+pub struct ClientHandler<'a> {
     out: WSSender,
-    running: RunLoop,
+    running: Option<RunLoop>,
     client_name: String,
     router: Arc<Mutex<Router>>,
-    router_channel: Sender<RouterMessage>
+    router_channel: Sender<RouterMessage>,
+    identity: Option<Identity>,
+    format: WireFormat,
+    decoder: WireDecoder,
+    session: Option<(String, ClientSocket)>,
+    sessions: ClientSessions,
+    eve_paths: &'a EvePaths<'a>,
+    eve_flags: &'a EveFlags,
 }
 
-impl ClientHandler {
-    pub fn new(client_name:&str, out:WSSender, router: Arc<Mutex<Router>>, eve_paths:&EvePaths, eve_flags:&EveFlags) -> ClientHandler {
+impl<'a> ClientHandler<'a> {
+    pub fn new(client_name:&str, out:WSSender, router: Arc<Mutex<Router>>, sessions: ClientSessions, eve_paths:&'a EvePaths<'a>, eve_flags:&'a EveFlags) -> ClientHandler<'a> {
         let router_channel = router.lock().expect("ERROR: Failed to lock router: Cannot clone channel.").deref().get_channel();
+        ClientHandler {out, running: None, client_name: client_name.to_owned(), router, router_channel, identity: None, format: WireFormat::Json, decoder: WireDecoder::new(), session: None, sessions, eve_paths, eve_flags }
+    }
+
+    fn resume(&mut self, session_id:String, session:DetachedSession, identity:&Identity) {
+        log_info!(&session.client_name, "Resuming for '{}'", &identity.name);
+        self.client_name = session.client_name;
+        session.socket.attach(self.out.clone(), self.format, Some(&session_id));
+        self.running = Some(session.running);
+        self.session = Some((session_id, session.socket));
+    }
+
+    // Keeps the client program around for the grace period so a reconnecting client can pick it
+    // back up, and tears it down if nobody does.
+    fn detach(&mut self, running:RunLoop, session_id:String, socket:ClientSocket, identity:Identity) {
+        let session = DetachedSession { client_name: self.client_name.to_owned(), running, socket };
+        let router = self.router.clone();
+        self.sessions.detach(&session_id, &identity.name, session, move |session| {
+            log_info!(&session.client_name, "Session expired");
+            router.lock().unwrap().unregister(&session.client_name);
+            session.running.close();
+        });
+    }
+
+    // The client's program is only started once the connection has been authenticated.
+    fn start(&mut self, identity:&Identity) {
+        let client_name = &self.client_name[..];
+        let out = &self.out;
+        let router = &self.router;
+        let eve_paths = self.eve_paths;
+        let eve_flags = self.eve_flags;
         let mut runner = ProgramRunner::new(client_name);
+        runner.limits(eve_flags.limits);
+        runner.pause_limits(eve_flags.pause_limits);
+        if let Some(ref dir) = eve_flags.record_dir {
+            if let Err(why) = runner.record(&format!("{}/{}.jsonl", dir, client_name)) {
+                log_error!(client_name, "{}", why);
+            }
+        }
         let outgoing = runner.program.outgoing.clone();
+        let session_id = format!("{:x}", rand::thread_rng().next_u64());
+        let socket = ClientSocket::new(out.clone(), self.format, client_name, eve_flags.socket_config);
         router.lock().expect("ERROR: Failed to lock router: Cannot register new client.").register(&client_name, outgoing.clone());
         if !eve_flags.clean {
             runner.program.attach(Box::new(SystemTimerWatcher::new(outgoing.clone())));
+            runner.program.attach(Box::new(SystemScheduleWatcher::new(outgoing.clone())));
             runner.program.attach(Box::new(CompilerWatcher::new(outgoing.clone(), false)));
             runner.program.attach(Box::new(RawTextCompilerWatcher::new(outgoing.clone())));
             runner.program.attach(Box::new(FileWatcher::new(outgoing.clone())));
-            runner.program.attach(Box::new(WebsocketClientWatcher::new(out.clone(), client_name)));
+            // Outbound requests go from the server's machine, so a client only gets to make them
+            // when asked for.
+            if eve_flags.allow_client_http {
+                runner.program.attach(Box::new(HttpWatcher::new(outgoing.clone())));
+            }
+            runner.program.attach(Box::new(WebsocketClientWatcher::with_socket(socket.clone(), Some(&session_id))));
             runner.program.attach(Box::new(ConsoleWatcher::new()));
+            runner.program.attach(Box::new(LogWatcher::new(client_name)));
             runner.program.attach(Box::new(PanicWatcher::new()));
             runner.program.attach(Box::new(RemoteWatcher::new(client_name, &router.lock().expect("ERROR: Failed to lock router: Cannot init RemoteWatcher.").deref())));
+            for plugin in eve_flags.plugins.iter() {
+                match load_plugin(plugin, outgoing.clone()) {
+                    Ok(watchers) => for watcher in watchers { runner.program.attach(watcher); },
+                    Err(why) => log_error!(client_name, "{}", why),
+                }
+            }
             if eve_flags.editor {
                 let editor_watcher = EditorWatcher::new(&mut runner, router.clone(), out.clone(), eve_paths.libraries(), eve_paths.programs());
                 runner.program.attach(Box::new(editor_watcher));
@@ -97,19 +169,88 @@ impl ClientHandler {
         for file in eve_paths.files.iter() {
             runner.load(file);
         }
+        outgoing.send(RunLoopMessage::Transaction(identity.to_raw_changes(client_name))).unwrap();
 
         let running = runner.run();
 
         if eve_flags.watch {
-            println!("Starting file watcher!");
+            log_debug!(client_name, "Starting file watcher");
             ClientHandler::make_file_notifier(eve_paths, &running);
         }
 
-        ClientHandler {out, running, client_name: client_name.to_owned(), router, router_channel }
+        self.running = Some(running);
+        self.session = Some((session_id, socket));
+    }
+
+    fn handle_message(&mut self, message:ClientMessage) {
+        match message {
+            ClientMessage::Transaction { client, adds, removes } => {
+                let identity = match self.identity {
+                    Some(ref identity) => identity,
+                    None => return,
+                };
+                if !identity.can_send_to(&self.client_name, &client) {
+                    self.deny(&client, format!("'{}' may not send transactions to '{}'", &identity.name, &client));
+                    return;
+                }
+                let mut raw_changes = vec![];
+                raw_changes.extend(adds.into_iter().map(|(e,a,v)| {
+                    RawChange { e:e.into(), a:a.into(), v:v.into(), n:Internable::String("input".to_string()),count:1 }
+                }));
+                raw_changes.extend(removes.into_iter().map(|(e,a,v)| {
+                    RawChange { e:e.into(), a:a.into(), v:v.into(), n:Internable::String("input".to_string()),count:-1 }
+                }));
+                if raw_changes.iter().any(is_reserved) {
+                    self.deny(&client, format!("'{}' may not change client identities", &identity.name));
+                    return;
+                }
+                if let Some(change) = raw_changes.iter().find(|change| !identity.can_write(&Internable::to_string(&change.a))) {
+                    self.deny(&client, format!("'{}' may not write the attribute '{}'", &identity.name, Internable::to_string(&change.a)));
+                    return;
+                }
+
+                self.router_channel.send(RouterMessage::Local(client, raw_changes)).expect("ERROR: Failed to send message to client");
+            }
+            ClientMessage::Explain { block } => {
+                // The run loop answers on its own thread, so we wait for the reply off the socket's thread.
+                let (reply, response) = mpsc::channel();
+                match self.running {
+                    Some(ref running) => running.send(RunLoopMessage::Explain(block.to_owned(), reply)),
+                    None => return,
+                }
+                let out = self.out.clone();
+                let client = self.client_name.to_owned();
+                thread::Builder::new().name(format!("{} explain", client)).spawn(move || {
+                    if let Ok(explanation) = response.recv_timeout(Duration::from_secs(5)) {
+                        let text = serde_json::to_string(&json!({"type": "explain", "client": client, "block": block, "explanation": explanation})).unwrap();
+                        out.send(Message::Text(text)).ok();
+                    }
+                }).unwrap();
+            }
+            _ => { }
+        }
+    }
+
+    fn deny(&self, client:&str, message:String) {
+        log_warn!(&self.client_name, "Denied: {}", message);
+        let text = serde_json::to_string(&json!({"type": "error", "client": client, "error": message})).unwrap();
+        self.out.send(Message::Text(text)).ok();
+    }
+
+    // Binary clients only send transactions, everything else still goes over JSON.
+    fn decode_binary(&mut self, bytes:&[u8]) -> Result<ClientMessage, String> {
+        match self.decoder.decode(bytes)? {
+            WireMessage::Transaction { client, adds, removes, .. } => {
+                let adds = adds.iter().map(|eav| self.decoder.eav(eav)).collect::<Result<Vec<_>, String>>()?;
+                let removes = removes.iter().map(|eav| self.decoder.eav(eav)).collect::<Result<Vec<_>, String>>()?;
+                Ok(ClientMessage::Transaction { client, adds, removes })
+            }
+            WireMessage::Diff { .. } => Err("Clients can't send diffs".to_string()),
+        }
     }
 
     fn make_file_notifier(eve_paths:&EvePaths, run_loop:&RunLoop) {
-        println!("WARN: @TODO: Make this die when the client DC's!");
+        // @TODO: Make this die when the client DC's!
         let client_channel = run_loop.channel();
         let files:Vec<String> = eve_paths.files.iter().map(|f| f.to_string()).collect();
         let libraries = eve_paths.libraries().map(|s| s.to_owned());
@@ -147,7 +288,7 @@ impl ClientHandler {
                     Ok(event) => {
                         match event {
                             DebouncedEvent::Error(err, ..) => {
-                                println!("{} {:?}! Closing client file watcher...", BrightRed.paint("Error:"), err);
+                                log_error!("file watcher", "{:?}! Closing client file watcher...", err);
                                 break;
                             },
                             DebouncedEvent::NoticeRemove(path) |
@@ -171,7 +312,7 @@ impl ClientHandler {
                                 if should_reload {
                                     dirty.insert(path);
                                     if let Err(_) = client_channel.send(RunLoopMessage::Reload(dirty.clone())) {
-                                        println!("Closing client file watcher.");
+                                        log_debug!("file watcher", "Closing client file watcher");
                                         break;
                                     }
                                     dirty.clear();
@@ -183,49 +324,79 @@ impl ClientHandler {
                             }
                         };
                     },
-                    Err(err) => println!("ERROR: {:?}", err)
+                    Err(err) => log_error!("file watcher", "{:?}", err)
                 }
             }
         });
     }
 }
 
-impl Handler for ClientHandler {
+impl<'a> Handler for ClientHandler<'a> {
 
     //fn on_request(&mut self, req: &ws::Request) -> Result<ws::Response,ws::Error> {
     //println!("Handler received request:\n{:?}");
     //ws::Response::from_request(req)
     //}
 
+    fn on_open(&mut self, shake: Handshake) -> Result<(), ws::Error> {
+        let token = query_param(shake.request.resource(), "token");
+        let session_id = query_param(shake.request.resource(), "session");
+        self.format = WireFormat::from_name(query_param(shake.request.resource(), "format").as_ref().map(|format| &format[..]));
+        match self.eve_flags.verifier.verify(token.as_ref().map(|token| &token[..])) {
+            Some(identity) => {
+                log_info!(&self.client_name, "Authenticated as '{}'", &identity.name);
+                let resumable = session_id.and_then(|session_id| {
+                    self.sessions.resume(&session_id, &identity.name).map(|session| (session_id, session))
+                });
+                match resumable {
+                    Some((session_id, session)) => self.resume(session_id, session, &identity),
+                    None => self.start(&identity),
+                }
+                self.identity = Some(identity);
+                Ok(())
+            }
+            None => {
+                log_warn!(&self.client_name, "Denied: presented an invalid token");
+                self.out.close_with_reason(CloseCode::Policy, "Invalid token")
+            }
+        }
+    }
+
     fn on_message(&mut self, msg: Message) -> Result<(), ws::Error> {
         // println!("Server got message '{}'. ", msg);
-        if let Message::Text(s) = msg {
-            let deserialized: Result<ClientMessage, Error> = serde_json::from_str(&s);
-            // println!("deserialized = {:?}", deserialized);
-            match deserialized {
-                Ok(ClientMessage::Transaction { client, adds, removes }) => {
-                    let mut raw_changes = vec![];
-                    raw_changes.extend(adds.into_iter().map(|(e,a,v)| {
-                        RawChange { e:e.into(), a:a.into(), v:v.into(), n:Internable::String("input".to_string()),count:1 }
-                    }));
-                    raw_changes.extend(removes.into_iter().map(|(e,a,v)| {
-                        RawChange { e:e.into(), a:a.into(), v:v.into(), n:Internable::String("input".to_string()),count:-1 }
-                    }));
-
-                    self.router_channel.send(RouterMessage::Local(client, raw_changes)).expect("ERROR: Failed to send message to client");
+        match msg {
+            Message::Text(s) => {
+                let deserialized: Result<ClientMessage, Error> = serde_json::from_str(&s);
+                // println!("deserialized = {:?}", deserialized);
+                if let Ok(message) = deserialized {
+                    for message in unbatch(&self.client_name, message) {
+                        self.handle_message(message);
+                    }
+                }
+            }
+            Message::Binary(bytes) => {
+                match self.decode_binary(&bytes) {
+                    Ok(message) => self.handle_message(message),
+                    Err(why) => log_warn!(&self.client_name, "Unable to decode binary message: {}", why),
                 }
-                _ => { }
             }
-            Ok(())
-        } else {
-            Ok(())
         }
+        Ok(())
     }
 
     fn on_close(&mut self, code: CloseCode, reason: &str) {
-        println!("WebSocket closing for ({:?}) {}", code, reason);
-        self.router.lock().unwrap().unregister(&self.client_name);
-        self.running.close();
+        log_info!(&self.client_name, "WebSocket closing ({:?}) {}", code, reason);
+        if let Some(running) = self.running.take() {
+            match (self.session.take(), self.identity.take(), self.sessions.resumable()) {
+                (Some((session_id, socket)), Some(identity), true) => {
+                    self.detach(running, session_id, socket, identity);
+                }
+                _ => {
+                    self.router.lock().unwrap().unregister(&self.client_name);
+                    running.close();
+                }
+            }
+        }
     }
 }
 
@@ -241,10 +412,39 @@ impl AfterMiddleware for Custom404 {
     }
 }
 
-fn http_server(address: String) -> std::thread::JoinHandle<()> {
+//-------------------------------------------------------------------------
+// Eve Routes
+//-------------------------------------------------------------------------
+
+struct EveRoutes {
+    routes: HttpRoutes,
+}
+
+impl IronHandler for EveRoutes {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let method = req.method.to_string();
+        let path = format!("/{}", req.url.path().join("/"));
+        let query = req.url.query().map(|query| query.to_string());
+        let headers = req.headers.iter().map(|header| (header.name().to_string(), header.value_string())).collect();
+        let mut body = String::new();
+        if let Err(why) = req.body.read_to_string(&mut body) {
+            return Ok(Response::with((status::BadRequest, why.to_string())));
+        }
+
+        let response = self.routes.handle(&method, &path, query, headers, body);
+        let mut res = Response::with((status::Status::from_u16(response.status), response.body));
+        for (name, value) in response.headers {
+            res.headers.append_raw(name, value.into_bytes());
+        }
+        Ok(res)
+    }
+}
+
+fn http_server(address: String, routes: HttpRoutes, routes_prefix: String) -> std::thread::JoinHandle<()> {
     thread::spawn(move || {
         let mut mount = Mount::new();
         mount.mount("/", Static::new(Path::new("assets/index.html")));
+        mount.mount(&routes_prefix, EveRoutes { routes });
         mount.mount("/assets/", Static::new(Path::new("assets/")));
         mount.mount("/dist/", Static::new(Path::new("dist/")));
         mount.mount("/examples/", Static::new(Path::new("examples/")));
@@ -252,31 +452,60 @@ fn http_server(address: String) -> std::thread::JoinHandle<()> {
         let mut chain = Chain::new(mount);
         chain.link_after(Custom404);
 
-        println!("{} HTTP Server at {}... ", BrightGreen.paint("Starting:"), address);
+        log_info!("server", "Starting HTTP server at {}...", address);
         match Iron::new(chain).http(&address) {
             Ok(_) => {},
-            Err(why) => println!("{} Failed to start HTTP Server: {}", BrightRed.paint("Error:"), why),
+            Err(why) => log_error!("server", "Failed to start HTTP server: {}", why),
         };
 
     })
 }
 
-fn websocket_server(address: String, eve_paths:&EvePaths, eve_flags:&EveFlags) {
-    println!("{} Websocket Server at {}... ", BrightGreen.paint("Starting:"), address);
+fn websocket_server(address: String, http_address: String, eve_paths:&EvePaths, eve_flags:&EveFlags) {
+    log_info!("server", "Starting websocket server at {}...", address);
 
     // create a server program
-    let mut runner = ProgramRunner::new("server");
+    let mut runner = ProgramRunner::new(SERVER_PROGRAM);
+    runner.limits(eve_flags.limits);
     let outgoing = runner.program.outgoing.clone();
     let router = Arc::new(Mutex::new(Router::new(outgoing.clone())));
-    router.lock().unwrap().register("server", outgoing.clone());
+    router.lock().unwrap().register(SERVER_PROGRAM, outgoing.clone());
+    if let Some(ref peer_address) = eve_flags.peer_address {
+        router.lock().unwrap().listen_for_peers(&eve_flags.peer_name, peer_address, &eve_flags.peer_secret);
+    }
+    for peer in eve_flags.peers.iter() {
+        router.lock().unwrap().connect_peer(&eve_flags.peer_name, peer, &eve_flags.peer_secret);
+    }
+
+    // HTTP requests under the routes prefix are answered by the server program.
+    let routes = HttpRoutes::new(outgoing.clone(), eve_flags.http_timeout);
+    http_server(http_address, routes.clone(), eve_flags.routes_prefix.to_owned());
 
     if !eve_flags.clean {
+        runner.program.attach(Box::new(routes.watcher()));
         runner.program.attach(Box::new(SystemTimerWatcher::new(outgoing.clone())));
+        runner.program.attach(Box::new(SystemScheduleWatcher::new(outgoing.clone())));
         runner.program.attach(Box::new(CompilerWatcher::new(outgoing.clone(), false)));
+        runner.program.attach(Box::new(HttpWatcher::new(outgoing.clone())));
+        // Anything the server program commits can run a command or load code, so both are only
+        // allowed when asked for. Clients can't send it transactions, but its blocks still see
+        // what they share.
+        if eve_flags.allow_process {
+            runner.program.attach(Box::new(ProcessWatcher::new(outgoing.clone())));
+        }
+        if eve_flags.allow_plugins {
+            runner.program.attach(Box::new(PluginWatcher::new(outgoing.clone())));
+        }
         runner.program.attach(Box::new(RawTextCompilerWatcher::new(outgoing)));
         runner.program.attach(Box::new(ConsoleWatcher::new()));
+        runner.program.attach(Box::new(LogWatcher::new("server")));
         runner.program.attach(Box::new(PanicWatcher::new()));
-        runner.program.attach(Box::new(RemoteWatcher::new("server", &router.lock().unwrap().deref())));
+        runner.program.attach(Box::new(RemoteWatcher::new(SERVER_PROGRAM, &router.lock().unwrap().deref())));
+    }
+    for plugin in eve_flags.plugins.iter() {
+        for watcher in load_plugin(plugin, runner.program.outgoing.clone()).unwrap_or_else(|why| panic!("{}", why)) {
+            runner.program.attach(watcher);
+        }
     }
 
     if let &Some(persist_file) = &eve_paths.persist() {
@@ -285,20 +514,37 @@ fn websocket_server(address: String, eve_paths:&EvePaths, eve_flags:&EveFlags) {
         runner.persist(&mut persister);
     }
 
+    // The server program doesn't get the full set of libraries, but it needs the http one to
+    // answer requests, along with the ones for the watchers only it has.
+    let mut libraries = vec!["http"];
+    if eve_flags.allow_process {
+        libraries.push("process");
+    }
+    if eve_flags.allow_plugins {
+        libraries.push("plugin");
+    }
+    for library in libraries {
+        if let Some(path) = eve_paths.libraries_path.as_ref().map(|path| path.join(library)) {
+            if path.exists() {
+                runner.load(path.to_str().unwrap());
+            }
+        }
+    }
     for file in eve_paths.server_files.iter() {
         runner.load(file);
     }
 
     runner.run();
     let mut ix = 0;
+    let sessions:ClientSessions = Sessions::new(eve_flags.session_grace);
 
     match listen(address, |out| {
         ix += 1;
         let client_name = format!("ws_client_{}", ix);
-        ClientHandler::new(&client_name, out, router.clone(), eve_paths, eve_flags)
+        ClientHandler::new(&client_name, out, router.clone(), sessions.clone(), eve_paths, eve_flags)
     }) {
         Ok(_) => {},
-        Err(why) => println!("{} Failed to start Websocket Server: {}", BrightRed.paint("Error:"), why),
+        Err(why) => log_error!("server", "Failed to start websocket server: {}", why),
     };
 }
 
@@ -309,7 +555,23 @@ fn websocket_server(address: String, eve_paths:&EvePaths, eve_flags:&EveFlags) {
 pub struct EveFlags {
     editor: bool,
     watch: bool,
-    clean: bool
+    clean: bool,
+    limits: TransactionLimits,
+    pause_limits: PauseLimits,
+    routes_prefix: String,
+    http_timeout: Duration,
+    verifier: Box<Verifier>,
+    session_grace: Duration,
+    socket_config: SocketConfig,
+    peer_name: String,
+    peer_address: Option<String>,
+    peers: Vec<String>,
+    peer_secret: String,
+    record_dir: Option<String>,
+    allow_process: bool,
+    allow_plugins: bool,
+    allow_client_http: bool,
+    plugins: Vec<String>,
 }
 
 fn main() {
@@ -346,6 +608,15 @@ fn main() {
              .value_name("FILE")
              .help("Loads the specified file into the server instance")
              .takes_value(true))
+        .arg(Arg::with_name("allow-process")
+             .long("allow-process")
+             .help("Lets the server program run commands with #process/spawn"))
+        .arg(Arg::with_name("allow-plugins")
+             .long("allow-plugins")
+             .help("Lets the server program load plugins with #plugin/load"))
+        .arg(Arg::with_name("allow-client-http")
+             .long("allow-client-http")
+             .help("Lets client programs send requests with #http/request"))
         .arg(Arg::with_name("port")
              .short("p")
              .long("port")
@@ -358,6 +629,62 @@ fn main() {
              .value_name("PORT")
              .help("Sets the port for the HTTP server (8081)")
              .takes_value(true))
+        .arg(Arg::with_name("auth")
+             .long("auth")
+             .value_name("FILE")
+             .help("Requires websocket clients to connect with a token listed in FILE")
+             .takes_value(true))
+        .arg(Arg::with_name("session-grace")
+             .long("session-grace")
+             .value_name("SECONDS")
+             .help("Keeps a disconnected client's program running this long so a reconnecting client can resume it (30)")
+             .takes_value(true))
+        .arg(Arg::with_name("ws-tick")
+             .long("ws-tick")
+             .value_name("MS")
+             .help("How often batched diffs are sent to each websocket client (16)")
+             .takes_value(true))
+        .arg(Arg::with_name("ws-max-pending")
+             .long("ws-max-pending")
+             .value_name("ROWS")
+             .help("How many unsent rows a slow websocket client may fall behind by (10000)")
+             .takes_value(true))
+        .arg(Arg::with_name("ws-overflow")
+             .long("ws-overflow")
+             .value_name("POLICY")
+             .help("What to do when a client falls too far behind. Options: ('merge', 'drop') (merge)")
+             .takes_value(true))
+        .arg(Arg::with_name("routes")
+             .long("routes")
+             .value_name("PREFIX")
+             .help("Sets the path under which HTTP requests are handled by the server program (/api/)")
+             .takes_value(true))
+        .arg(Arg::with_name("http-timeout")
+             .long("http-timeout")
+             .value_name("SECONDS")
+             .help("Responds with a 504 if the server program doesn't answer an HTTP request in time (30)")
+             .takes_value(true))
+        .arg(Arg::with_name("peer-port")
+             .long("peer-port")
+             .value_name("PORT")
+             .help("Accepts connections from other Eve servers on this port so their programs can reach ours")
+             .takes_value(true))
+        .arg(Arg::with_name("peer")
+             .long("peer")
+             .value_name("URL")
+             .help("Connects to another Eve server's peer port, e.g. ws://127.0.0.1:3013")
+             .takes_value(true)
+             .multiple(true))
+        .arg(Arg::with_name("peer-secret")
+             .long("peer-secret")
+             .value_name("SECRET")
+             .help("The secret shared by all peers, required with --peer-port or --peer")
+             .takes_value(true))
+        .arg(Arg::with_name("name")
+             .long("name")
+             .value_name("NAME")
+             .help("How this server introduces itself to its peers (ADDRESS:PORT)")
+             .takes_value(true))
         .arg(Arg::with_name("address")
              .short("a")
              .long("address")
@@ -368,13 +695,114 @@ fn main() {
              .short("C")
              .long("clean")
              .help("Starts Eve with a clean database and no watchers (false)"))
+        .arg(Arg::with_name("max-rounds")
+             .long("max-rounds")
+             .value_name("ROUNDS")
+             .help("Aborts any transaction that takes more than this many rounds to reach a fixpoint (1000)")
+             .takes_value(true))
+        .arg(Arg::with_name("max-frames")
+             .long("max-frames")
+             .value_name("FRAMES")
+             .help("Aborts any transaction that commits more than this many times in a row (1000)")
+             .takes_value(true))
+        .arg(Arg::with_name("pause-queue")
+             .long("pause-queue")
+             .value_name("TRANSACTIONS")
+             .help("How many transactions a paused client program holds onto (10000)")
+             .takes_value(true))
+        .arg(Arg::with_name("pause-overflow")
+             .long("pause-overflow")
+             .value_name("POLICY")
+             .help("What a paused program drops once its queue is full. Options: ('newest', 'oldest') (newest)")
+             .takes_value(true))
+        .arg(Arg::with_name("record")
+             .long("record")
+             .value_name("DIR")
+             .help("Records each client program's transactions to DIR/<client>.jsonl for replay")
+             .takes_value(true))
+        .arg(Arg::with_name("plugin")
+             .long("plugin")
+             .value_name("LIBRARY")
+             .help("Loads the watchers provided by a plugin shared LIBRARY")
+             .takes_value(true)
+             .multiple(true))
+        .args(&logging::log_args())
         .get_matches();
 
-    println!("");
+    let log_config = LogConfig::from_matches(&matches);
+    logging::configure(log_config.unwrap_or_else(|why| panic!("{}", why)));
+
+    let mut limits = TransactionLimits::new();
+    if let Some(rounds) = matches.value_of("max-rounds") {
+        limits.max_rounds = rounds.parse().expect("--max-rounds must be a positive integer");
+    }
+    if let Some(frames) = matches.value_of("max-frames") {
+        limits.max_frames = frames.parse().expect("--max-frames must be a positive integer");
+    }
+
+    let mut pause_limits = PauseLimits::new();
+    if let Some(max_queued) = matches.value_of("pause-queue") {
+        pause_limits.max_queued = max_queued.parse().expect("--pause-queue must be a positive integer");
+    }
+    pause_limits.overflow = match matches.value_of("pause-overflow") {
+        Some("oldest") => PauseOverflow::DropOldest,
+        Some("newest") | None => PauseOverflow::DropNewest,
+        Some(other) => panic!("Unknown --pause-overflow policy '{}'", other),
+    };
+
+    let mut socket_config = SocketConfig::new();
+    if let Some(tick) = matches.value_of("ws-tick") {
+        socket_config.tick = Duration::from_millis(tick.parse().expect("--ws-tick must be a positive integer"));
+    }
+    if let Some(max_pending) = matches.value_of("ws-max-pending") {
+        socket_config.max_pending = max_pending.parse().expect("--ws-max-pending must be a positive integer");
+    }
+    socket_config.overflow = match matches.value_of("ws-overflow") {
+        Some("drop") => OverflowPolicy::Drop,
+        Some("merge") | None => OverflowPolicy::Merge,
+        Some(other) => panic!("Unknown --ws-overflow policy '{}'", other),
+    };
+
+    let session_grace = matches.value_of("session-grace").unwrap_or("30").parse().expect("--session-grace must be a positive integer");
+    let http_timeout = matches.value_of("http-timeout").unwrap_or("30").parse().expect("--http-timeout must be a positive integer");
+
+    let verifier:Box<Verifier> = match matches.value_of("auth") {
+        Some(path) => Box::new(TokenVerifier::from_file(path).unwrap_or_else(|why| panic!("{} {}", BrightRed.paint("Error:"), why))),
+        None => Box::new(AllowAll),
+    };
+
+    let peers:Vec<String> = matches.values_of("peer").map_or(vec![], |peers| peers.map(|peer| peer.to_owned()).collect());
+    let peer_secret = match matches.value_of("peer-secret") {
+        Some(secret) => secret.to_owned(),
+        None if matches.is_present("peer-port") || peers.len() > 0 => panic!("{} --peer-secret is required to talk to other servers", BrightRed.paint("Error:")),
+        None => String::new(),
+    };
+
+    let wport = matches.value_of("port").unwrap_or("3012");
+    let hport = matches.value_of("http-port").unwrap_or("8081");
+    let address = matches.value_of("address").unwrap_or("127.0.0.1");
+    let http_address = format!("{}:{}",address,hport);
+    let websocket_address = format!("{}:{}",address,wport);
 
     let eve_flags = EveFlags{clean: matches.is_present("clean"),
                              editor: matches.is_present("editor"),
-                             watch: matches.is_present("watch")};
+                             watch: matches.is_present("watch"),
+                             limits,
+                             pause_limits,
+                             routes_prefix: matches.value_of("routes").unwrap_or("/api/").to_owned(),
+                             http_timeout: Duration::from_secs(http_timeout),
+                             verifier,
+                             session_grace: Duration::from_secs(session_grace),
+                             socket_config,
+                             peer_name: matches.value_of("name").map(|name| name.to_owned()).unwrap_or_else(|| websocket_address.to_owned()),
+                             peer_address: matches.value_of("peer-port").map(|port| format!("{}:{}", address, port)),
+                             peers,
+                             peer_secret,
+                             record_dir: matches.value_of("record").map(|dir| dir.to_owned()),
+                             allow_process: matches.is_present("allow-process"),
+                             allow_plugins: matches.is_present("allow-plugins"),
+                             allow_client_http: matches.is_present("allow-client-http"),
+                             plugins: matches.values_of("plugin").map_or(vec![], |plugins| plugins.map(|plugin| plugin.to_owned()).collect())};
 
     let eve_paths = EvePaths::new(eve_flags.clean,
                                   matches.values_of("EVE_FILES").map_or(vec![], |files| files.collect()),
@@ -383,12 +811,5 @@ fn main() {
                                   matches.value_of("libraries-path"),
                                   matches.value_of("programs-path"));
 
-    let wport = matches.value_of("port").unwrap_or("3012");
-    let hport = matches.value_of("http-port").unwrap_or("8081");
-    let address = matches.value_of("address").unwrap_or("127.0.0.1");
-    let http_address = format!("{}:{}",address,hport);
-    let websocket_address = format!("{}:{}",address,wport);
-
-    http_server(http_address);
-    websocket_server(websocket_address, &eve_paths, &eve_flags);
+    websocket_server(websocket_address, http_address, &eve_paths, &eve_flags);
 }
@@ -12,10 +12,12 @@ use ops::{Interner, Field, Constraint, register, make_scan, make_anti_scan, Inte
 use std::io::prelude::*;
 use std::fs::{self, File};
 use std::cmp::{self};
+use std::rc::Rc;
 use self::walkdir::WalkDir;
 use parser::{embedded_blocks, block};
 use combinators::{ParseResult, ParseState, Span, EMPTY_SPAN};
 use error::{self, CompileError, report_errors};
+use watchers::{WatchSchema, WatchSchemas};
 use self::term_painter::ToStyle;
 use self::term_painter::Color::*;
 
@@ -146,6 +148,48 @@ pub fn get_function_info(op:&str) -> Option<&FunctionInfo> {
     return FUNCTION_INFO.get(op);
 }
 
+// Only constants can be checked at compile time, anything coming from a variable is left to the
+// watcher.
+fn check_watch_row(interner:&Interner, comp:&mut Compilation, name:&str, schema:&WatchSchema, fields:&Vec<Field>, spans:&Vec<Span>, span:&Span) {
+    let constant = |field:&Field| match field {
+        &Field::Value(value) => Some(interner.get_value(value).clone()),
+        _ => None,
+    };
+    let row = if schema.is_tagged() {
+        match fields.get(0).map(|field| constant(field)) {
+            Some(Some(Internable::String(kind))) => match schema.row(&kind) {
+                Some(row) => row,
+                None => {
+                    comp.error(&spans[0], error::Error::UnknownWatchKind(name.to_string(), kind, schema.kinds()));
+                    return;
+                }
+            },
+            Some(Some(other)) => {
+                comp.error(&spans[0], error::Error::UnknownWatchKind(name.to_string(), other.print(), schema.kinds()));
+                return;
+            }
+            _ => return,
+        }
+    } else {
+        match schema.rows.get(0) {
+            Some(row) => row,
+            None => return,
+        }
+    };
+    if fields.len() != row.arity() {
+        comp.error(span, error::Error::WatchArity(name.to_string(), row.describe(), fields.len()));
+        return;
+    }
+    let offset = row.arity() - row.fields.len();
+    for (ix, &(ref field_name, ref kind)) in row.fields.iter().enumerate() {
+        if let Some(value) = constant(&fields[ix + offset]) {
+            if !kind.accepts(&value) {
+                comp.error(&spans[ix + offset], error::Error::WatchType(name.to_string(), field_name.to_owned(), kind.name().to_string()));
+            }
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum OutputType {
     Bind,
@@ -206,6 +250,16 @@ pub enum SubBlock {
 }
 
 impl SubBlock {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            &SubBlock::Not(..) => "not",
+            &SubBlock::Aggregate(..) => "aggregate",
+            &SubBlock::AggregateScan(..) => "aggregate scan",
+            &SubBlock::IfBranch(..) => "if branch",
+            &SubBlock::If(..) => "if",
+        }
+    }
+
     pub fn get_mut_compilation(&mut self) -> &mut Compilation {
         match self {
             &mut SubBlock::Not(ref mut comp) => comp,
@@ -1295,12 +1349,19 @@ impl<'a> Node<'a> {
                 None
             },
             &Node::Watch(ref name, ref values) => {
+                // Unlike functions, watchers can come from plugins, so their schemas come from
+                // whichever ones the program has attached.
+                let schema = cur_block.schemas.get(*name).cloned();
                 for value in values {
                     let (local_span, unwrapped) = value.to_pos_ref(span);
                     if let &Node::ExprSet(ref items) = unwrapped {
                         let registers = items.iter()
                             .map(|v| v.compile(interner, cur_block, local_span).unwrap())
                             .collect();
+                        if let Some(ref schema) = schema {
+                            let spans = items.iter().map(|v| v.to_pos_ref(local_span).0.clone()).collect();
+                            check_watch_row(interner, cur_block, name, schema, &registers, &spans, local_span);
+                        }
                         cur_block.constraints.push(Constraint::Watch {name:name.to_string(), registers});
                     }
                 }
@@ -1582,18 +1643,27 @@ pub struct Compilation {
     required_fields: Vec<Field>,
     is_child: bool,
     id: usize,
-    errors: Vec<CompileError>
+    errors: Vec<CompileError>,
+    register_map: HashMap<Field, Field>,
+    schemas: Rc<WatchSchemas>,
 }
 
 impl Compilation {
     pub fn new(block_name:String) -> Compilation {
-        Compilation { mode: CompilationMode::Search, vars:make_det_hash_map(), var_values:make_det_hash_map(), unified_registers:make_det_hash_map(), provided_registers:make_det_hash_map(), equalities:vec![], id:0, block_name, constraints:vec![], sub_blocks:vec![], required_fields:vec![], is_child: false, errors: vec![] }
+        Compilation { mode: CompilationMode::Search, vars:make_det_hash_map(), var_values:make_det_hash_map(), unified_registers:make_det_hash_map(), provided_registers:make_det_hash_map(), equalities:vec![], id:0, block_name, constraints:vec![], sub_blocks:vec![], required_fields:vec![], is_child: false, errors: vec![], register_map:make_det_hash_map(), schemas: Rc::new(HashMap::new()) }
+    }
+
+    pub fn with_schemas(block_name:String, schemas:Rc<WatchSchemas>) -> Compilation {
+        let mut comp = Compilation::new(block_name);
+        comp.schemas = schemas;
+        comp
     }
 
     pub fn new_child(parent:&Compilation) -> Compilation {
         let mut child = Compilation::new(format!("{}|{}", parent.block_name, parent.sub_blocks.len()));
         child.id = parent.id + 10000 + (1000 * parent.sub_blocks.len());
         child.is_child = true;
+        child.schemas = parent.schemas.clone();
         child
     }
 
@@ -1700,6 +1770,52 @@ impl Compilation {
         for c in self.constraints.iter_mut() {
             c.replace_registers(&regs);
         }
+        self.register_map = regs;
+    }
+
+    // Collects the source names of the variables in this compilation and all of its sub blocks,
+    // keyed by their pre-finalize register. User-written names win over generated ones.
+    pub fn gather_variable_names(&self, names:&mut HashMap<Field, String>) {
+        for (name, ix) in self.vars.iter() {
+            let reg = match self.unified_registers.get(&register(*ix)) {
+                Some(&Field::Register(cur)) => Field::Register(cur),
+                _ => register(*ix),
+            };
+            let generated = name.starts_with("__");
+            let replace = match names.get(&reg) {
+                Some(existing) => existing.starts_with("__") && !generated,
+                None => true,
+            };
+            if replace {
+                names.insert(reg, name.to_string());
+            }
+        }
+        for sub_block in self.sub_blocks.iter() {
+            match sub_block {
+                &SubBlock::Not(ref comp) |
+                &SubBlock::Aggregate(ref comp, ..) |
+                &SubBlock::AggregateScan(ref comp) |
+                &SubBlock::IfBranch(ref comp, ..) |
+                &SubBlock::If(ref comp, ..) => comp.gather_variable_names(names),
+            }
+        }
+    }
+
+    // Maps the registers of the finalized constraints back to the variable names they came from.
+    pub fn register_names(&self, names:&HashMap<Field, String>) -> HashMap<usize, String> {
+        let mut result = HashMap::new();
+        for (old, new) in self.register_map.iter() {
+            if let (&Field::Register(ix), Some(name)) = (new, names.get(old)) {
+                let replace = match result.get(&ix) {
+                    Some(existing) => existing.starts_with("__") && !name.starts_with("__"),
+                    None => true,
+                };
+                if replace {
+                    result.insert(ix, name.to_string());
+                }
+            }
+        }
+        result
     }
 
     pub fn get_value(&mut self, name: &str) -> Field {
@@ -1723,10 +1839,10 @@ impl Compilation {
     }
 }
 
-pub fn make_block(interner:&mut Interner, name:&str, content:&str) -> Vec<Block> {
+pub fn make_block(interner:&mut Interner, name:&str, content:&str, schemas:&WatchSchemas) -> Vec<Block> {
     let mut state = ParseState::new(content);
     let parsed = block(&mut state);
-    let mut comp = Compilation::new(name.to_string());
+    let mut comp = Compilation::with_schemas(name.to_string(), Rc::new(schemas.clone()));
     // println!("Parsed {:?}", parsed);
     match parsed {
         ParseResult::Ok(mut block) => {
@@ -1751,13 +1867,17 @@ pub fn compilation_to_blocks(mut comp:Compilation, interner: &mut Interner, path
         return compilation_blocks;
     }
 
-    let block_name = &comp.block_name;
+    let block_name = comp.block_name.to_string();
+    let mut variable_names = HashMap::new();
+    comp.gather_variable_names(&mut variable_names);
+    let block_register_names = comp.register_names(&variable_names);
 
     let mut sub_ix = 0;
     let mut subs:Vec<&mut SubBlock> = comp.sub_blocks.iter_mut().collect();
     while subs.len() > 0 {
         let sub_name = format!("{}|sub_block|{}", block_name, sub_ix);
         let mut cur = subs.pop().unwrap();
+        let sub_kind = cur.kind();
         let mut sub_comp = cur.get_mut_compilation();
         if sub_comp.constraints.len() > 0 {
             sub_comp.finalize();
@@ -1770,6 +1890,8 @@ pub fn compilation_to_blocks(mut comp:Compilation, interner: &mut Interner, path
             let interned_name = interner.string_id(&sub_name);
             let mut block = Block::new(interner, &sub_name, interned_name, sub_comp.constraints.clone());
             block.path = path.to_owned();
+            block.register_names = sub_comp.register_names(&variable_names);
+            block.sub_block_kind = Some(sub_kind.to_string());
             compilation_blocks.push(block);
         }
         subs.extend(sub_comp.sub_blocks.iter_mut());
@@ -1778,11 +1900,20 @@ pub fn compilation_to_blocks(mut comp:Compilation, interner: &mut Interner, path
     let interned_name = interner.string_id(&block_name);
     let mut block = Block::new(interner, &block_name, interned_name, comp.constraints);
     block.path = path.to_owned();
+    block.register_names = block_register_names;
     compilation_blocks.push(block);
     compilation_blocks
 }
 
+// Compiles without checking watch sections, e.g. for code that's headed to some other program.
 pub fn parse_string(interner:&mut Interner, content:&str, path:&str, debug: bool) -> Vec<Block> {
+    parse_string_named(interner, content, path, debug, &HashMap::new(), &HashMap::new())
+}
+
+// Blocks are normally named by their position in the file, `names` lets the caller override the
+// name of the block at a given (1-based) position. Watch sections are checked against `schemas`.
+pub fn parse_string_named(interner:&mut Interner, content:&str, path:&str, debug: bool, names:&HashMap<usize, String>, schemas:&WatchSchemas) -> Vec<Block> {
+    let schemas = Rc::new(schemas.clone());
     let mut state = ParseState::new(content);
     let res = embedded_blocks(&mut state, path);
     if let ParseResult::Ok(mut cur) = res {
@@ -1791,23 +1922,33 @@ pub fn parse_string(interner:&mut Interner, content:&str, path:&str, debug: bool
             let mut ix = 0;
             for block in blocks {
                 ix += 1;
-                let block_name = format!("{}|block|{}", path, ix);
-                let mut comp = Compilation::new(block_name.to_string());
+                let block_name = match names.get(&ix) {
+                    Some(name) => name.to_string(),
+                    None => format!("{}|block|{}", path, ix),
+                };
+                let mut comp = Compilation::with_schemas(block_name.to_string(), schemas.clone());
                 block.gather_equalities(interner, &mut comp);
                 block.unify(&mut comp);
                 block.compile(interner, &mut comp, &EMPTY_SPAN);
 
                 comp.finalize();
+                let (span, code) = match block {
+                    &mut Node::Pos(ref span, box Node::Block { code, .. }) => (Some(span.clone()), code),
+                    &mut Node::Block { code, .. } => (None, code),
+                    _ => (None, ""),
+                };
                 if debug {
                     println!("---------------------- Block {} ---------------------------", block_name);
-                    if let &mut Node::Block { code, ..} = block {
-                        println!("{}\n\n => \n", code);
-                    }
+                    println!("{}\n\n => \n", code);
                     for c in comp.constraints.iter() {
                         println!("   {:?}", c);
                     }
                 }
-                program_blocks.extend(compilation_to_blocks(comp, interner, path, content, debug));
+                for mut compiled in compilation_to_blocks(comp, interner, path, content, debug) {
+                    compiled.span = span.clone();
+                    compiled.code = code.to_string();
+                    program_blocks.push(compiled);
+                }
             }
             program_blocks
         } else {
@@ -1818,7 +1959,7 @@ pub fn parse_string(interner:&mut Interner, content:&str, path:&str, debug: bool
     }
 }
 
-pub fn parse_file(interner:&mut Interner, path:&str, report: bool, debug: bool) -> Vec<Block> {
+pub fn parse_file(interner:&mut Interner, path:&str, report: bool, debug: bool, schemas:&WatchSchemas) -> Vec<Block> {
     let metadata = fs::metadata(path).expect(&format!("Invalid path: {:?}", path));
     let mut paths = vec![];
     if metadata.is_file() {
@@ -1844,7 +1985,7 @@ pub fn parse_file(interner:&mut Interner, path:&str, report: bool, debug: bool)
         let mut file = File::open(&cur_path).expect("Unable to open the file");
         let mut contents = String::new();
         file.read_to_string(&mut contents).expect("Unable to read the file");
-        blocks.extend(parse_string(interner, &contents, &cur_path, debug).into_iter());
+        blocks.extend(parse_string_named(interner, &contents, &cur_path, debug, &HashMap::new(), schemas).into_iter());
     }
     blocks
 }
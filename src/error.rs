@@ -42,6 +42,9 @@ pub enum Error {
     Unprovided(String),
     UnknownFunction(String),
     UnknownFunctionParam(String, String),
+    UnknownWatchKind(String, String, Vec<String>),
+    WatchArity(String, String, usize),
+    WatchType(String, String, String),
     ParseError(ParseError),
 }
 
@@ -53,6 +56,9 @@ impl fmt::Display for Error {
             &Error::Unprovided(ref var) => { write!(f, "Nothing in the block is providing `{}`. You can search for\n something that provides `{}`, or bind a constant.\n e.g. `{}: \"Hello\"`", var, var, var) }
             &Error::UnknownFunction(ref func) => { write!(f, "I don't know the `{}` function, so I'm not sure what to execute.", func) }
             &Error::UnknownFunctionParam(ref func, ref param) => { write!(f, "The `{}` function doesn't have a `{}` attribute.", func, param) }
+            &Error::UnknownWatchKind(ref watcher, ref kind, ref kinds) => { write!(f, "The `{}` watcher doesn't know what to do with `{}` rows.\n It accepts: {}", watcher, kind, kinds.join(", ")) }
+            &Error::WatchArity(ref watcher, ref shape, ref found) => { write!(f, "The `{}` watcher expects rows shaped like `{}`,\n but this one has {} values.", watcher, shape, found) }
+            &Error::WatchType(ref watcher, ref field, ref kind) => { write!(f, "The `{}` of a `{}` row has to be a {}.", field, watcher, kind) }
             &Error::ParseError(ref err) => { write!(f, "{}", err) }
         }
     }
@@ -398,11 +398,37 @@ impl RoundEntry {
 pub struct HashIndex {
     a: HashMap<Interned, HashIndexLevel, MyHasher>,
     pub size: u32,
+    journaling: bool,
+    journal: Vec<(Interned, Interned, Interned, bool)>,
 }
 
 impl HashIndex {
     pub fn new() -> HashIndex{
-        HashIndex { a: HashMap::default(), size: 0 }
+        HashIndex { a: HashMap::default(), size: 0, journaling: false, journal: vec![] }
+    }
+
+    // While journaling, every insert and remove that actually changes the index is recorded so
+    // that a transaction which gets aborted can be undone.
+    pub fn begin(&mut self) {
+        self.journal.clear();
+        self.journaling = true;
+    }
+
+    pub fn commit(&mut self) {
+        self.journal.clear();
+        self.journaling = false;
+    }
+
+    pub fn rollback(&mut self) {
+        self.journaling = false;
+        let journal:Vec<_> = self.journal.drain(..).collect();
+        for (e, a, v, added) in journal.into_iter().rev() {
+            if added {
+                self.remove(e, a, v);
+            } else {
+                self.insert(e, a, v);
+            }
+        }
     }
 
     pub fn insert(&mut self, e: Interned, a:Interned, v:Interned) -> bool {
@@ -418,7 +444,10 @@ impl HashIndex {
                 true
             },
         };
-        if added { self.size += 1 };
+        if added {
+            self.size += 1;
+            if self.journaling { self.journal.push((e, a, v, true)); }
+        };
         added
     }
 
@@ -430,7 +459,10 @@ impl HashIndex {
             }
             Entry::Vacant(_) => { false },
         };
-        if removed { self.size -= 1; };
+        if removed {
+            self.size -= 1;
+            if self.journaling { self.journal.push((e, a, v, false)); }
+        };
         removed
     }
 
@@ -509,14 +541,47 @@ impl HashIndex {
 pub struct DistinctIndex {
     pub eavs: HashMap<(Interned, Interned, Interned), RoundEntry, MyHasher>,
     empty: Vec<i32>,
+    journaling: bool,
+    journal: HashMap<(Interned, Interned, Interned), Option<RoundEntry>, MyHasher>,
 }
 
 impl DistinctIndex {
     pub fn new() -> DistinctIndex {
-        DistinctIndex { eavs: HashMap::default(), empty: vec![] }
+        DistinctIndex { eavs: HashMap::default(), empty: vec![], journaling: false, journal: HashMap::default() }
+    }
+
+    // While journaling, we keep the entry each eav had before it was first touched so that an
+    // aborted transaction can put them all back.
+    pub fn begin(&mut self) {
+        self.journal.clear();
+        self.journaling = true;
+    }
+
+    pub fn commit(&mut self) {
+        self.journal.clear();
+        self.journaling = false;
+    }
+
+    pub fn rollback(&mut self) {
+        self.journaling = false;
+        for (key, prev) in self.journal.drain() {
+            match prev {
+                Some(entry) => { self.eavs.insert(key, entry); }
+                None => { self.eavs.remove(&key); }
+            }
+        }
+    }
+
+    fn remember(&mut self, key:(Interned, Interned, Interned)) {
+        if self.journaling {
+            if let Entry::Vacant(o) = self.journal.entry(key) {
+                o.insert(self.eavs.get(&key).cloned());
+            }
+        }
     }
 
     pub fn insert_active(&mut self, e: Interned, a:Interned, v:Interned, round:Round) -> bool {
+        self.remember((e,a,v));
         match self.eavs.entry((e,a,v)) {
             Entry::Occupied(mut entry) => {
                 let info = entry.get_mut();
@@ -533,6 +598,7 @@ impl DistinctIndex {
     }
 
     pub fn remove_active(&mut self, e: Interned, a:Interned, v:Interned, round:Round) -> bool {
+        self.remember((e,a,v));
         match self.eavs.entry((e,a,v)) {
             Entry::Occupied(mut entry) => {
                 // There are two possibilities we have to worry about here. One is that we have
@@ -591,6 +657,7 @@ impl DistinctIndex {
 
     pub fn raw_insert(&mut self, e:Interned, a:Interned, v:Interned, round:Round, count:Count) -> bool {
         let key = (e, a, v);
+        self.remember(key);
         let info = self.eavs.entry(key).or_insert_with(|| RoundEntry { inserted:false, rounds: vec![], active_rounds:vec![] });
         let ref mut counts = info.rounds;
         ensure_len(counts, (round + 1) as usize);
@@ -618,6 +685,7 @@ impl DistinctIndex {
 
     pub fn distinct(&mut self, input:&Change, rounds:&mut RoundHolder) {
         let key = (input.e, input.a, input.v);
+        self.remember(key);
         let insert = |round, delta| {
             rounds.insert(input.with_round_count(round, delta));
         };
@@ -665,7 +733,7 @@ impl<'a> Iterator for DistinctIter<'a> {
 // RemoteIndex
 //-------------------------------------------------------------------------
 
-#[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd)]
+#[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd, Serialize, Deserialize)]
 pub struct RawRemoteChange {
     pub e: Internable,
     pub a: Internable,
@@ -781,6 +849,7 @@ impl AggregateEntry {
     }
 }
 
+#[derive(Clone)]
 enum IntermediateLevel {
     Value(HashMap<Vec<Interned>, RoundEntry, MyHasher>),
     KeyOnly(RoundEntry),
@@ -799,6 +868,8 @@ pub struct IntermediateIndex {
     pub rounds: HashMap<Round, HashMap<Vec<Interned>, IntermediateChange, MyHasher>, MyHasher>,
     max_round: Round,
     empty: Vec<i32>,
+    journaling: bool,
+    journal: HashMap<Vec<Interned>, Option<IntermediateLevel>, MyHasher>,
 
     debug_vec: Vec<DebugEntry>
 }
@@ -896,7 +967,38 @@ pub fn update_aggregate(interner: &mut Interner, changes: &mut Vec<AggregateChan
 impl IntermediateIndex {
 
     pub fn new() -> IntermediateIndex {
-        IntermediateIndex { index: HashMap::default(), rounds: HashMap::default(), empty: vec![], max_round:0, debug_vec: vec![] }
+        IntermediateIndex { index: HashMap::default(), rounds: HashMap::default(), empty: vec![], max_round:0, journaling: false, journal: HashMap::default(), debug_vec: vec![] }
+    }
+
+    pub fn begin(&mut self) {
+        self.journal.clear();
+        self.journaling = true;
+    }
+
+    pub fn commit(&mut self) {
+        self.journal.clear();
+        self.journaling = false;
+    }
+
+    pub fn rollback(&mut self) {
+        self.journaling = false;
+        for (key, prev) in self.journal.drain() {
+            match prev {
+                Some(level) => { self.index.insert(key, level); }
+                None => { self.index.remove(&key); }
+            }
+        }
+        self.rounds.clear();
+        self.max_round = 0;
+    }
+
+    // @FIXME: this clones the whole level the first time a key is touched in a transaction, which
+    // gets expensive for large sort aggregates.
+    fn remember(&mut self, key:&[Interned]) {
+        if self.journaling && !self.journal.contains_key(key) {
+            let prev = self.index.get(key).cloned();
+            self.journal.insert(key.to_vec(), prev);
+        }
     }
 
     pub fn check(&self, key:&Vec<Interned>, value:&Vec<Interned>) -> bool {
@@ -940,6 +1042,7 @@ impl IntermediateIndex {
     pub fn aggregate(&mut self, interner:&mut Interner, group:Vec<Interned>, mut projection:Vec<Internable>, value:Vec<Internable>, round:Round, count:Count, action:AggregateFunction, out:Vec<Interned>, kind:FunctionKind) {
         let projection_len = projection.len();
         let mut changes = vec![];
+        self.remember(&group);
         {
             let cur = self.index.entry(group).or_insert_with(|| {
                 if kind == FunctionKind::Sum || kind == FunctionKind::SortedSum {
@@ -1073,6 +1176,7 @@ impl IntermediateIndex {
     }
 
     pub fn update_active_rounds(&mut self, change: &IntermediateChange) {
+        self.remember(&change.key[..change.value_pos]);
         let (key, value) = change.key.split_at(change.value_pos);
         let count = change.count;
         let should_remove = match self.index.get_mut(key) {
@@ -1113,6 +1217,7 @@ impl IntermediateIndex {
     pub fn distinct(&mut self, full_key:Vec<Interned>, key:Vec<Interned>, value:Vec<Interned>, round:Round, count:Count, negate:bool) {
         // println!("    -> Intermediate! {:?} {:?} {:?}", full_key, round, count);
         self.max_round = cmp::max(self.max_round, round);
+        self.remember(&key);
         intermediate_distinct(&mut self.index, &mut self.rounds, full_key, key, value, round, count, negate);
     }
 
@@ -1266,10 +1371,19 @@ impl WatchIndex {
        self.next.len() > 0
     }
 
+    pub fn rollback(&mut self) {
+        self.next.clear();
+    }
+
     pub fn insert(&mut self, key: Vec<Interned>, count: Count) {
         update_watch_count(&mut self.next, key, count);
     }
 
+    // Everything currently being watched, as if it had all just been added.
+    pub fn current(&self) -> WatchDiff {
+        WatchDiff { adds: self.cur.keys().cloned().collect(), removes: vec![] }
+    }
+
     pub fn reconcile(&mut self) -> WatchDiff {
         let mut adds = vec![];
         let mut removes = vec![];
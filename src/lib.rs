@@ -22,12 +22,19 @@ extern crate rand;
 
 extern crate unicode_segmentation;
 
+#[macro_use]
+pub mod logging;
+
 pub mod ops;
 
 #[macro_use]
 pub mod combinators;
 
 pub mod paths;
+pub mod auth;
+pub mod session;
+pub mod wire;
+pub mod record;
 
 pub mod indexes;
 pub mod compiler;
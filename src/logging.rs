@@ -0,0 +1,305 @@
+extern crate time;
+extern crate term_painter;
+extern crate clap;
+
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use self::term_painter::ToStyle;
+use self::term_painter::Color::*;
+use self::clap::{Arg, ArgMatches};
+
+//-------------------------------------------------------------------------
+// Levels
+//-------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    pub fn from_name(name:&str) -> Option<Level> {
+        match &name.to_lowercase()[..] {
+            "error" => Some(Level::Error),
+            "warn" | "warning" => Some(Level::Warn),
+            "info" | "log" => Some(Level::Info),
+            "debug" => Some(Level::Debug),
+            "trace" => Some(Level::Trace),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        }
+    }
+
+    fn from_index(ix:usize) -> Level {
+        match ix {
+            0 => Level::Error,
+            1 => Level::Warn,
+            2 => Level::Info,
+            3 => Level::Debug,
+            _ => Level::Trace,
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+//-------------------------------------------------------------------------
+// Config
+//-------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+impl LogFormat {
+    pub fn from_name(name:&str) -> Option<LogFormat> {
+        match name {
+            "text" => Some(LogFormat::Text),
+            "json" => Some(LogFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LogSink {
+    Stderr,
+    // Once the file grows past `max_bytes` it's moved to `path.1`, the old `path.1` to `path.2`
+    // and so on, keeping at most `keep` old files around.
+    File { path: String, max_bytes: u64, keep: usize },
+}
+
+#[derive(Debug, Clone)]
+pub struct LogConfig {
+    pub level: Level,
+    pub format: LogFormat,
+    pub sink: LogSink,
+}
+
+impl LogConfig {
+    pub fn new() -> LogConfig {
+        LogConfig { level: Level::Info, format: LogFormat::Text, sink: LogSink::Stderr }
+    }
+
+    // Shared by the binaries so their `--log-*` flags mean the same thing.
+    pub fn from_flags(level:Option<&str>, format:Option<&str>, file:Option<&str>, max_bytes:Option<&str>, keep:Option<&str>) -> Result<LogConfig, String> {
+        let mut config = LogConfig::new();
+        if let Some(level) = level {
+            config.level = Level::from_name(level).ok_or_else(|| format!("Unknown log level '{}'", level))?;
+        }
+        if let Some(format) = format {
+            config.format = LogFormat::from_name(format).ok_or_else(|| format!("Unknown log format '{}'", format))?;
+        }
+        if let Some(path) = file {
+            let max_bytes = max_bytes.unwrap_or("10000000").parse().map_err(|_| "--log-max-size must be a positive integer".to_string())?;
+            let keep = keep.unwrap_or("5").parse().map_err(|_| "--log-keep must be a positive integer".to_string())?;
+            config.sink = LogSink::File { path: path.to_owned(), max_bytes, keep };
+        }
+        Ok(config)
+    }
+
+    pub fn from_matches(matches:&ArgMatches) -> Result<LogConfig, String> {
+        LogConfig::from_flags(matches.value_of("log-level"), matches.value_of("log-format"), matches.value_of("log-file"),
+                              matches.value_of("log-max-size"), matches.value_of("log-keep"))
+    }
+}
+
+// The `--log-*` flags read by `LogConfig::from_matches`.
+pub fn log_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("log-level")
+            .long("log-level")
+            .value_name("LEVEL")
+            .help("Only logs messages at or above LEVEL. Options: ('error', 'warn', 'info', 'debug', 'trace') (info)")
+            .takes_value(true),
+        Arg::with_name("log-format")
+            .long("log-format")
+            .value_name("FORMAT")
+            .help("How log messages are written. Options: ('text', 'json') (text)")
+            .takes_value(true),
+        Arg::with_name("log-file")
+            .long("log-file")
+            .value_name("FILE")
+            .help("Writes the log to FILE instead of stderr, rotating it as it grows")
+            .takes_value(true),
+        Arg::with_name("log-max-size")
+            .long("log-max-size")
+            .value_name("BYTES")
+            .help("Rotates the log file once it grows past this many bytes (10000000)")
+            .takes_value(true),
+        Arg::with_name("log-keep")
+            .long("log-keep")
+            .value_name("FILES")
+            .help("How many rotated log files to keep around (5)")
+            .takes_value(true),
+    ]
+}
+
+//-------------------------------------------------------------------------
+// Logger
+//-------------------------------------------------------------------------
+
+pub struct LogRecord<'a> {
+    pub level: Level,
+    pub target: &'a str,
+    pub message: &'a str,
+    pub fields: &'a [(String, String)],
+}
+
+impl<'a> LogRecord<'a> {
+    pub fn to_text(&self, time:&str, color:bool) -> String {
+        let level = format!("{:5}", self.level.name().to_uppercase());
+        let level = if !color { level } else {
+            match self.level {
+                Level::Error => BrightRed.paint(level).to_string(),
+                Level::Warn => BrightYellow.paint(level).to_string(),
+                Level::Info => BrightCyan.paint(level).to_string(),
+                _ => level,
+            }
+        };
+        let mut text = format!("{} {} [{}] {}", time, level, self.target, self.message);
+        for &(ref name, ref value) in self.fields.iter() {
+            text.push_str(&format!(" {}={:?}", name, value));
+        }
+        text
+    }
+
+    pub fn to_json(&self, time:&str) -> String {
+        let mut fields = ::serde_json::Map::new();
+        for &(ref name, ref value) in self.fields.iter() {
+            fields.insert(name.to_owned(), ::serde_json::Value::String(value.to_owned()));
+        }
+        json!({"time": time, "level": self.level.name(), "target": self.target, "message": self.message, "fields": fields}).to_string()
+    }
+}
+
+pub struct Logger {
+    config: LogConfig,
+    file: Option<File>,
+    written: u64,
+}
+
+impl Logger {
+    pub fn new(config:LogConfig) -> Logger {
+        Logger { config, file: None, written: 0 }
+    }
+
+    pub fn level(&self) -> Level {
+        self.config.level
+    }
+
+    pub fn write(&mut self, record:&LogRecord) -> io::Result<()> {
+        if record.level > self.config.level { return Ok(()); }
+        let time = format!("{}", time::now_utc().strftime("%Y-%m-%dT%H:%M:%S.%fZ").unwrap());
+        match self.config.sink.clone() {
+            LogSink::Stderr => {
+                let line = match self.config.format {
+                    LogFormat::Text => record.to_text(&time, true),
+                    LogFormat::Json => record.to_json(&time),
+                };
+                writeln!(io::stderr(), "{}", line)
+            }
+            LogSink::File { path, max_bytes, keep } => {
+                let mut line = match self.config.format {
+                    LogFormat::Text => record.to_text(&time, false),
+                    LogFormat::Json => record.to_json(&time),
+                };
+                line.push('\n');
+                if self.file.is_some() && self.written + line.len() as u64 > max_bytes {
+                    self.file = None;
+                    rotate(&path, keep)?;
+                }
+                if self.file.is_none() {
+                    let file = OpenOptions::new().append(true).create(true).open(&path)?;
+                    self.written = file.metadata()?.len();
+                    self.file = Some(file);
+                }
+                self.written += line.len() as u64;
+                self.file.as_mut().unwrap().write_all(line.as_bytes())
+            }
+        }
+    }
+}
+
+fn rotate(path:&str, keep:usize) -> io::Result<()> {
+    if keep == 0 {
+        return fs::remove_file(path);
+    }
+    fs::remove_file(format!("{}.{}", path, keep)).ok();
+    for ix in (1..keep).rev() {
+        fs::rename(format!("{}.{}", path, ix), format!("{}.{}", path, ix + 1)).ok();
+    }
+    fs::rename(path, format!("{}.1", path))
+}
+
+//-------------------------------------------------------------------------
+// Global logger
+//-------------------------------------------------------------------------
+
+lazy_static! {
+    static ref LOGGER: Mutex<Logger> = Mutex::new(Logger::new(LogConfig::new()));
+    // Kept outside of the lock so that checking whether something would be logged is cheap.
+    static ref LEVEL: AtomicUsize = AtomicUsize::new(Level::Info as usize);
+}
+
+pub fn configure(config:LogConfig) {
+    LEVEL.store(config.level as usize, Ordering::Relaxed);
+    *LOGGER.lock().unwrap() = Logger::new(config);
+}
+
+pub fn level() -> Level {
+    Level::from_index(LEVEL.load(Ordering::Relaxed))
+}
+
+pub fn enabled(level:Level) -> bool {
+    level as usize <= LEVEL.load(Ordering::Relaxed)
+}
+
+pub fn log(level:Level, target:&str, message:&str, fields:&[(String, String)]) {
+    if !enabled(level) { return; }
+    let record = LogRecord { level, target, message, fields };
+    // If we can't log there's nowhere left to complain to, so the best we can do is stderr.
+    if let Err(why) = LOGGER.lock().unwrap().write(&record) {
+        writeln!(io::stderr(), "Unable to write log: {}", why).ok();
+    }
+}
+
+#[macro_export]
+macro_rules! log_at (($level:expr, $target:expr, $($arg:tt)*) => ({
+    if $crate::logging::enabled($level) {
+        $crate::logging::log($level, $target, &format!($($arg)*), &[]);
+    }
+}));
+
+#[macro_export]
+macro_rules! log_error (($target:expr, $($arg:tt)*) => ( log_at!($crate::logging::Level::Error, $target, $($arg)*) ));
+#[macro_export]
+macro_rules! log_warn (($target:expr, $($arg:tt)*) => ( log_at!($crate::logging::Level::Warn, $target, $($arg)*) ));
+#[macro_export]
+macro_rules! log_info (($target:expr, $($arg:tt)*) => ( log_at!($crate::logging::Level::Info, $target, $($arg)*) ));
+#[macro_export]
+macro_rules! log_debug (($target:expr, $($arg:tt)*) => ( log_at!($crate::logging::Level::Debug, $target, $($arg)*) ));
+#[macro_export]
+macro_rules! log_trace (($target:expr, $($arg:tt)*) => ( log_at!($crate::logging::Level::Trace, $target, $($arg)*) ));
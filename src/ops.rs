@@ -11,34 +11,38 @@ extern crate natord;
 use unicode_segmentation::UnicodeSegmentation;
 
 use indexes::{HashIndex, DistinctIter, DistinctIndex, WatchIndex, IntermediateIndex, MyHasher, AggregateEntry,
-              CollapsedChanges, RemoteIndex, RemoteChange, RawRemoteChange};
+              CollapsedChanges, RemoteIndex, RemoteChange, RawRemoteChange, WatchDiff};
 use solver::Solver;
-use compiler::{make_block, parse_file, FunctionKind, Node};
-use std::collections::{HashMap, HashSet, Bound, BTreeMap};
+use compiler::{make_block, parse_file, parse_string_named, FunctionKind, Node};
+use std::collections::{HashMap, HashSet, Bound, BTreeMap, VecDeque};
 use std::mem::transmute;
 use std::cmp::{self, Eq, PartialOrd};
 use std::collections::hash_map::{DefaultHasher, Entry};
 use std::hash::{Hash, Hasher};
 use std::iter::{Iterator, FromIterator};
 use std::fmt;
-use watchers::{Watcher};
+use watchers::{Watcher, WatchSchemas};
 use std::sync::mpsc::{Sender, Receiver, SendError};
 use std::sync::mpsc;
 use serde::ser::{Serialize, Serializer};
 use serde::de::{Deserialize, Deserializer, Visitor};
 use std::error::Error;
 use std::thread::{self, JoinHandle};
-use std::io::{Write, BufReader, BufWriter};
+use std::io::{Read, Write, BufReader, BufWriter};
 use std::fs::{OpenOptions, File, canonicalize};
 use std::path::{Path, PathBuf};
 use std::f32::consts::{PI};
 use std::mem;
 use std::usize;
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
 use rand::{Rng, SeedableRng, XorShiftRng};
 use self::term_painter::ToStyle;
 use self::term_painter::Color::*;
 use parser;
-use combinators::{ParseState, ParseResult};
+use combinators::{ParseState, ParseResult, Span};
+use record::{Recorder, RecordedMessage, RecordedBlock};
+use logging::{self, Level};
 
 
 //-------------------------------------------------------------------------
@@ -76,6 +80,20 @@ pub fn print_block_constraints(block:&Block) {
     println!("");
 }
 
+// Code changes are listed at debug, and the constraints of added blocks at trace.
+pub fn log_code_changes(program:&str, adds:&Vec<Block>, removes:&Vec<String>) {
+    for block in adds.iter() {
+        log_debug!(program, "Adding block {}", block.name);
+        if logging::enabled(Level::Trace) {
+            let constraints:Vec<String> = block.constraints.iter().map(|constraint| format!("  {:?}", constraint)).collect();
+            log_trace!(program, "Constraints for {}:\n{}", block.name, constraints.join("\n"));
+        }
+    }
+    for block in removes.iter() {
+        log_debug!(program, "Removing block {}", block);
+    }
+}
+
 pub fn s(string: &str) -> Internable {
     Internable::String(string.to_string())
 }
@@ -190,13 +208,17 @@ pub struct Block {
     pub path: String,
     pub constraints: Vec<Constraint>,
     pub solver: Option<Solver>,
-    pub shapes: Vec<Vec<PipeShape>>
+    pub shapes: Vec<Vec<PipeShape>>,
+    pub span: Option<Span>,
+    pub code: String,
+    pub register_names: HashMap<usize, String>,
+    pub sub_block_kind: Option<String>,
 }
 
 impl Block {
 
     pub fn new(interner:&mut Interner, name:&str, block_id:Interned, constraints:Vec<Constraint>) -> Block {
-        let mut me = Block { name:name.to_string(), block_id, path: "".to_owned(), constraints, solver:None, shapes: vec![] };
+        let mut me = Block { name:name.to_string(), block_id, path: "".to_owned(), constraints, solver:None, shapes: vec![], span: None, code: "".to_owned(), register_names: HashMap::new(), sub_block_kind: None };
         let shapes = me.to_shapes();
         me.shapes.extend(shapes);
         me.solver = Some(Solver::new(interner, block_id, 0, None, &me.constraints));
@@ -304,6 +326,51 @@ impl Block {
         shapes
     }
 
+    pub fn explain_shape(shape:&PipeShape, interner:&Interner) -> String {
+        let names = HashMap::new();
+        match shape {
+            &PipeShape::Scan(e, a, v) => {
+                format!("Scan ( {}, {}, {} )", explain_field(interner, &names, &Field::Value(e)),
+                        explain_field(interner, &names, &Field::Value(a)), explain_field(interner, &names, &Field::Value(v)))
+            }
+            &PipeShape::Intermediate(id) => format!("Intermediate ( {} )", explain_field(interner, &names, &Field::Value(id))),
+            &PipeShape::Remote(id) => format!("Remote ( {} )", explain_field(interner, &names, &Field::Value(id))),
+        }
+    }
+
+    pub fn explain(&self, interner:&Interner, pipes:&Vec<&Solver>) -> String {
+        let mut out = String::new();
+        match self.sub_block_kind {
+            Some(ref kind) => out.push_str(&format!("{} ({} sub block)\n", self.name, kind)),
+            None => out.push_str(&format!("{}\n", self.name)),
+        }
+        if self.path != "" {
+            out.push_str(&format!("  path: {}\n", self.path));
+        }
+        if let Some(ref span) = self.span {
+            out.push_str(&format!("  source: lines {}-{}\n", span.start.line + 1, span.stop.line + 1));
+        }
+        if self.sub_block_kind.is_none() && self.code != "" {
+            for line in self.code.trim_right().lines() {
+                out.push_str(&format!("    | {}\n", line));
+            }
+        }
+        out.push_str("  constraints:\n");
+        for (ix, constraint) in self.constraints.iter().enumerate() {
+            out.push_str(&format!("    [{}] {}\n", ix, constraint.explain(interner, &self.register_names)));
+        }
+        out.push_str("  pipes:\n");
+        for pipe in pipes.iter() {
+            let triggers:Vec<String> = match self.shapes.get(pipe.id - 1) {
+                Some(shapes) => shapes.iter().map(|shape| Block::explain_shape(shape, interner)).collect(),
+                None => vec![],
+            };
+            out.push_str(&format!("    pipe {} triggered by: {}\n", pipe.id, triggers.join(" | ")));
+            out.push_str(&pipe.explain(interner, &self.register_names, &self.constraints));
+        }
+        out
+    }
+
 }
 
 impl PartialEq for Block {
@@ -410,6 +477,14 @@ impl EstimateIterPool {
     pub fn get(&mut self, iter_ix:usize) -> &mut EstimateIter {
         &mut self.iters[iter_ix]
     }
+
+    // A solve that blows up part way through leaves its iterators mid-flight, and the next solve
+    // to pick them up would start from the stale estimates.
+    pub fn reset_all(&mut self) {
+        for iter in self.iters.iter_mut() {
+            iter.reset();
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -568,6 +643,7 @@ pub struct Frame {
     pub remote: Option<RemoteChange>,
     pub row: Row,
     pub block_ix: usize,
+    pub block: Interned,
     pub results: Vec<Interned>,
     #[allow(dead_code)]
     pub counters: Counters,
@@ -575,7 +651,7 @@ pub struct Frame {
 
 impl Frame {
     pub fn new() -> Frame {
-        Frame {row: Row::new(64), block_ix:0, input: None, intermediate: None, remote: None, results: vec![], counters: Counters {iter_next: 0, accept: 0, accept_bail: 0, inserts: 0, instructions: 0, accept_ns: 0, total_ns: 0, considered: 0}}
+        Frame {row: Row::new(64), block_ix:0, block:0, input: None, intermediate: None, remote: None, results: vec![], counters: Counters {iter_next: 0, accept: 0, accept_bail: 0, inserts: 0, instructions: 0, accept_ns: 0, total_ns: 0, considered: 0}}
     }
 
     pub fn get_register(&self, register:usize) -> Interned {
@@ -597,6 +673,136 @@ impl Frame {
     }
 }
 
+//-------------------------------------------------------------------------
+// Profiler
+//-------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Default)]
+pub struct PipeProfile {
+    pub runs: u64,
+    pub instructions: u64,
+    pub inserts: u64,
+    pub total_ns: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ConstraintProfile {
+    pub proposals: u64,
+    pub proposed_estimate: u64,
+    pub accepts: u64,
+    pub rejects: u64,
+    // Time spent checking rows against the constraint. Proposing isn't timed, it's done once per
+    // solve and is cheap next to the accepts.
+    pub accept_ns: u64,
+}
+
+// A profile attributes the work done in a transaction to the blocks and constraints that did
+// it. Pipes are keyed by (block, pipe id) and constraints by (block, constraint index), where
+// the constraint index lines up with `Block::constraints`.
+pub struct Profile {
+    pub pipes: HashMap<(Interned, usize), PipeProfile>,
+    pub constraints: HashMap<(Interned, usize), ConstraintProfile>,
+}
+
+impl Profile {
+    pub fn new() -> Profile {
+        Profile { pipes: HashMap::new(), constraints: HashMap::new() }
+    }
+
+    pub fn clear(&mut self) {
+        self.pipes.clear();
+        self.constraints.clear();
+    }
+
+    pub fn pipe_run(&mut self, block:Interned, pipe:usize, instructions:u64, inserts:u64, total_ns:u64) {
+        let entry = self.pipes.entry((block, pipe)).or_insert_with(|| PipeProfile::default());
+        entry.runs += 1;
+        entry.instructions += instructions;
+        entry.inserts += inserts;
+        entry.total_ns += total_ns;
+    }
+
+    pub fn propose(&mut self, block:Interned, constraint:usize, estimate:usize) {
+        let entry = self.constraints.entry((block, constraint)).or_insert_with(|| ConstraintProfile::default());
+        entry.proposals += 1;
+        entry.proposed_estimate += estimate as u64;
+    }
+
+    pub fn accept(&mut self, block:Interned, constraint:usize, accepted:bool, ns:u64) {
+        let entry = self.constraints.entry((block, constraint)).or_insert_with(|| ConstraintProfile::default());
+        if accepted { entry.accepts += 1; } else { entry.rejects += 1; }
+        entry.accept_ns += ns;
+    }
+
+    fn block_totals(&self) -> Vec<(Interned, PipeProfile)> {
+        let mut totals:HashMap<Interned, PipeProfile> = HashMap::new();
+        for (&(block, _), pipe) in self.pipes.iter() {
+            let total = totals.entry(block).or_insert_with(|| PipeProfile::default());
+            total.runs += pipe.runs;
+            total.instructions += pipe.instructions;
+            total.inserts += pipe.inserts;
+            total.total_ns += pipe.total_ns;
+        }
+        let mut sorted:Vec<(Interned, PipeProfile)> = totals.into_iter().collect();
+        sorted.sort_by(|a, b| b.1.total_ns.cmp(&a.1.total_ns).then(b.1.instructions.cmp(&a.1.instructions)));
+        sorted
+    }
+
+    fn sorted_pipes(&self, block:Interned) -> Vec<(usize, &PipeProfile)> {
+        let mut pipes:Vec<(usize, &PipeProfile)> = self.pipes.iter().filter(|&(&(b, _), _)| b == block).map(|(&(_, id), pipe)| (id, pipe)).collect();
+        pipes.sort_by(|a, b| b.1.total_ns.cmp(&a.1.total_ns));
+        pipes
+    }
+
+    fn sorted_constraints(&self, block:Interned) -> Vec<(usize, &ConstraintProfile)> {
+        let mut constraints:Vec<(usize, &ConstraintProfile)> = self.constraints.iter().filter(|&(&(b, _), _)| b == block).map(|(&(_, ix), c)| (ix, c)).collect();
+        constraints.sort_by(|a, b| (b.1.accepts + b.1.rejects + b.1.proposals).cmp(&(a.1.accepts + a.1.rejects + a.1.proposals)));
+        constraints
+    }
+
+    fn describe_constraint(block_info:&BlockInfo, block:Interned, ix:usize) -> String {
+        match block_info.blocks.iter().find(|b| b.block_id == block).and_then(|b| b.constraints.get(ix)) {
+            Some(constraint) => format!("{:?}", constraint),
+            None => format!("<constraint {}>", ix),
+        }
+    }
+
+    pub fn report(&self, block_info:&BlockInfo, interner:&Interner) -> String {
+        let mut out = String::new();
+        for (block, total) in self.block_totals() {
+            out.push_str(&format!("{} {:.3}ms - {} runs - {} insts - {} inserts\n",
+                                  interner.get_value(block).print(), total.total_ns as f64 / 1_000_000.0,
+                                  total.runs, total.instructions, total.inserts));
+            for (id, pipe) in self.sorted_pipes(block) {
+                out.push_str(&format!("    pipe {}: {:.3}ms - {} runs - {} insts - {} inserts\n",
+                                      id, pipe.total_ns as f64 / 1_000_000.0, pipe.runs, pipe.instructions, pipe.inserts));
+            }
+            for (ix, constraint) in self.sorted_constraints(block) {
+                out.push_str(&format!("    [{}] {} proposals (~{} rows) - {} accepts - {} rejects - {:.3}ms   {}\n",
+                                      ix, constraint.proposals, constraint.proposed_estimate, constraint.accepts,
+                                      constraint.rejects, constraint.accept_ns as f64 / 1_000_000.0,
+                                      Profile::describe_constraint(block_info, block, ix)));
+            }
+        }
+        out
+    }
+
+    pub fn to_json(&self, block_info:&BlockInfo, interner:&Interner) -> serde_json::Value {
+        let blocks:Vec<serde_json::Value> = self.block_totals().into_iter().map(|(block, total)| {
+            let pipes:Vec<serde_json::Value> = self.sorted_pipes(block).into_iter().map(|(id, pipe)| {
+                json!({"pipe": id, "runs": pipe.runs, "instructions": pipe.instructions, "inserts": pipe.inserts, "ns": pipe.total_ns})
+            }).collect();
+            let constraints:Vec<serde_json::Value> = self.sorted_constraints(block).into_iter().map(|(ix, c)| {
+                json!({"constraint": ix, "description": Profile::describe_constraint(block_info, block, ix),
+                       "proposals": c.proposals, "estimate": c.proposed_estimate, "accepts": c.accepts, "rejects": c.rejects, "ns": c.accept_ns})
+            }).collect();
+            json!({"block": interner.get_value(block).print(), "runs": total.runs, "instructions": total.instructions,
+                   "inserts": total.inserts, "ns": total.total_ns, "pipes": pipes, "constraints": constraints})
+        }).collect();
+        json!({"blocks": blocks})
+    }
+}
+
 //-------------------------------------------------------------------------
 // Field
 //-------------------------------------------------------------------------
@@ -644,6 +850,25 @@ pub fn format_field(interner:&Interner, field:&Field) -> String{
     }
 }
 
+pub fn explain_field(interner:&Interner, names:&HashMap<usize, String>, field:&Field) -> String {
+    match field {
+        &Field::Register(reg) => match names.get(&reg) {
+            Some(name) => name.to_string(),
+            None => format!("?{}", reg),
+        },
+        &Field::Value(0) => "_".to_string(),
+        &Field::Value(interned) => match interner.get_value(interned) {
+            &Internable::String(ref string) => format!("{:?}", string),
+            other => other.print(),
+        }
+    }
+}
+
+pub fn explain_fields(interner:&Interner, names:&HashMap<usize, String>, fields:&Vec<Field>) -> String {
+    let explained:Vec<String> = fields.iter().map(|field| explain_field(interner, names, field)).collect();
+    format!("[{}]", explained.join(", "))
+}
+
 //-------------------------------------------------------------------------
 // Interner
 //-------------------------------------------------------------------------
@@ -967,6 +1192,14 @@ fn replace_registers(fields:&mut Vec<&mut Field>, lookup:&HashMap<Field,Field>)
     }
 }
 
+fn map_field_values<F>(fields:&mut Vec<&mut Field>, f:&mut F) where F: FnMut(Interned) -> Interned {
+    for field in fields.iter_mut() {
+        if let Field::Value(value) = **field {
+            **field = Field::Value(f(value));
+        }
+    }
+}
+
 impl Constraint {
     pub fn get_registers(&self) -> Vec<Field> {
         match self {
@@ -1123,6 +1356,49 @@ impl Constraint {
             &mut Constraint::Watch {ref mut registers, ..} => { replace_registers(&mut registers.iter_mut().collect(), lookup); },
         }
     }
+
+    pub fn map_values<F>(&mut self, f:&mut F) where F: FnMut(Interned) -> Interned {
+        match self {
+            &mut Constraint::Scan { ref mut e, ref mut a, ref mut v, ..} |
+            &mut Constraint::LookupCommit { ref mut e, ref mut a, ref mut v, ..} |
+            &mut Constraint::Insert { ref mut e, ref mut a, ref mut v, ..} |
+            &mut Constraint::Remove { ref mut e, ref mut a, ref mut v } => { map_field_values(&mut vec![e,a,v], f); }
+            &mut Constraint::LookupRemote { ref mut e, ref mut a, ref mut v, ref mut _type, ref mut _for, ref mut to, ref mut from, ..} => {
+                map_field_values(&mut vec![e,a,v,_for,_type,from,to], f);
+            }
+            &mut Constraint::AntiScan { ref mut key, ..} => { map_field_values(&mut key.iter_mut().collect(), f); }
+            &mut Constraint::IntermediateScan { ref mut full_key, ref mut key, ref mut value, ..} => {
+                map_field_values(&mut full_key.iter_mut().collect(), f);
+                map_field_values(&mut key.iter_mut().collect(), f);
+                map_field_values(&mut value.iter_mut().collect(), f);
+            }
+            &mut Constraint::Function {ref mut output, ref mut params, ..} => {
+                map_field_values(&mut vec![output], f);
+                map_field_values(&mut params.iter_mut().collect(), f);
+            }
+            &mut Constraint::MultiFunction {ref mut outputs, ref mut params, ..} => {
+                map_field_values(&mut outputs.iter_mut().collect(), f);
+                map_field_values(&mut params.iter_mut().collect(), f);
+            }
+            &mut Constraint::Aggregate {ref mut output, ref mut params, ref mut group, ref mut projection, ref mut output_key, ..} => {
+                map_field_values(&mut output.iter_mut().collect(), f);
+                map_field_values(&mut output_key.iter_mut().collect(), f);
+                map_field_values(&mut params.iter_mut().collect(), f);
+                map_field_values(&mut group.iter_mut().collect(), f);
+                map_field_values(&mut projection.iter_mut().collect(), f);
+            }
+            &mut Constraint::Filter {ref mut left, ref mut right, ..} => { map_field_values(&mut vec![left, right], f); }
+            &mut Constraint::InsertIntermediate { ref mut key, ref mut value, .. } => {
+                map_field_values(&mut key.iter_mut().collect(), f);
+                map_field_values(&mut value.iter_mut().collect(), f);
+            }
+            &mut Constraint::RemoveAttribute { ref mut e, ref mut a } => { map_field_values(&mut vec![e,a], f); },
+            &mut Constraint::RemoveEntity { ref mut e } => { map_field_values(&mut vec![e], f); },
+            &mut Constraint::DynamicCommit { ref mut e, ref mut a, ref mut v, ref mut _type } => { map_field_values(&mut vec![e,a,v,_type], f); },
+            &mut Constraint::Project { .. } => {},
+            &mut Constraint::Watch {ref mut registers, ..} => { map_field_values(&mut registers.iter_mut().collect(), f); },
+        }
+    }
 }
 
 impl Clone for Constraint {
@@ -1239,6 +1515,38 @@ impl fmt::Debug for Constraint {
     }
 }
 
+impl Constraint {
+    pub fn explain(&self, interner:&Interner, names:&HashMap<usize, String>) -> String {
+        let f = |field:&Field| explain_field(interner, names, field);
+        let fs = |fields:&Vec<Field>| explain_fields(interner, names, fields);
+        match self {
+            &Constraint::Scan { ref e, ref a, ref v, .. } => format!("Scan ( {}, {}, {} )", f(e), f(a), f(v)),
+            &Constraint::LookupCommit { ref e, ref a, ref v, .. } => format!("LookupCommit ( {}, {}, {} )", f(e), f(a), f(v)),
+            &Constraint::LookupRemote { ref e, ref a, ref v, ref _for, ref _type, ref from, ref to, .. } => {
+                format!("LookupRemote ( {}, {}, {}, for: {}, type: {}, from: {}, to: {} )", f(e), f(a), f(v), f(_for), f(_type), f(from), f(to))
+            }
+            &Constraint::AntiScan { ref key, .. } => format!("AntiScan {}", fs(key)),
+            &Constraint::IntermediateScan { ref key, ref value, .. } => format!("IntermediateScan ( {} -> {} )", fs(key), fs(value)),
+            &Constraint::Insert { ref e, ref a, ref v, commit } => format!("{} ( {}, {}, {} )", if commit { "Commit" } else { "Bind" }, f(e), f(a), f(v)),
+            &Constraint::InsertIntermediate { ref key, ref value, negate } => format!("InsertIntermediate ( {} -> {}{} )", fs(key), fs(value), if negate { ", negated" } else { "" }),
+            &Constraint::Remove { ref e, ref a, ref v } => format!("Remove ( {}, {}, {} )", f(e), f(a), f(v)),
+            &Constraint::RemoveAttribute { ref e, ref a } => format!("RemoveAttribute ( {}, {} )", f(e), f(a)),
+            &Constraint::RemoveEntity { ref e } => format!("RemoveEntity ( {} )", f(e)),
+            &Constraint::DynamicCommit { ref e, ref a, ref v, ref _type } => format!("DynamicCommit ( {}, {}, {}, type: {} )", f(e), f(a), f(v), f(_type)),
+            &Constraint::Function { ref op, ref params, ref output, .. } => format!("{} = {}{}", f(output), op, fs(params)),
+            &Constraint::MultiFunction { ref op, ref params, ref outputs, .. } => format!("{} = {}{}", fs(outputs), op, fs(params)),
+            &Constraint::Aggregate { ref op, ref group, ref projection, ref params, ref output_key, .. } => {
+                format!("{} = {}(per: {}, for: {}, {})", fs(output_key), op, fs(group), fs(projection), fs(params))
+            }
+            &Constraint::Filter { ref op, ref left, ref right, .. } => format!("Filter ( {} {} {} )", f(left), op, f(right)),
+            &Constraint::Project { ref registers } => {
+                let fields = registers.iter().map(|reg| Field::Register(*reg)).collect();
+                format!("Project {}", fs(&fields))
+            }
+            &Constraint::Watch { ref name, ref registers } => format!("Watch {}{}", name, fs(registers)),
+        }
+    }
+}
 
 pub fn make_register_mask(fields: Vec<&Field>) -> u64 {
     let mut mask = 0;
@@ -2331,6 +2639,10 @@ pub struct RoundHolder {
     commits: HashMap<(Interned, Interned, Interned, Interned), (ChangeType, Change)>,
     staged_commit_keys: Vec<(Interned, Interned, Interned, Interned)>,
     collapsed_commits: CollapsedChanges,
+    // The blocks that committed in the frame being built and in the last prepared frame, so a
+    // runaway transaction can name who keeps it going.
+    committers: HashSet<Interned>,
+    prev_committers: HashSet<Interned>,
     pub max_round: usize,
 }
 
@@ -2341,13 +2653,17 @@ impl RoundHolder {
         for _ in 0..100 {
             rounds.push(HashMap::new());
         }
-        RoundHolder { rounds, commits:HashMap::new(), staged_commit_keys:vec![], collapsed_commits:CollapsedChanges::new(), max_round: 0 }
+        RoundHolder { rounds, commits:HashMap::new(), staged_commit_keys:vec![], collapsed_commits:CollapsedChanges::new(), committers:HashSet::new(), prev_committers:HashSet::new(), max_round: 0 }
     }
 
     pub fn insert(&mut self, change:Change) {
         let key = (change.e, change.a, change.v);
         let round = change.round as usize;
         self.max_round = cmp::max(round, self.max_round);
+        // The iterator peeks one round past the max, so keep a spare round around.
+        while self.rounds.len() < round + 2 {
+            self.rounds.push(HashMap::new());
+        }
         match self.rounds[round].entry(key) {
             Entry::Occupied(mut o) => {
                 o.get_mut().count += change.count;
@@ -2358,7 +2674,8 @@ impl RoundHolder {
         };
     }
 
-    pub fn commit(&mut self, change:Change, change_type:ChangeType) {
+    pub fn commit(&mut self, block:Interned, change:Change, change_type:ChangeType) {
+        self.committers.insert(block);
         let key = (change.n, change.e, change.a, change.v);
         if change.a == 0 || change.v == 0 {
             self.staged_commit_keys.push(key);
@@ -2424,6 +2741,8 @@ impl RoundHolder {
             }
         }
         self.staged_commit_keys.clear();
+        mem::swap(&mut self.committers, &mut self.prev_committers);
+        self.committers.clear();
         for info in self.commits.values() {
             match info {
                 &(ChangeType::Insert, Change {count, ..}) => {
@@ -2454,6 +2773,29 @@ impl RoundHolder {
         self.max_round = 0;
     }
 
+    pub fn pending(&self, round:usize) -> Vec<Change> {
+        match self.rounds.get(round) {
+            Some(changes) => changes.values().filter(|change| change.count != 0).cloned().collect(),
+            None => vec![],
+        }
+    }
+
+    pub fn committers(&self) -> Vec<Interned> {
+        self.prev_committers.iter().cloned().collect()
+    }
+
+    pub fn abort(&mut self) {
+        for round in self.rounds.iter_mut() {
+            round.clear();
+        }
+        self.commits.clear();
+        self.committers.clear();
+        self.prev_committers.clear();
+        self.staged_commit_keys.clear();
+        self.collapsed_commits.clear();
+        self.max_round = 0;
+    }
+
     pub fn iter(&self) -> RoundHolderIter {
         RoundHolderIter::new()
     }
@@ -2515,6 +2857,112 @@ impl RoundHolderIter {
 
 }
 
+//-------------------------------------------------------------------------
+// Hot reload
+//-------------------------------------------------------------------------
+
+// Blocks are named by their position in a file, so a name can't tell us whether a block changed
+// when the file is reloaded. Instead we compare the constraints of a block and its sub-blocks,
+// with any ids derived from the block's name normalized away.
+#[derive(PartialEq)]
+pub struct BlockIdentity {
+    parts: Vec<(String, HashSet<Constraint>)>,
+}
+
+impl BlockIdentity {
+    pub fn new(interner:&mut Interner, root:&str, blocks:&Vec<&Block>) -> BlockIdentity {
+        let prefix = format!("{}|", root);
+        let mut normalized:HashMap<Interned, Interned> = HashMap::new();
+        let mut parts = vec![];
+        for block in blocks.iter() {
+            let mut constraints = HashSet::new();
+            for constraint in block.constraints.iter() {
+                let mut constraint = constraint.clone();
+                constraint.map_values(&mut |value| {
+                    if let Some(&id) = normalized.get(&value) { return id; }
+                    let replacement = match interner.get_value(value) {
+                        &Internable::String(ref string) if string.starts_with(&prefix) => Some(format!("<block>|{}", &string[prefix.len()..])),
+                        _ => None,
+                    };
+                    let id = match replacement {
+                        Some(string) => interner.string_id(&string),
+                        None => value,
+                    };
+                    normalized.insert(value, id);
+                    id
+                });
+                constraints.insert(constraint);
+            }
+            parts.push((block.name[root.len()..].to_string(), constraints));
+        }
+        parts.sort_by(|a, b| a.0.cmp(&b.0));
+        BlockIdentity { parts }
+    }
+}
+
+pub fn root_block_name(name:&str) -> &str {
+    match name.find("|sub_block|") {
+        Some(ix) => &name[..ix],
+        None => name,
+    }
+}
+
+// Groups blocks with their sub-blocks, keeping the order the roots were first seen in.
+fn group_blocks<'a, I>(blocks:I) -> Vec<(String, Vec<&'a Block>)> where I: Iterator<Item=&'a Block> {
+    let mut groups:Vec<(String, Vec<&'a Block>)> = vec![];
+    for block in blocks {
+        let root = root_block_name(&block.name);
+        match groups.iter().position(|&(ref name, _)| name == root) {
+            Some(ix) => groups[ix].1.push(block),
+            None => groups.push((root.to_string(), vec![block])),
+        }
+    }
+    groups
+}
+
+pub struct ReloadReport {
+    pub path: String,
+    pub added: Vec<Block>,
+    pub removed: Vec<String>,
+    pub added_roots: Vec<String>,
+    pub removed_roots: Vec<String>,
+    pub unchanged: usize,
+    pub error: Option<String>,
+}
+
+impl ReloadReport {
+    pub fn is_empty(&self) -> bool {
+        self.added.len() == 0 && self.removed.len() == 0 && self.error.is_none()
+    }
+
+    pub fn summary(&self) -> String {
+        match self.error {
+            Some(ref error) => format!("failed, {}", error),
+            None => format!("{} added, {} removed, {} unchanged", self.added_roots.len(), self.removed_roots.len(), self.unchanged),
+        }
+    }
+
+    pub fn to_raw_changes(&self) -> Vec<RawChange> {
+        let node = s("eve/reload");
+        let id = s(&format!("eve/reload|{}|{}", self.path, time::precise_time_ns()));
+        let mut changes = vec![
+            RawChange { e: id.clone(), a: s("tag"), v: s("eve/reload"), n: node.clone(), count: 1 },
+            RawChange { e: id.clone(), a: s("path"), v: s(&self.path), n: node.clone(), count: 1 },
+            RawChange { e: id.clone(), a: s("unchanged"), v: Internable::from_number(self.unchanged as f32), n: node.clone(), count: 1 },
+        ];
+        for name in self.added_roots.iter() {
+            changes.push(RawChange { e: id.clone(), a: s("added"), v: s(name), n: node.clone(), count: 1 });
+        }
+        for name in self.removed_roots.iter() {
+            changes.push(RawChange { e: id.clone(), a: s("removed"), v: s(name), n: node.clone(), count: 1 });
+        }
+        if let Some(ref error) = self.error {
+            changes.push(RawChange { e: id.clone(), a: s("error"), v: s(error), n: node.clone(), count: 1 });
+        }
+        changes
+    }
+}
+
 //-------------------------------------------------------------------------
 // Program
 //-------------------------------------------------------------------------
@@ -2529,6 +2977,33 @@ pub struct RuntimeState {
     pub interner: Interner,
     pub watch_indexes: HashMap<String, WatchIndex>,
     pub intermediates: IntermediateIndex,
+    pub profile: Option<Profile>,
+    pub limits: TransactionLimits,
+}
+
+impl RuntimeState {
+    pub fn begin_transaction(&mut self) {
+        self.index.begin();
+        self.distinct_index.begin();
+        self.intermediates.begin();
+    }
+
+    pub fn commit_transaction(&mut self) {
+        self.index.commit();
+        self.distinct_index.commit();
+        self.intermediates.commit();
+    }
+
+    pub fn rollback_transaction(&mut self) {
+        self.index.rollback();
+        self.distinct_index.rollback();
+        self.intermediates.rollback();
+        self.rounds.abort();
+        self.remote_index.clear();
+        for index in self.watch_indexes.values_mut() {
+            index.rollback();
+        }
+    }
 }
 
 pub struct BlockInfo {
@@ -2551,11 +3026,16 @@ pub enum RunLoopMessage {
     Stop,
     Pause,
     Resume,
+    // Applies the next transaction queued up while paused.
+    Step,
     Reload(HashSet<PathBuf>),
     Transaction(Vec<RawChange>),
     RemoteTransaction(Vec<RawRemoteChange>),
     CodeTransaction(Vec<Block>, Vec<String>),
-    RemoteCodeTransaction(Vec<PortableBlock>, Vec<String>)
+    RemoteCodeTransaction(Vec<PortableBlock>, Vec<String>),
+    Explain(String, Sender<String>),
+    AttachWatcher(Box<Watcher + Send>),
+    DetachWatcher(String),
 }
 
 impl RunLoopMessage {
@@ -2564,6 +3044,7 @@ impl RunLoopMessage {
             &RunLoopMessage::Stop => "`Stop message`".to_string(),
             &RunLoopMessage::Pause => "`Pause message`".to_string(),
             &RunLoopMessage::Resume => "`Resume message`".to_string(),
+            &RunLoopMessage::Step => "`Step message`".to_string(),
             &RunLoopMessage::Reload(ref hs) => {
                 let paths = hs.iter()
                     .map(|pb|
@@ -2613,6 +3094,9 @@ impl RunLoopMessage {
                         removed_blocks.len(),
                         removed_blocks.join(", "))
             }
+            &RunLoopMessage::Explain(ref name, _) => format!("`Explain` for block: {}", name),
+            &RunLoopMessage::AttachWatcher(ref watcher) => format!("`Attach watcher`: {}", watcher.get_name()),
+            &RunLoopMessage::DetachWatcher(ref name) => format!("`Detach watcher`: {}", name),
         }
     }
 }
@@ -2681,7 +3165,7 @@ impl Program {
         let remote_pipe_lookup = HashMap::new();
         let blocks = vec![];
         let (outgoing, incoming) = mpsc::channel();
-        let state = RuntimeState { debug:false, rounds, remote_index, output_rounds, index, distinct_index, interner, watch_indexes, intermediates };
+        let state = RuntimeState { debug:false, rounds, remote_index, output_rounds, index, distinct_index, interner, watch_indexes, intermediates, profile:None, limits:TransactionLimits::new() };
         let block_info = BlockInfo { pipe_lookup, remote_pipe_lookup, intermediate_pipe_lookup, block_names, blocks };
         Program { name: name.to_owned(), state, block_info, watchers, incoming, outgoing }
     }
@@ -2763,14 +3247,16 @@ impl Program {
     }
 
     pub fn insert_block(&mut self, name:&str, code:&str) {
-        let bs = make_block(&mut self.state.interner, name, code);
+        let schemas = self.watch_schemas();
+        let bs = make_block(&mut self.state.interner, name, code, &schemas);
         for b in bs {
             self.register_block(b);
         }
     }
 
     pub fn block(&mut self, name:&str, code:&str) -> CodeTransaction {
-        let bs = make_block(&mut self.state.interner, name, code);
+        let schemas = self.watch_schemas();
+        let bs = make_block(&mut self.state.interner, name, code, &schemas);
         let mut txn = CodeTransaction::new();
         txn.exec(self, bs, vec![]);
         txn
@@ -2784,12 +3270,187 @@ impl Program {
         self.block_info.blocks.iter().filter(|block| block.path == path).collect()
     }
 
-    pub fn attach(&mut self, watcher:Box<Watcher + Send>) {
+    // Diffs the blocks currently loaded from `path` against the file's new contents. Blocks that
+    // are unchanged are left running, even if they've moved within the file.
+    pub fn reload(&mut self, path:&str, debug:bool) -> ReloadReport {
+        // A file we can't read is most likely mid-save, so we hold on to what we've got rather
+        // than treating it as empty and tearing every block down.
+        let mut content = String::new();
+        let read = File::open(path).and_then(|mut file| file.read_to_string(&mut content));
+        if let Err(error) = read {
+            let error = format!("unable to read {}: {}", path, error);
+            log_error!(&self.name, "Hot-reload {}", error);
+            let unchanged = group_blocks(self.block_info.blocks.iter().filter(|block| block.path == path)).len();
+            return ReloadReport { path: path.to_owned(), added: vec![], removed: vec![], added_roots: vec![], removed_roots: vec![], unchanged, error: Some(error) };
+        }
+
+        let mut old:Vec<(String, BlockIdentity)> = vec![];
+        {
+            let interner = &mut self.state.interner;
+            for (root, blocks) in group_blocks(self.block_info.blocks.iter().filter(|block| block.path == path)) {
+                let identity = BlockIdentity::new(interner, &root, &blocks);
+                old.push((root, identity));
+            }
+        }
+
+        let schemas = self.watch_schemas();
+        let mut parsed = parse_string_named(&mut self.state.interner, &content, path, debug, &HashMap::new(), &schemas);
+        let mut kept = vec![];
+        let mut added_roots = vec![];
+        for (root, blocks) in group_blocks(parsed.iter()) {
+            let identity = BlockIdentity::new(&mut self.state.interner, &root, &blocks);
+            match old.iter().position(|&(_, ref existing)| *existing == identity) {
+                Some(ix) => {
+                    let (existing, _) = old.swap_remove(ix);
+                    kept.push((existing, root));
+                }
+                None => added_roots.push(root),
+            }
+        }
+        let removed_roots:Vec<String> = old.into_iter().map(|(root, _)| root).collect();
+
+        // Kept blocks may have moved, so they keep their old names but pick up their new source.
+        for &(ref existing, ref root) in kept.iter() {
+            if let Some(source) = parsed.iter().find(|block| &block.name == root) {
+                for block in self.block_info.blocks.iter_mut() {
+                    if block.path == path && root_block_name(&block.name) == existing.as_str() {
+                        block.span = source.span.clone();
+                        block.code = source.code.clone();
+                    }
+                }
+            }
+        }
+
+        // An added block can't take the name of a block we're keeping, so it gets a fresh one.
+        let prefix = format!("{}|block|", path);
+        let position = |name:&str| if name.starts_with(&prefix) { name[prefix.len()..].parse::<usize>().ok() } else { None };
+        let taken:HashSet<String> = kept.iter().map(|&(ref existing, _)| existing.to_owned()).collect();
+        let mut next = kept.iter().flat_map(|&(ref existing, ref root)| vec![position(existing), position(root)])
+            .chain(added_roots.iter().map(|root| position(root)))
+            .filter_map(|ix| ix)
+            .max().unwrap_or(0);
+        let mut names = HashMap::new();
+        for root in added_roots.iter_mut() {
+            if taken.contains(&*root) {
+                if let Some(ix) = position(root) {
+                    next += 1;
+                    *root = format!("{}{}", prefix, next);
+                    names.insert(ix, root.to_owned());
+                }
+            }
+        }
+        if names.len() > 0 {
+            parsed = parse_string_named(&mut self.state.interner, &content, path, debug, &names, &schemas);
+        }
+
+        let added:Vec<Block> = parsed.into_iter().filter(|block| added_roots.iter().any(|root| root == root_block_name(&block.name))).collect();
+        let removed:Vec<String> = self.block_info.blocks.iter()
+            .filter(|block| block.path == path && removed_roots.iter().any(|root| root == root_block_name(&block.name)))
+            .map(|block| block.name.to_owned())
+            .collect();
+
+        ReloadReport { path: path.to_owned(), added, removed, added_roots, removed_roots, unchanged: kept.len(), error: None }
+    }
+
+    // Attaching a watcher under a name that's already taken replaces the old one.
+    pub fn attach(&mut self, mut watcher:Box<Watcher + Send>) {
         let name = watcher.get_name();
-        println!("[{}] {} {}", &self.name, BrightCyan.paint("Loaded Watcher:"), name);
+        if let Some(mut replaced) = self.watchers.remove(&name) {
+            replaced.on_shutdown();
+        }
+        log_info!(&self.name, "Loaded watcher: {}", name);
+        let current = match self.state.watch_indexes.get(&name) {
+            Some(index) => index.current(),
+            None => WatchDiff { adds: vec![], removes: vec![] },
+        };
+        let attached = {
+            let interner = &mut self.state.interner;
+            let outgoing = self.outgoing.clone();
+            panic::catch_unwind(AssertUnwindSafe(|| watcher.on_attach(interner, outgoing, current)))
+        };
+        if let Err(payload) = attached {
+            let error = TransactionError::WatcherPanic { message: panic_message(&payload), watcher: name.to_string() };
+            report_error(self, error);
+        }
         self.watchers.insert(name, watcher);
     }
 
+    // Blocks are checked against the watchers attached right now, so a detached watcher's schema
+    // goes with it.
+    pub fn watch_schemas(&self) -> WatchSchemas {
+        self.watchers.iter().filter_map(|(name, watcher)| watcher.schema().map(|schema| (name.to_owned(), schema))).collect()
+    }
+
+    pub fn detach(&mut self, name:&str) -> bool {
+        match self.watchers.remove(name) {
+            Some(mut watcher) => {
+                watcher.on_shutdown();
+                log_info!(&self.name, "Detached watcher: {}", name);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn shutdown(&mut self) {
+        for watcher in self.watchers.values_mut() {
+            watcher.on_shutdown();
+        }
+    }
+
+    pub fn block_pipes(&self, block_id:Interned) -> Vec<&Solver> {
+        let mut pipes:Vec<&Solver> = vec![];
+        let lookups = self.block_info.pipe_lookup.values()
+            .chain(self.block_info.intermediate_pipe_lookup.values())
+            .chain(self.block_info.remote_pipe_lookup.values());
+        for found in lookups {
+            for pipe in found.iter() {
+                if pipe.block == block_id && !pipes.iter().any(|existing| existing.id == pipe.id) {
+                    pipes.push(pipe);
+                }
+            }
+        }
+        pipes.sort_by_key(|pipe| pipe.id);
+        pipes
+    }
+
+    // Blocks can be given by their full name or by a `|` delimited suffix of it, e.g. `block|2`.
+    pub fn explain(&self, name:&str) -> Option<String> {
+        let suffix = format!("|{}", name);
+        let roots:Vec<&Block> = self.block_info.blocks.iter().filter(|block| {
+            block.sub_block_kind.is_none() && (block.name == name || block.name.ends_with(&suffix))
+        }).collect();
+        if roots.len() == 0 { return None; }
+        let mut out = String::new();
+        for root in roots {
+            out.push_str(&root.explain(&self.state.interner, &self.block_pipes(root.block_id)));
+            let prefix = format!("{}|sub_block|", root.name);
+            let mut subs:Vec<&Block> = self.block_info.blocks.iter().filter(|block| block.name.starts_with(&prefix)).collect();
+            subs.sort_by(|a, b| natord::compare(&a.name, &b.name));
+            if subs.len() > 0 {
+                out.push_str("  sub blocks:\n");
+                for sub in subs {
+                    for line in sub.explain(&self.state.interner, &self.block_pipes(sub.block_id)).lines() {
+                        out.push_str(&format!("    {}\n", line));
+                    }
+                }
+            }
+            out.push_str("\n");
+        }
+        Some(out)
+    }
+
+    pub fn report_profile(&mut self, json:bool) {
+        if let Some(ref mut profile) = self.state.profile {
+            if json {
+                println!("{}", profile.to_json(&self.block_info, &self.state.interner));
+            } else {
+                println!("[{}] {}\n{}", &self.name, BrightCyan.paint("Profile:"), profile.report(&self.block_info, &self.state.interner));
+            }
+            profile.clear();
+        }
+    }
+
     pub fn get_pipes<'a>(&self, block_info:&'a BlockInfo, input: &Change, pipes: &mut HashSet<&'a Solver>) {
         let ref pipe_lookup = block_info.pipe_lookup;
         let mut tuple = (0,0,0);
@@ -2868,10 +3529,303 @@ impl Program {
     }
 }
 
+//-------------------------------------------------------------------------
+// Transaction limits
+//-------------------------------------------------------------------------
+
+// A pair of blocks that keep toggling each other's output will never reach a fixpoint, so we bound
+// the number of rounds per frame and the number of commit frames per transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TransactionLimits {
+    pub max_rounds: Round,
+    pub max_frames: usize,
+}
+
+impl TransactionLimits {
+    pub fn new() -> TransactionLimits {
+        TransactionLimits { max_rounds: 1000, max_frames: 1000 }
+    }
+}
+
+//-------------------------------------------------------------------------
+// Pause queue
+//-------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PauseOverflow {
+    // Make room by forgetting the oldest queued transaction.
+    DropOldest,
+    // Ignore anything new until there's room again.
+    DropNewest,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PauseLimits {
+    pub max_queued: usize,
+    pub overflow: PauseOverflow,
+}
+
+impl PauseLimits {
+    pub fn new() -> PauseLimits {
+        PauseLimits { max_queued: 10000, overflow: PauseOverflow::DropNewest }
+    }
+}
+
+// Transactions and code changes that arrive while a program is paused wait here until it's resumed
+// or stepped. Only transactions count towards the limit, dropping a code change would leave the
+// program out of step with its source.
+pub struct PauseQueue {
+    limits: PauseLimits,
+    queued: VecDeque<RunLoopMessage>,
+    transactions: usize,
+    dropped: usize,
+}
+
+impl PauseQueue {
+    pub fn new(limits:PauseLimits) -> PauseQueue {
+        PauseQueue { limits, queued: VecDeque::new(), transactions: 0, dropped: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.queued.len()
+    }
+
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+
+    fn is_transaction(message:&RunLoopMessage) -> bool {
+        match message {
+            &RunLoopMessage::Transaction(_) | &RunLoopMessage::RemoteTransaction(_) => true,
+            _ => false,
+        }
+    }
+
+    pub fn push(&mut self, message:RunLoopMessage) {
+        if !PauseQueue::is_transaction(&message) {
+            self.queued.push_back(message);
+            return;
+        }
+        if self.transactions >= self.limits.max_queued {
+            self.dropped += 1;
+            if self.dropped == 1 {
+                log_warn!("run loop", "More than {} transactions queued while paused, dropping the {}", self.limits.max_queued,
+                          if self.limits.overflow == PauseOverflow::DropOldest { "oldest" } else { "newest" });
+            }
+            match self.limits.overflow {
+                PauseOverflow::DropOldest => {
+                    if let Some(ix) = self.queued.iter().position(PauseQueue::is_transaction) {
+                        self.queued.remove(ix);
+                        self.transactions -= 1;
+                    }
+                }
+                PauseOverflow::DropNewest => return,
+            }
+        }
+        self.transactions += 1;
+        self.queued.push_back(message);
+    }
+
+    pub fn pop(&mut self) -> Option<RunLoopMessage> {
+        let message = self.queued.pop_front();
+        if message.as_ref().map(PauseQueue::is_transaction).unwrap_or(false) {
+            self.transactions -= 1;
+        }
+        message
+    }
+
+    pub fn drain(&mut self) -> Vec<RunLoopMessage> {
+        self.dropped = 0;
+        self.transactions = 0;
+        self.queued.drain(..).collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunawayKind {
+    Rounds,
+    Frames,
+}
+
+#[derive(Debug, Clone)]
+pub struct RunawayTransaction {
+    pub kind: RunawayKind,
+    pub limit: usize,
+    pub blocks: Vec<Interned>,
+    pub facts: Vec<Change>,
+}
+
+const MAX_REPORTED_FACTS:usize = 50;
+
+impl RunawayTransaction {
+    pub fn new(kind:RunawayKind, limit:usize, blocks:Vec<Interned>, mut facts:Vec<Change>) -> RunawayTransaction {
+        let mut blocks = blocks;
+        blocks.sort();
+        blocks.dedup();
+        facts.truncate(MAX_REPORTED_FACTS);
+        RunawayTransaction { kind, limit, blocks, facts }
+    }
+
+    pub fn message(&self) -> String {
+        let what = match self.kind {
+            RunawayKind::Rounds => "rounds",
+            RunawayKind::Frames => "commit frames",
+        };
+        format!("Transaction exceeded the limit of {} {} and was rolled back. The blocks involved never reach a fixpoint.", self.limit, what)
+    }
+
+    fn print_fact(fact:&Change, interner:&Interner) -> String {
+        format!("({}, {}, {}) {}", interner.get_value(fact.e).print(), interner.get_value(fact.a).print(), interner.get_value(fact.v).print(),
+                if fact.count > 0 { "+" } else { "-" })
+    }
+
+    pub fn report(&self, interner:&Interner) -> String {
+        let mut out = format!("{}\n", self.message());
+        if self.blocks.len() > 0 {
+            out.push_str("  Blocks:\n");
+            for block in self.blocks.iter() {
+                out.push_str(&format!("    - {}\n", interner.get_value(*block).print()));
+            }
+        }
+        if self.facts.len() > 0 {
+            out.push_str("  Facts:\n");
+            for fact in self.facts.iter() {
+                out.push_str(&format!("    - {}\n", RunawayTransaction::print_fact(fact, interner)));
+            }
+        }
+        out
+    }
+
+    fn add_details(&self, id:&str, interner:&Interner, changes:&mut Vec<RawChange>) {
+        let node = s("eve/error");
+        let err_id = s(id);
+        let kind = match self.kind {
+            RunawayKind::Rounds => "rounds",
+            RunawayKind::Frames => "frames",
+        };
+        changes.push(RawChange { e: err_id.clone(), a: s("kind"), v: s(kind), n: node.clone(), count: 1 });
+        changes.push(RawChange { e: err_id.clone(), a: s("limit"), v: n(self.limit as f32), n: node.clone(), count: 1 });
+        for block in self.blocks.iter() {
+            changes.push(RawChange { e: err_id.clone(), a: s("block"), v: interner.get_value(*block).clone(), n: node.clone(), count: 1 });
+        }
+        for (ix, fact) in self.facts.iter().enumerate() {
+            let fact_id = s(&format!("{}|fact|{}", id, ix));
+            changes.push(RawChange { e: err_id.clone(), a: s("fact"), v: fact_id.clone(), n: node.clone(), count: 1 });
+            changes.push(RawChange { e: fact_id.clone(), a: s("entity"), v: interner.get_value(fact.e).clone(), n: node.clone(), count: 1 });
+            changes.push(RawChange { e: fact_id.clone(), a: s("attribute"), v: interner.get_value(fact.a).clone(), n: node.clone(), count: 1 });
+            changes.push(RawChange { e: fact_id.clone(), a: s("value"), v: interner.get_value(fact.v).clone(), n: node.clone(), count: 1 });
+            changes.push(RawChange { e: fact_id, a: s("count"), v: n(fact.count as f32), n: node.clone(), count: 1 });
+        }
+    }
+}
+
+//-------------------------------------------------------------------------
+// Transaction errors
+//-------------------------------------------------------------------------
+
+#[derive(Debug, Clone)]
+pub enum TransactionError {
+    Runaway(RunawayTransaction),
+    Panic { message: String, block: Option<Interned> },
+    WatcherPanic { message: String, watcher: String },
+}
+
+pub fn panic_message(payload:&Box<Any + Send>) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.to_string()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+impl TransactionError {
+    pub fn from_panic(payload:Box<Any + Send>, block:Interned) -> TransactionError {
+        TransactionError::Panic { message: panic_message(&payload), block: if block > 0 { Some(block) } else { None } }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            &TransactionError::Runaway(..) => "runaway",
+            &TransactionError::Panic { .. } => "panic",
+            &TransactionError::WatcherPanic { .. } => "watcher-panic",
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            &TransactionError::Runaway(ref runaway) => runaway.message(),
+            &TransactionError::Panic { ref message, .. } => format!("Transaction panicked and was rolled back: {}", message),
+            &TransactionError::WatcherPanic { ref message, ref watcher } => format!("Watcher '{}' panicked: {}", watcher, message),
+        }
+    }
+
+    pub fn report(&self, interner:&Interner) -> String {
+        match self {
+            &TransactionError::Runaway(ref runaway) => runaway.report(interner),
+            &TransactionError::Panic { block: Some(block), .. } => {
+                format!("{}\n  Block: {}\n", self.message(), interner.get_value(block).print())
+            }
+            _ => format!("{}\n", self.message()),
+        }
+    }
+
+    pub fn to_raw_changes(&self, interner:&Interner) -> Vec<RawChange> {
+        let node = s("eve/error");
+        let id = format!("eve/error/{}|{}", self.kind(), time::precise_time_ns());
+        let err_id = s(&id);
+        let mut changes = vec![
+            RawChange { e: err_id.clone(), a: s("tag"), v: s("eve/error"), n: node.clone(), count: 1 },
+            RawChange { e: err_id.clone(), a: s("tag"), v: s(&format!("eve/error/{}", self.kind())), n: node.clone(), count: 1 },
+            RawChange { e: err_id.clone(), a: s("message"), v: s(&self.message()), n: node.clone(), count: 1 },
+        ];
+        match self {
+            &TransactionError::Runaway(ref runaway) => runaway.add_details(&id, interner, &mut changes),
+            &TransactionError::Panic { block: Some(block), .. } => {
+                changes.push(RawChange { e: err_id.clone(), a: s("block"), v: interner.get_value(block).clone(), n: node.clone(), count: 1 });
+            }
+            &TransactionError::WatcherPanic { ref watcher, .. } => {
+                changes.push(RawChange { e: err_id.clone(), a: s("watcher"), v: s(watcher), n: node.clone(), count: 1 });
+            }
+            _ => {}
+        }
+        changes
+    }
+}
+
+// Reports the error on the console and as an #eve/error record fed back into the program.
+fn report_error(program:&mut Program, error:TransactionError) {
+    log_error!(&program.name, "{}", error.report(&program.state.interner).trim_right());
+    let changes = error.to_raw_changes(&program.state.interner);
+    program.outgoing.send(RunLoopMessage::Transaction(changes)).ok();
+}
+
+// Undoes everything the transaction did before reporting what went wrong.
+fn abort_transaction(program:&mut Program, error:TransactionError) {
+    program.state.rollback_transaction();
+    report_error(program, error);
+}
+
 //-------------------------------------------------------------------------
 // Transaction
 //-------------------------------------------------------------------------
 
+fn profile_pipe<F>(pipe: &Solver, state: &mut RuntimeState, frame: &mut Frame, run: F) where F: FnOnce(&mut RuntimeState, &mut Frame) {
+    if state.profile.is_none() {
+        run(state, frame);
+        return;
+    }
+    let start_ns = time::precise_time_ns();
+    let instructions = frame.counters.instructions;
+    let inserts = frame.counters.inserts;
+    run(state, frame);
+    let total_ns = time::precise_time_ns() - start_ns;
+    if let Some(ref mut profile) = state.profile {
+        profile.pipe_run(pipe.block, pipe.id, frame.counters.instructions - instructions, frame.counters.inserts - inserts, total_ns);
+    }
+}
+
 fn intermediate_flow(frame: &mut Frame, state: &mut RuntimeState, block_info: &BlockInfo, iter_pool:&mut EstimateIterPool, current_round:Round, max_round:&mut Round) {
     let mut intermediate_max = state.intermediates.consume_round();
     *max_round = cmp::max(*max_round, intermediate_max);
@@ -2888,7 +3842,7 @@ fn intermediate_flow(frame: &mut Frame, state: &mut RuntimeState, block_info: &B
                     for pipe in actives.iter() {
                         // print_pipe(pipe, block_info, state);
                         frame.row.reset();
-                        pipe.run_intermediate(state, iter_pool, frame);
+                        profile_pipe(pipe, state, frame, |state, frame| pipe.run_intermediate(state, iter_pool, frame));
                         // if state.debug {
                         //     state.debug = false;
                         //     println!("\n---------------------------------\n");
@@ -2903,20 +3857,36 @@ fn intermediate_flow(frame: &mut Frame, state: &mut RuntimeState, block_info: &B
     }
 }
 
-fn transaction_flow(commits: &mut Vec<Change>, frame: &mut Frame, iter_pool:&mut EstimateIterPool, program: &mut Program) {
+fn transaction_flow(commits: &mut Vec<Change>, frame: &mut Frame, iter_pool:&mut EstimateIterPool, program: &mut Program) -> Result<(), TransactionError> {
     transaction_flow_meta(commits, frame, iter_pool, program, None)
 }
 
-fn transaction_flow_meta(commits: &mut Vec<Change>, frame: &mut Frame, iter_pool:&mut EstimateIterPool, program: &mut Program, maybe_meta: Option<&mut MetaMessage>) {
+fn transaction_flow_meta(commits: &mut Vec<Change>, frame: &mut Frame, iter_pool:&mut EstimateIterPool, program: &mut Program, maybe_meta: Option<&mut MetaMessage>) -> Result<(), TransactionError> {
     {
+        let limits = program.state.limits;
         let mut pipes = HashSet::new();
         let mut next_frame = true;
+        let mut frames = 0;
+        // The blocks that produced output in the current and previous round, so that when the
+        // round limit is hit we can say who keeps feeding the next round.
+        let mut producers = HashSet::new();
+        let mut prev_producers = HashSet::new();
 
         while next_frame {
+            frames += 1;
+            if frames > limits.max_frames {
+                let facts = program.state.rounds.pending(0);
+                let blocks = program.state.rounds.committers();
+                return Err(TransactionError::Runaway(RunawayTransaction::new(RunawayKind::Frames, limits.max_frames, blocks, facts)));
+            }
             let mut current_round = 0;
             let mut max_round:Round = program.state.rounds.max_round as Round;
             let mut items = program.state.rounds.iter();
             while current_round <= max_round {
+                if current_round > limits.max_rounds {
+                    let facts = program.state.rounds.pending(current_round as usize);
+                    return Err(TransactionError::Runaway(RunawayTransaction::new(RunawayKind::Rounds, limits.max_rounds as usize, prev_producers.drain().collect(), facts)));
+                }
                 let round = items.get_round(&mut program.state.rounds, current_round);
                 for change in round.iter() {
                     // println!("-> {}", change.print(&program.state.interner));
@@ -2943,7 +3913,9 @@ fn transaction_flow_meta(commits: &mut Vec<Change>, frame: &mut Frame, iter_pool
                     for pipe in pipes.iter() {
                         // println!("  PIPE: {:?} - {:?}", pipe.block, pipe.id);
                         frame.row.reset();
-                        pipe.run(&mut program.state, iter_pool, frame);
+                        let inserts = frame.counters.inserts;
+                        profile_pipe(pipe, &mut program.state, frame, |state, frame| pipe.run(state, iter_pool, frame));
+                        if frame.counters.inserts > inserts { producers.insert(pipe.block); }
                     }
                     // as stated above, we want to do removes after so that when we look
                     // for AB and BA, they find the same values as when they were added.
@@ -2964,19 +3936,32 @@ fn transaction_flow_meta(commits: &mut Vec<Change>, frame: &mut Frame, iter_pool
                 intermediate_flow(frame, &mut program.state, &program.block_info, iter_pool, current_round, &mut max_round);
                 max_round = cmp::max(max_round, program.state.rounds.max_round as Round);
                 current_round += 1;
+                mem::swap(&mut producers, &mut prev_producers);
+                producers.clear();
             }
             next_frame = program.state.rounds.prepare_commits(&mut program.state.index, &mut program.state.distinct_index);
         }
     }
 
+    // Watchers only see the transaction once it's been committed, so a watcher blowing up doesn't
+    // undo it, we just report it and move on.
+    program.state.commit_transaction();
+    let mut watcher_errors = vec![];
     for (name, index) in program.state.watch_indexes.iter_mut() {
         if index.dirty() {
             let diff = index.reconcile();
             if let Some(watcher) = program.watchers.get_mut(name) {
-                watcher.on_diff(&mut program.state.interner, diff);
+                let interner = &mut program.state.interner;
+                if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| watcher.on_diff(interner, diff))) {
+                    watcher_errors.push(TransactionError::WatcherPanic { message: panic_message(&payload), watcher: name.to_string() });
+                }
             }
         }
     }
+    for error in watcher_errors {
+        report_error(program, error);
+    }
+    Ok(())
 }
 
 pub struct Transaction<'a> {
@@ -3009,10 +3994,29 @@ impl<'a> Transaction<'a> {
         if let Some(&mut MetaMessage::Transaction{ref mut inputs, ..}) = maybe_meta {
             inputs.extend(self.changes.iter().map(|c| c.to_raw(&program.state.interner)));
         }
-        for change in self.changes.iter() {
-            program.state.distinct_index.distinct(&change, &mut program.state.rounds);
+        self.frame.block = 0;
+        program.state.begin_transaction();
+        let result = {
+            let changes = &self.changes;
+            let commits = &mut self.commits;
+            let frame = &mut self.frame;
+            let iter_pool = &mut *self.iter_pool;
+            let program = &mut *program;
+            panic::catch_unwind(AssertUnwindSafe(move || {
+                for change in changes.iter() {
+                    program.state.distinct_index.distinct(&change, &mut program.state.rounds);
+                }
+                transaction_flow_meta(commits, frame, iter_pool, program, maybe_meta)
+            }))
+        };
+        let result = result.unwrap_or_else(|payload| Err(TransactionError::from_panic(payload, self.frame.block)));
+        if let Err(error) = result {
+            self.commits.clear();
+            self.iter_pool.reset_all();
+            self.frame.reset();
+            abort_transaction(program, error);
+            return;
         }
-        transaction_flow_meta(&mut self.commits, &mut self.frame, self.iter_pool, program, maybe_meta);
         if let &mut Some(ref channel) = persistence_channel {
             self.collapsed_commits.clear();
             let mut to_persist = vec![];
@@ -3038,6 +4042,125 @@ impl<'a> Transaction<'a> {
 // Remote Transaction
 //-------------------------------------------------------------------------
 
+fn run_transaction(program:&mut Program, iter_pool:&mut EstimateIterPool, persistence_channel:&mut Option<Sender<PersisterMessage>>, meta_channel:&Option<Sender<MetaMessage>>, recorder:&mut Option<Recorder>, changes:Vec<RawChange>, debug_profile_json:bool) {
+    if let Some(ref mut recorder) = *recorder {
+        recorder.record(RecordedMessage::Transaction(changes.clone()));
+    }
+    log_debug!(&program.name, "Txn started");
+    let start_ns = time::precise_time_ns();
+    let mut txn = Transaction::new(iter_pool);
+    for cur in changes {
+        // println!("  -> {:?}", cur);
+        txn.input_change(cur.to_change(&mut program.state.interner));
+    };
+
+    if let &Some(ref meta_chan) = meta_channel {
+        let mut meta_message = MetaMessage::Transaction{inputs: vec![], outputs: vec![]};
+        txn.exec_meta(program, persistence_channel, Some(&mut meta_message));
+        meta_chan.send(meta_message.collapse());
+    } else {
+        txn.exec(program, persistence_channel);
+    }
+
+    let end_ns = time::precise_time_ns();
+    let time = (end_ns - start_ns) as f64;
+    log_debug!(&program.name, "Txn took {:?} - {:?} insts ({:?} ns) - {:?} inserts ({:?} ns)", time / 1_000_000.0, txn.frame.counters.instructions, (time / (txn.frame.counters.instructions as f64)).floor(), txn.frame.counters.inserts, (time / (txn.frame.counters.inserts as f64)).floor());
+    program.report_profile(debug_profile_json);
+}
+
+// Transactions that were queued while paused.
+fn run_queued(program:&mut Program, iter_pool:&mut EstimateIterPool, persistence_channel:&mut Option<Sender<PersisterMessage>>, meta_channel:&Option<Sender<MetaMessage>>, recorder:&mut Option<Recorder>, message:RunLoopMessage, debug_compile:bool, debug_profile_json:bool) {
+    match message {
+        RunLoopMessage::Transaction(v) => run_transaction(program, iter_pool, persistence_channel, meta_channel, recorder, v, debug_profile_json),
+        RunLoopMessage::RemoteTransaction(v) => run_remote_transaction(program, iter_pool, persistence_channel, recorder, v, debug_profile_json),
+        RunLoopMessage::CodeTransaction(adds, removes) => run_code_transaction(program, recorder, adds, removes),
+        RunLoopMessage::RemoteCodeTransaction(adds, removes) => run_remote_code_transaction(program, recorder, adds, removes),
+        RunLoopMessage::Reload(paths) => run_reload(program, iter_pool, persistence_channel, meta_channel, recorder, paths, debug_compile, debug_profile_json),
+        _ => {}
+    }
+}
+
+fn run_code_transaction(program:&mut Program, recorder:&mut Option<Recorder>, adds:Vec<Block>, removes:Vec<String>) {
+    let start_ns = time::precise_time_ns();
+    let mut tx = CodeTransaction::new();
+    log_debug!(&program.name, "Code txn started");
+    if let Some(ref mut recorder) = *recorder {
+        let added = RecordedBlock::from_blocks(&program.state.interner, &adds);
+        recorder.record(RecordedMessage::CodeTransaction { added, removed: removes.clone() });
+    }
+    log_code_changes(&program.name, &adds, &removes);
+    tx.exec(program, adds, removes);
+    let end_ns = time::precise_time_ns();
+    let time = (end_ns - start_ns) as f64;
+    log_debug!(&program.name, "Txn took {:?}", time / 1_000_000.0);
+}
+
+fn run_remote_code_transaction(program:&mut Program, recorder:&mut Option<Recorder>, adds:Vec<PortableBlock>, removes:Vec<String>) {
+    let start_ns = time::precise_time_ns();
+    let mut tx = CodeTransaction::new();
+    log_debug!(&program.name, "Remote code txn started");
+    if let Some(ref mut recorder) = *recorder {
+        let added = adds.iter().cloned().map(RecordedBlock::Portable).collect();
+        recorder.record(RecordedMessage::CodeTransaction { added, removed: removes.clone() });
+    }
+    let added_blocks:Vec<Block> = adds.iter().map(|b| b.intern(&mut program.state.interner)).collect();
+    log_code_changes(&program.name, &added_blocks, &removes);
+
+    tx.exec(program, added_blocks, removes);
+    let end_ns = time::precise_time_ns();
+    let time = (end_ns - start_ns) as f64;
+    log_debug!(&program.name, "Txn took {:?}", time / 1_000_000.0);
+}
+
+// The code change and its report are applied right away rather than sent back around, so that
+// anything queued up behind the reload sees the new code.
+fn run_reload(program:&mut Program, iter_pool:&mut EstimateIterPool, persistence_channel:&mut Option<Sender<PersisterMessage>>, meta_channel:&Option<Sender<MetaMessage>>, recorder:&mut Option<Recorder>, paths:HashSet<PathBuf>, debug_compile:bool, debug_profile_json:bool) {
+    let mut added_blocks:Vec<Block> = vec![];
+    let mut removed_blocks:Vec<String> = vec![];
+    let mut reports:Vec<RawChange> = vec![];
+    for path in paths {
+        let canonical = path.canonicalize();
+        let resolved = match canonical {
+            Ok(resolved) => resolved,
+            Err(_) => path,
+        };
+        let resolved_path = resolved.to_str().unwrap();
+
+        let report = program.reload(resolved_path, debug_compile);
+        if report.error.is_none() {
+            log_info!(&program.name, "Hot-reloading {} ({})", resolved_path, report.summary());
+        }
+        if report.is_empty() { continue; }
+        reports.extend(report.to_raw_changes());
+        added_blocks.extend(report.added);
+        removed_blocks.extend(report.removed);
+    }
+
+    if added_blocks.len() > 0 || removed_blocks.len() > 0 {
+        run_code_transaction(program, recorder, added_blocks, removed_blocks);
+    }
+    if reports.len() > 0 {
+        run_transaction(program, iter_pool, persistence_channel, meta_channel, recorder, reports, debug_profile_json);
+    }
+}
+
+fn run_remote_transaction(program:&mut Program, iter_pool:&mut EstimateIterPool, persistence_channel:&mut Option<Sender<PersisterMessage>>, recorder:&mut Option<Recorder>, changes:Vec<RawRemoteChange>, debug_profile_json:bool) {
+    if let Some(ref mut recorder) = *recorder {
+        recorder.record(RecordedMessage::RemoteTransaction(changes.clone()));
+    }
+    let start_ns = time::precise_time_ns();
+    log_debug!(&program.name, "Remote txn started");
+    let mut txn = RemoteTransaction::new(iter_pool);
+    for cur in changes {
+        txn.input_change(cur.to_change(&mut program.state.interner));
+    };
+    txn.exec(program, persistence_channel);
+    let end_ns = time::precise_time_ns();
+    let time = (end_ns - start_ns) as f64;
+    log_debug!(&program.name, "Txn took {:?} - {:?} insts ({:?} ns) - {:?} inserts ({:?} ns)", time / 1_000_000.0, txn.frame.counters.instructions, (time / (txn.frame.counters.instructions as f64)).floor(), txn.frame.counters.inserts, (time / (txn.frame.counters.inserts as f64)).floor());
+    program.report_profile(debug_profile_json);
+}
+
 pub struct RemoteTransaction<'a> {
     changes: Vec<RemoteChange>,
     commits: Vec<Change>,
@@ -3057,16 +4180,50 @@ impl<'a> RemoteTransaction<'a> {
     }
 
     pub fn exec(&mut self, program: &mut Program, persistence_channel: &mut Option<Sender<PersisterMessage>>) {
-        let ref mut frame = self.frame;
-        let ref mut iter_pool = self.iter_pool;
+        self.frame.block = 0;
+        program.state.begin_transaction();
+        let result = {
+            let changes = &mut self.changes;
+            let commits = &mut self.commits;
+            let frame = &mut self.frame;
+            let iter_pool = &mut *self.iter_pool;
+            let program = &mut *program;
+            panic::catch_unwind(AssertUnwindSafe(move || RemoteTransaction::flow(changes, commits, frame, iter_pool, program)))
+        };
+        let result = result.unwrap_or_else(|payload| Err(TransactionError::from_panic(payload, self.frame.block)));
+        if let Err(error) = result {
+            self.changes.clear();
+            self.commits.clear();
+            self.iter_pool.reset_all();
+            self.frame.reset();
+            abort_transaction(program, error);
+            return;
+        }
+        program.state.remote_index.clear();
 
-        for change in self.changes.drain(..) {
+        if let &mut Some(ref channel) = persistence_channel {
+            self.collapsed_commits.clear();
+            let mut to_persist = vec![];
+            for commit in self.commits.drain(..) {
+                self.collapsed_commits.insert(commit);
+            }
+            for commit in self.collapsed_commits.drain() {
+                to_persist.push(commit.to_raw(&program.state.interner));
+            }
+            channel.send(PersisterMessage::Write(to_persist)).unwrap();
+        } else {
+            self.commits.clear();
+        }
+    }
+
+    fn flow(changes:&mut Vec<RemoteChange>, commits:&mut Vec<Change>, frame:&mut Frame, iter_pool:&mut EstimateIterPool, program:&mut Program) -> Result<(), TransactionError> {
+        for change in changes.drain(..) {
             if let Some(ref pipes) = program.block_info.remote_pipe_lookup.get(&0) {
                 frame.reset();
                 frame.remote = Some(change.clone());
                 for pipe in pipes.iter() {
                     frame.row.reset();
-                    pipe.run_remote(&mut program.state, iter_pool, frame);
+                    profile_pipe(pipe, &mut program.state, frame, |state, frame| pipe.run_remote(state, iter_pool, frame));
                 }
             }
             if let Some(ref pipes) = program.block_info.remote_pipe_lookup.get(&change._for) {
@@ -3074,28 +4231,13 @@ impl<'a> RemoteTransaction<'a> {
                 frame.remote = Some(change.clone());
                 for pipe in pipes.iter() {
                     frame.row.reset();
-                    pipe.run_remote(&mut program.state, iter_pool, frame);
+                    profile_pipe(pipe, &mut program.state, frame, |state, frame| pipe.run_remote(state, iter_pool, frame));
                 }
             }
             program.state.remote_index.insert(change);
         }
 
-        transaction_flow(&mut self.commits, frame, iter_pool, program);
-        program.state.remote_index.clear();
-
-        if let &mut Some(ref channel) = persistence_channel {
-            self.collapsed_commits.clear();
-            let mut to_persist = vec![];
-            for commit in self.commits.drain(..) {
-                self.collapsed_commits.insert(commit);
-            }
-            for commit in self.collapsed_commits.drain() {
-                to_persist.push(commit.to_raw(&program.state.interner));
-            }
-            channel.send(PersisterMessage::Write(to_persist)).unwrap();
-        } else {
-            self.commits.clear();
-        }
+        transaction_flow(commits, frame, iter_pool, program)
     }
 
     pub fn clear(&mut self) {
@@ -3127,11 +4269,45 @@ impl CodeTransaction {
     }
 
     pub fn exec(&mut self, program: &mut Program, to_add:Vec<Block>, to_remove:Vec<String>) {
-        let ref mut frame = self.frame;
-        let ref mut iter_pool = self.iter_pool;
+        // If the new code fails we undo the code change along with its effects.
+        let mut registered = vec![];
+        let mut removed_blocks = vec![];
+
+        self.frame.block = 0;
+        program.state.begin_transaction();
+        let result = {
+            let changes = &self.changes;
+            let commits = &mut self.commits;
+            let frame = &mut self.frame;
+            let iter_pool = &mut self.iter_pool;
+            let program = &mut *program;
+            let registered = &mut registered;
+            let removed_blocks = &mut removed_blocks;
+            panic::catch_unwind(AssertUnwindSafe(move || {
+                CodeTransaction::flow(changes, commits, frame, iter_pool, program, to_add, to_remove, registered, removed_blocks)
+            }))
+        };
+        let result = result.unwrap_or_else(|payload| Err(TransactionError::from_panic(payload, self.frame.block)));
+        if let Err(error) = result {
+            self.commits.clear();
+            self.iter_pool.reset_all();
+            self.frame.reset();
+            // Only the blocks that made it in, a panic part way through the adds leaves the rest
+            // unregistered already.
+            for name in registered {
+                program.unregister_block(name);
+            }
+            for block in removed_blocks {
+                program.register_block(block);
+            }
+            abort_transaction(program, error);
+        }
+    }
 
+    fn flow(changes:&Vec<Change>, commits:&mut Vec<Change>, frame:&mut Frame, iter_pool:&mut EstimateIterPool, program:&mut Program,
+            to_add:Vec<Block>, to_remove:Vec<String>, registered:&mut Vec<String>, removed_blocks:&mut Vec<Block>) -> Result<(), TransactionError> {
         for name in to_remove {
-            {
+            let removed = {
                 let block_ix = match program.block_info.block_names.get(&name) {
                     Some(v) => *v,
                     _ => panic!("Unable to find block to remove: '{}'", name)
@@ -3141,25 +4317,29 @@ impl CodeTransaction {
                 frame.reset();
                 frame.input = Some(Change { e:0,a:0,v:0,n: 0, transaction:0, round:0, count:-1 });
                 remove.run(&mut program.state, iter_pool, frame);
-            }
+                remove.clone()
+            };
             program.unregister_block(name);
+            removed_blocks.push(removed);
         }
 
         for add in to_add {
             frame.reset();
             frame.input = Some(Change { e:0,a:0,v:0,n: 0, transaction:0, round:0, count:1 });
+            let name = add.name.to_owned();
             program.register_block(add);
+            registered.push(name);
             program.block_info.blocks.last().unwrap().run(&mut program.state, iter_pool, frame);
         }
 
         let mut max_round = 0;
         intermediate_flow(frame, &mut program.state, &program.block_info, iter_pool, 0, &mut max_round);
 
-        for change in self.changes.iter() {
+        for change in changes.iter() {
             program.state.distinct_index.distinct(&change, &mut program.state.rounds);
         }
 
-        transaction_flow(&mut self.commits, frame, iter_pool, program);
+        transaction_flow(commits, frame, iter_pool, program)
     }
 }
 
@@ -3167,7 +4347,7 @@ impl CodeTransaction {
 // Portable Code Transaction
 //-------------------------------------------------------------------------
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum PortableField {
     Register(usize),
     Value(Internable)
@@ -3203,6 +4383,7 @@ impl Field {
     }
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum PortableConstraint {
     Scan(PortableField, PortableField, PortableField),
     Filter(String, PortableField, PortableField),
@@ -3361,6 +4542,7 @@ impl Constraint {
     }
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct PortableBlock {
     pub name: String,
     pub block_id: Internable,
@@ -3419,7 +4601,7 @@ impl Persister {
                 match incoming.recv().unwrap() {
                     PersisterMessage::Stop => { break; }
                     PersisterMessage::Write(items) => {
-                        println!("Let's persist some stuff!");
+                        log_debug!("persist", "Persisting {} changes", items.len());
                         for item in items {
                             let result = bincode::serialize(&item, bincode::Infinite).unwrap();
                             match writer.write_all(&result) {
@@ -3439,7 +4621,7 @@ impl Persister {
         let file = match File::open(path) {
             Ok(f) => f,
             Err(_) => {
-                println!("Unable to load db: {}", path);
+                log_error!("persist", "Unable to load db: {}", path);
                 return;
             }
         };
@@ -3448,11 +4630,12 @@ impl Persister {
             let result:Result<RawChange, _> = bincode::deserialize_from(&mut reader, bincode::Infinite);
             match result {
                 Ok(c) => {
-                    println!("{:?}", c);
+                    log_trace!("persist", "Loaded {:?}", c);
                     self.loaded.push(c);
                 },
-                Err(info) => {
-                    println!("ran out {:?}", info);
+                // The db is just a run of changes, so we read until we can't.
+                Err(_) => {
+                    log_info!("persist", "Loaded {} changes from {}", self.loaded.len(), path);
                     break;
                 }
             }
@@ -3512,7 +4695,9 @@ impl RunLoop {
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum DebugMode {
-    Compile
+    Compile,
+    Profile,
+    ProfileJson,
 }
 
 pub struct ProgramRunner {
@@ -3522,12 +4707,14 @@ pub struct ProgramRunner {
     initial_commits: Vec<RawChange>,
     persistence_channel: Option<Sender<PersisterMessage>>,
     debug_modes: HashSet<DebugMode>,
+    pause_limits: PauseLimits,
+    recorder: Option<Recorder>,
     pub meta_channel: Option<Sender<MetaMessage>>
 }
 
 impl ProgramRunner {
     pub fn new(name:&str) -> ProgramRunner {
-        ProgramRunner {name: name.to_owned(), paths: vec![], program: Program::new(name), persistence_channel:None, initial_commits: vec![], debug_modes: HashSet::new(), meta_channel: None }
+        ProgramRunner {name: name.to_owned(), paths: vec![], program: Program::new(name), persistence_channel:None, initial_commits: vec![], debug_modes: HashSet::new(), pause_limits: PauseLimits::new(), recorder: None, meta_channel: None }
     }
 
     pub fn load(&mut self, path:&str) {
@@ -3536,45 +4723,77 @@ impl ProgramRunner {
 
     pub fn persist(&mut self, persister:&mut Persister) {
         self.persistence_channel = Some(persister.get_channel());
-        self.initial_commits = persister.get_commits();
+        self.initial_commits.extend(persister.get_commits());
+    }
+
+    // Facts that are committed along with the program's blocks, before any transaction runs.
+    pub fn startup(&mut self, changes:Vec<RawChange>) {
+        self.initial_commits.extend(changes);
     }
 
     pub fn debug(&mut self, mode:DebugMode) {
         self.debug_modes.insert(mode);
     }
 
+    pub fn limits(&mut self, limits:TransactionLimits) {
+        self.program.state.limits = limits;
+    }
+
+    pub fn pause_limits(&mut self, limits:PauseLimits) {
+        self.pause_limits = limits;
+    }
+
+    // Everything the run loop applies gets written to `path` so it can be replayed later.
+    pub fn record(&mut self, path:&str) -> Result<(), String> {
+        self.recorder = Some(Recorder::new(path)?);
+        Ok(())
+    }
+
     pub fn run(self) -> RunLoop {
         let outgoing = self.program.outgoing.clone();
-        let echo_channel = outgoing.clone();
         let mut program = self.program;
         let paths = self.paths;
         let mut persistence_channel = self.persistence_channel;
         let initial_commits = self.initial_commits;
         let debug_compile = self.debug_modes.contains(&DebugMode::Compile);
+        let debug_profile_json = self.debug_modes.contains(&DebugMode::ProfileJson);
+        if debug_profile_json || self.debug_modes.contains(&DebugMode::Profile) {
+            program.state.profile = Some(Profile::new());
+        }
         let meta_channel = self.meta_channel.map(|c| c.clone());
+        let pause_limits = self.pause_limits;
+        let mut recorder = self.recorder;
 
         let thread = thread::Builder::new().name(program.name.to_owned()).spawn(move || {
             let mut blocks = vec![];
             let mut start_ns = time::precise_time_ns();
+            let schemas = program.watch_schemas();
             for path in paths {
-                blocks.extend(parse_file(&mut program.state.interner, &path, true, debug_compile));
+                blocks.extend(parse_file(&mut program.state.interner, &path, true, debug_compile, &schemas));
             }
             let mut end_ns = time::precise_time_ns();
-            println!("[{}] Compile took {:?}", &program.name, (end_ns - start_ns) as f64 / 1_000_000.0);
+            log_info!(&program.name, "Compile took {:?}", (end_ns - start_ns) as f64 / 1_000_000.0);
 
             start_ns = time::precise_time_ns();
+            if let Some(ref mut recorder) = recorder {
+                let registered = RecordedBlock::from_blocks(&program.state.interner, &program.block_info.blocks);
+                let loaded = RecordedBlock::from_blocks(&program.state.interner, &blocks);
+                recorder.record(RecordedMessage::Load { registered, blocks: loaded, commits: initial_commits.clone(), limits: program.state.limits });
+            }
             let mut txn = CodeTransaction::new();
             for initial in initial_commits {
                 txn.input_change(initial.to_change(&mut program.state.interner));
             }
             txn.exec(&mut program, blocks, vec![]);
             end_ns = time::precise_time_ns();
-            println!("[{}] Load took {:?}", &program.name, (end_ns - start_ns) as f64 / 1_000_000.0);
+            log_info!(&program.name, "Load took {:?}", (end_ns - start_ns) as f64 / 1_000_000.0);
+            program.report_profile(debug_profile_json);
 
             let mut iter_pool = EstimateIterPool::new();
-            println!("[{}] Starting run loop.", &program.name);
+            log_debug!(&program.name, "Starting run loop");
 
             let mut paused = false;
+            let mut queue = PauseQueue::new(pause_limits);
 
             'outer: loop {
                 match (program.incoming.recv(), paused) {
@@ -3586,127 +4805,52 @@ impl ProgramRunner {
                     },
                     (Ok(RunLoopMessage::Resume), _) => {
                         paused = false;
-                    },
-                    (Ok(RunLoopMessage::Reload(paths)), _) => {
-                        let mut added_blocks:Vec<Block> = vec![];
-                        let mut removed_blocks:Vec<String> = vec![];
-                        for path in paths {
-                            let canonical = path.canonicalize();
-                            let resolved = match canonical {
-                                Ok(resolved) => resolved,
-                                Err(_) => path,
-                            };
-                            let resolved_path = resolved.to_str().unwrap();
-                            println!("Hot-reloading {} ...", resolved_path);
-
-                            let mut parsed_blocks:Vec<Block> = if resolved.exists() {
-                                parse_file(&mut program.state.interner, resolved_path, true, debug_compile)
-                            } else {
-                                vec![]
-                            };
-                            let new_blocks:HashSet<&Block> = parsed_blocks.iter().collect();
-
-                            let mut old_blocks:HashSet<&Block> = HashSet::new();
-                            old_blocks.extend(program.blocks_by_path(resolved_path).iter());
-
-                            let mut added = &new_blocks - &old_blocks;
-                            let mut removed = &old_blocks - &new_blocks;
-
-                            added_blocks.extend(added.drain().map(|block| block.clone()));
-                            removed_blocks.extend(removed.drain().map(|block| block.name.to_owned()));
+                        for message in queue.drain() {
+                            run_queued(&mut program, &mut iter_pool, &mut persistence_channel, &meta_channel, &mut recorder, message, debug_compile, debug_profile_json);
                         }
-
-                        echo_channel.send(RunLoopMessage::CodeTransaction(added_blocks, removed_blocks));
-                    }
-                    (Ok(RunLoopMessage::Transaction(v)), true) => {},
-                    (Ok(RunLoopMessage::Transaction(v)), false) => {
-                        println!("[{}] Txn started", &program.name);
-                        let start_ns = time::precise_time_ns();
-                        let mut txn = Transaction::new(&mut iter_pool);
-                        for cur in v {
-                            // println!("  -> {:?}", cur);
-                            txn.input_change(cur.to_change(&mut program.state.interner));
-                        };
-
-                        if let Some(ref meta_chan) = meta_channel {
-                            let mut meta_message = MetaMessage::Transaction{inputs: vec![], outputs: vec![]};
-                            txn.exec_meta(&mut program, &mut persistence_channel, Some(&mut meta_message));
-                            meta_chan.send(meta_message.collapse());
-                        } else {
-                            txn.exec(&mut program, &mut persistence_channel);
+                    },
+                    (Ok(RunLoopMessage::Step), _) => {
+                        if let Some(message) = queue.pop() {
+                            run_queued(&mut program, &mut iter_pool, &mut persistence_channel, &meta_channel, &mut recorder, message, debug_compile, debug_profile_json);
+                            log_info!(&program.name, "Stepped, {} transactions still queued", queue.len());
                         }
-
-
-                        let end_ns = time::precise_time_ns();
-                        let time = (end_ns - start_ns) as f64;
-                        println!("[{}] Txn took {:?} - {:?} insts ({:?} ns) - {:?} inserts ({:?} ns)", &program.name, time / 1_000_000.0, txn.frame.counters.instructions, (time / (txn.frame.counters.instructions as f64)).floor(), txn.frame.counters.inserts, (time / (txn.frame.counters.inserts as f64)).floor());
+                    },
+                    // Code changes wait along with everything else so they're applied in the order
+                    // they came in.
+                    (Ok(message @ RunLoopMessage::Transaction(_)), true) |
+                    (Ok(message @ RunLoopMessage::RemoteTransaction(_)), true) |
+                    (Ok(message @ RunLoopMessage::CodeTransaction(..)), true) |
+                    (Ok(message @ RunLoopMessage::RemoteCodeTransaction(..)), true) |
+                    (Ok(message @ RunLoopMessage::Reload(_)), true) => {
+                        queue.push(message);
+                    },
+                    (Ok(message @ RunLoopMessage::Transaction(_)), false) |
+                    (Ok(message @ RunLoopMessage::RemoteTransaction(_)), false) |
+                    (Ok(message @ RunLoopMessage::CodeTransaction(..)), false) |
+                    (Ok(message @ RunLoopMessage::RemoteCodeTransaction(..)), false) |
+                    (Ok(message @ RunLoopMessage::Reload(_)), false) => {
+                        run_queued(&mut program, &mut iter_pool, &mut persistence_channel, &meta_channel, &mut recorder, message, debug_compile, debug_profile_json);
                     }
-                    (Ok(RunLoopMessage::RemoteTransaction(v)), true) => {},
-                    (Ok(RunLoopMessage::RemoteTransaction(v)), false) => {
-                        let start_ns = time::precise_time_ns();
-                        println!("[{}] Remote txn started", &program.name);
-                        let mut txn = RemoteTransaction::new(&mut iter_pool);
-                        for cur in v {
-                            txn.input_change(cur.to_change(&mut program.state.interner));
-                        };
-                        txn.exec(&mut program, &mut persistence_channel);
-                        let end_ns = time::precise_time_ns();
-                        let time = (end_ns - start_ns) as f64;
-                        println!("[{}] Txn took {:?} - {:?} insts ({:?} ns) - {:?} inserts ({:?} ns)", &program.name, time / 1_000_000.0, txn.frame.counters.instructions, (time / (txn.frame.counters.instructions as f64)).floor(), txn.frame.counters.inserts, (time / (txn.frame.counters.inserts as f64)).floor());
+                    (Ok(RunLoopMessage::Explain(name, reply)), _) => {
+                        let explanation = program.explain(&name).unwrap_or_else(|| format!("No block named '{}'", name));
+                        reply.send(explanation).ok();
                     }
-                    (Ok(RunLoopMessage::CodeTransaction(adds, removes)), _) => {
-                        let start_ns = time::precise_time_ns();
-                        let mut tx = CodeTransaction::new();
-                        println!("[{}] Code Txn started", &program.name);
-                        if adds.len() > 0 {
-                            println!("  ADDS:");
-                            for block in adds.iter() {
-                                print_block_constraints(&block);
-                            }
-                        }
-                        if removes.len() > 0 {
-                            println!("  REMOVES:");
-                            for block in removes.iter() {
-                                println!("    - {:?}", block);
-                            }
-                        }
-                        tx.exec(&mut program, adds, removes);
-                        let end_ns = time::precise_time_ns();
-                        let time = (end_ns - start_ns) as f64;
-                        println!("[{}] Txn took {:?}", &program.name, time / 1_000_000.0);
+                    (Ok(RunLoopMessage::AttachWatcher(watcher)), _) => {
+                        program.attach(watcher);
                     }
-                    (Ok(RunLoopMessage::RemoteCodeTransaction(adds, removes)), _) => {
-                        let start_ns = time::precise_time_ns();
-                        let mut tx = CodeTransaction::new();
-                        println!("[{}] Remote Code Txn started", &program.name);
-                        let added_blocks:Vec<Block> = adds.iter().map(|b| b.intern(&mut program.state.interner)).collect();
-
-                        if adds.len() > 0 {
-                            println!("  ADDS:");
-                            for block in added_blocks.iter() {
-                                print_block_constraints(&block);
-                            }
-                        }
-                        if removes.len() > 0 {
-                            println!("  REMOVES:");
-                            for block in removes.iter() {
-                                println!("    - {:?}", block);
-                            }
+                    (Ok(RunLoopMessage::DetachWatcher(name)), _) => {
+                        if !program.detach(&name) {
+                            log_warn!(&program.name, "No watcher named '{}' to detach", name);
                         }
-
-                        tx.exec(&mut program, added_blocks, removes);
-                        let end_ns = time::precise_time_ns();
-                        let time = (end_ns - start_ns) as f64;
-                        println!("[{}] Txn took {:?}", &program.name, time / 1_000_000.0);
-
                     }
                     (Err(_), _) => { break; }
                 }
             }
+            program.shutdown();
             if let Some(channel) = persistence_channel {
                 channel.send(PersisterMessage::Stop).unwrap();
             }
-            println!("Closing run loop.");
+            log_debug!(&program.name, "Closing run loop");
         }).unwrap();
 
         RunLoop { thread, outgoing }
@@ -0,0 +1,166 @@
+extern crate serde_json;
+extern crate time;
+
+use ops::{Block, Program, PortableBlock, RawChange, Interner, Transaction, RemoteTransaction, CodeTransaction, EstimateIterPool, TransactionLimits};
+use indexes::RawRemoteChange;
+use compiler::parse_string_named;
+use watchers::WatchSchemas;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+
+//-------------------------------------------------------------------------
+// Recorded messages
+//-------------------------------------------------------------------------
+
+// Blocks are recorded as their source when we have it, so that replaying them compiles the same
+// way. Sub-blocks come back out of compiling their root.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum RecordedBlock {
+    Source { name: String, path: String, code: String },
+    Portable(PortableBlock),
+}
+
+impl RecordedBlock {
+    pub fn from_blocks(interner:&Interner, blocks:&Vec<Block>) -> Vec<RecordedBlock> {
+        blocks.iter().filter_map(|block| {
+            if block.code.len() == 0 {
+                Some(RecordedBlock::Portable(block.to_portable(interner)))
+            } else if block.name.contains("|sub_block|") {
+                None
+            } else {
+                Some(RecordedBlock::Source { name: block.name.to_owned(), path: block.path.to_owned(), code: block.code.to_owned() })
+            }
+        }).collect()
+    }
+
+    pub fn to_blocks(&self, interner:&mut Interner, schemas:&WatchSchemas) -> Vec<Block> {
+        match self {
+            &RecordedBlock::Source { ref name, ref path, ref code } => {
+                let mut names = HashMap::new();
+                names.insert(1, name.to_owned());
+                parse_string_named(interner, code, path, false, &names, schemas)
+            }
+            &RecordedBlock::Portable(ref block) => vec![block.intern(interner)],
+        }
+    }
+}
+
+// The inputs a program applied, in the order it applied them. Blocks registered before the run
+// loop started are kept apart from the ones it loaded, since only the latter get run on load. Anything a watcher would have
+// produced in response shows up as its own transaction, so replaying these is enough to get back
+// to the same state. The limits are kept too, since a transaction that ran away under them has to
+// run away again on replay.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum RecordedMessage {
+    Load {
+        registered: Vec<RecordedBlock>,
+        blocks: Vec<RecordedBlock>,
+        commits: Vec<RawChange>,
+        // Older logs don't have them, so they replay under the defaults.
+        #[serde(default = "TransactionLimits::new")]
+        limits: TransactionLimits,
+    },
+    Transaction(Vec<RawChange>),
+    RemoteTransaction(Vec<RawRemoteChange>),
+    CodeTransaction { added: Vec<RecordedBlock>, removed: Vec<String> },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedEntry {
+    // Nanoseconds since recording started.
+    pub time: u64,
+    pub message: RecordedMessage,
+}
+
+//-------------------------------------------------------------------------
+// Recorder
+//-------------------------------------------------------------------------
+
+// Writes one JSON entry per line and flushes as it goes, so the log survives a crash.
+pub struct Recorder {
+    out: BufWriter<File>,
+    start: u64,
+    failed: bool,
+}
+
+impl Recorder {
+    pub fn new(path:&str) -> Result<Recorder, String> {
+        let file = File::create(path).map_err(|why| format!("Unable to create '{}': {}", path, why))?;
+        Ok(Recorder { out: BufWriter::new(file), start: time::precise_time_ns(), failed: false })
+    }
+
+    pub fn record(&mut self, message:RecordedMessage) {
+        let entry = RecordedEntry { time: time::precise_time_ns() - self.start, message };
+        if let Err(why) = self.write(&entry) {
+            if !self.failed {
+                log_error!("record", "Unable to record message: {}", why);
+            }
+            self.failed = true;
+        }
+    }
+
+    fn write(&mut self, entry:&RecordedEntry) -> Result<(), String> {
+        serde_json::to_writer(&mut self.out, entry).map_err(|why| why.to_string())?;
+        self.out.write_all(b"\n").map_err(|why| why.to_string())?;
+        self.out.flush().map_err(|why| why.to_string())
+    }
+}
+
+//-------------------------------------------------------------------------
+// Replay
+//-------------------------------------------------------------------------
+
+pub fn read_log(path:&str) -> Result<Vec<RecordedEntry>, String> {
+    let file = File::open(path).map_err(|why| format!("Unable to open '{}': {}", path, why))?;
+    let mut entries = vec![];
+    for (ix, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|why| format!("Unable to read '{}': {}", path, why))?;
+        if line.trim().len() == 0 { continue; }
+        let entry = serde_json::from_str(&line).map_err(|why| format!("Malformed entry on line {} of '{}': {}", ix + 1, path, why))?;
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+// Applies a recorded log to the given program, which should be fresh and have no watchers attached
+// since everything they did is already in the log.
+pub fn replay(program:&mut Program, entries:Vec<RecordedEntry>) {
+    let mut iter_pool = EstimateIterPool::new();
+    let schemas = program.watch_schemas();
+    for entry in entries {
+        match entry.message {
+            RecordedMessage::Load { registered, blocks, commits, limits } => {
+                program.state.limits = limits;
+                let registered:Vec<Block> = registered.iter().flat_map(|block| block.to_blocks(&mut program.state.interner, &schemas)).collect();
+                for block in registered {
+                    program.register_block(block);
+                }
+                let blocks = blocks.iter().flat_map(|block| block.to_blocks(&mut program.state.interner, &schemas)).collect();
+                let mut txn = CodeTransaction::new();
+                for commit in commits {
+                    txn.input_change(commit.to_change(&mut program.state.interner));
+                }
+                txn.exec(program, blocks, vec![]);
+            }
+            RecordedMessage::Transaction(changes) => {
+                let mut txn = Transaction::new(&mut iter_pool);
+                for change in changes {
+                    txn.input_change(change.to_change(&mut program.state.interner));
+                }
+                txn.exec(program, &mut None);
+            }
+            RecordedMessage::RemoteTransaction(changes) => {
+                let mut txn = RemoteTransaction::new(&mut iter_pool);
+                for change in changes {
+                    txn.input_change(change.to_change(&mut program.state.interner));
+                }
+                txn.exec(program, &mut None);
+            }
+            RecordedMessage::CodeTransaction { added, removed } => {
+                let blocks = added.iter().flat_map(|block| block.to_blocks(&mut program.state.interner, &schemas)).collect();
+                CodeTransaction::new().exec(program, blocks, removed);
+            }
+        }
+    }
+}
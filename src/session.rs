@@ -0,0 +1,74 @@
+extern crate time;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+//-------------------------------------------------------------------------
+// Sessions
+//-------------------------------------------------------------------------
+
+struct Detached<T> {
+    session: T,
+    owner: String,
+    detached_at: u64,
+}
+
+// Sessions whose connection has gone away, kept for the grace period in case the client comes back.
+pub struct Sessions<T> {
+    detached: Arc<Mutex<HashMap<String, Detached<T>>>>,
+    grace: Duration,
+}
+
+impl<T> Clone for Sessions<T> {
+    fn clone(&self) -> Sessions<T> {
+        Sessions { detached: self.detached.clone(), grace: self.grace }
+    }
+}
+
+impl<T: Send + 'static> Sessions<T> {
+    pub fn new(grace:Duration) -> Sessions<T> {
+        Sessions { detached: Arc::new(Mutex::new(HashMap::new())), grace }
+    }
+
+    // With no grace period there's nothing to come back to.
+    pub fn resumable(&self) -> bool {
+        self.grace > Duration::from_secs(0)
+    }
+
+    pub fn len(&self) -> usize {
+        self.detached.lock().unwrap().len()
+    }
+
+    // Holds on to the session for the grace period and hands it to `expire` if nobody has resumed
+    // it by then.
+    pub fn detach<F: FnOnce(T) + Send + 'static>(&self, id:&str, owner:&str, session:T, expire:F) {
+        let detached_at = time::precise_time_ns();
+        self.detached.lock().unwrap().insert(id.to_owned(), Detached { session, owner: owner.to_owned(), detached_at });
+
+        let grace = self.grace;
+        let detached = self.detached.clone();
+        let id = id.to_owned();
+        thread::Builder::new().name(format!("session {} expiry", id)).spawn(move || {
+            thread::sleep(grace);
+            let expired = {
+                let mut detached = detached.lock().unwrap();
+                // The session may have been resumed and detached again since, in which case that
+                // detach's own timer is the one that counts.
+                let current = detached.get(&id).map(|session| session.detached_at == detached_at).unwrap_or(false);
+                if current { detached.remove(&id) } else { None }
+            };
+            if let Some(expired) = expired {
+                expire(expired.session);
+            }
+        }).unwrap();
+    }
+
+    // A session can only be picked back up by the identity that started it.
+    pub fn resume(&self, id:&str, owner:&str) -> Option<T> {
+        let mut detached = self.detached.lock().unwrap();
+        let matches = detached.get(id).map(|session| session.owner == owner).unwrap_or(false);
+        if matches { detached.remove(id).map(|session| session.session) } else { None }
+    }
+}
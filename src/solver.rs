@@ -1,7 +1,9 @@
+extern crate time;
+
 use ops::*;
 use compiler::{FunctionKind};
 use indexes::{WatchIndex, RemoteChangeField};
-use std::collections::{HashSet};
+use std::collections::{HashMap, HashSet};
 use std::hash::{Hash, Hasher};
 use std::usize;
 use std::iter;
@@ -50,6 +52,7 @@ pub struct Solver {
     outputs: Vec<OutputFunc>,
     get_iters: Vec<Arc<GetIteratorFunc>>,
     accepts: Vec<Arc<AcceptFunc>>,
+    accept_constraints: Vec<usize>,
     get_rounds: Vec<Arc<GetRoundsFunc>>,
     finished_mask: u64,
     moves: Vec<(usize, usize)>,
@@ -90,6 +93,7 @@ impl Clone for Solver {
             input_checks: self.input_checks.clone(),
             get_iters: self.get_iters.iter().cloned().collect(),
             accepts: self.accepts.iter().cloned().collect(),
+            accept_constraints: self.accept_constraints.clone(),
             get_rounds: self.get_rounds.iter().cloned().collect(),
             commits: self.commits.clone(),
             dynamic_commits: self.dynamic_commits.clone(),
@@ -127,6 +131,7 @@ impl Solver {
         let mut input_checks = vec![];
         let mut get_iters = vec![];
         let mut accepts = vec![];
+        let mut accept_constraints = vec![];
         let mut get_rounds = vec![];
         let mut commits = vec![];
         let mut dynamic_commits = vec![];
@@ -150,6 +155,7 @@ impl Solver {
                     // through, which may not be what you really wanted. As a result, we need to
                     // actually create an accept for this scan to make sure he really does pass.
                     accepts.push(make_scan_accept(active_scan.unwrap(), usize::MAX - 1));
+                    accept_constraints.push(constraints.iter().position(|c| c == active_scan.unwrap()).unwrap_or(usize::MAX));
                 }
                 if let Field::Register(ix) = a { moves.push((1, ix)); }
                 if let Field::Register(ix) = v { moves.push((2, ix)); }
@@ -196,11 +202,13 @@ impl Solver {
                 &Constraint::Scan {..} => {
                     get_iters.push(make_scan_get_iterator(constraint, ix));
                     accepts.push(make_scan_accept(constraint, ix));
+                    accept_constraints.push(ix);
                     get_rounds.push(make_scan_get_rounds(constraint));
                 },
                 &Constraint::LookupCommit {..} => {
                     get_iters.push(make_scan_get_iterator(constraint, ix));
                     accepts.push(make_scan_accept(constraint, ix));
+                    accept_constraints.push(ix);
                     get_rounds.push(make_commit_lookup_get_rounds(constraint));
                 },
                 &Constraint::LookupRemote {..} => {
@@ -212,11 +220,13 @@ impl Solver {
                 &Constraint::IntermediateScan {..} => {
                     get_iters.push(make_intermediate_get_iterator(constraint, ix));
                     accepts.push(make_intermediate_accept(constraint, ix));
+                    accept_constraints.push(ix);
                     get_rounds.push(make_intermediate_get_rounds(constraint));
                 }
                 &Constraint::Function {..} => {
                     get_iters.push(make_function_get_iterator(constraint, ix));
                     accepts.push(make_function_accept(constraint, ix));
+                    accept_constraints.push(ix);
                 }
                 &Constraint::MultiFunction {..} => {
                     get_iters.push(make_multi_get_iterator(constraint, ix));
@@ -227,6 +237,7 @@ impl Solver {
                 }
                 &Constraint::Filter {..} => {
                     accepts.push(make_filter_accept(constraint, ix));
+                    accept_constraints.push(ix);
                 }
                 &Constraint::Insert { e,a,v,commit } => {
                     if commit {
@@ -286,10 +297,11 @@ impl Solver {
         // compare.
         let interned_remove = interner.string_id("remove");
 
-        Solver { block, id, moves, input_checks, get_iters, accepts, get_rounds, dynamic_commits, commits, binds, intermediates, intermediate_accepts, outputs, watch_registers, project_fields, aggregates, finished_mask, interned_remove }
+        Solver { block, id, moves, input_checks, get_iters, accepts, accept_constraints, get_rounds, dynamic_commits, commits, binds, intermediates, intermediate_accepts, outputs, watch_registers, project_fields, aggregates, finished_mask, interned_remove }
     }
 
     pub fn run(&self, state:&mut RuntimeState, pool:&mut EstimateIterPool, frame:&mut Frame) {
+        frame.block = self.block;
         if !self.do_move(state, frame) { return; }
         if frame.row.solved_fields != self.finished_mask {
             self.solve_variables(state, pool, frame, 0);
@@ -301,6 +313,7 @@ impl Solver {
     }
 
     pub fn run_intermediate(&self, state:&mut RuntimeState, pool:&mut EstimateIterPool, frame:&mut Frame) {
+        frame.block = self.block;
         if !self.do_intermediate_move(frame) { return }
         for accept in self.accepts.iter() {
             let res = (*accept)(state, frame, usize::MAX);
@@ -316,6 +329,7 @@ impl Solver {
     }
 
     pub fn run_remote(&self, state:&mut RuntimeState, pool:&mut EstimateIterPool, frame:&mut Frame) {
+        frame.block = self.block;
         if !self.do_remote_move(frame) { return }
         for accept in self.accepts.iter() {
             let res = (*accept)(state, frame, usize::MAX);
@@ -429,12 +443,37 @@ impl Solver {
                     return;
                 }
             }
+            if iterator.estimate != usize::MAX {
+                if let Some(ref mut profile) = state.profile {
+                    profile.propose(self.block, iterator.constraint, iterator.estimate);
+                }
+            }
             iterator.constraint
         };
+        // The bookkeeping is only worth paying for when someone's looking at it.
+        let profiling = state.profile.is_some();
         'main: while { pool.get(ix).iter.next(&mut frame.row, ix) } {
-            for accept in self.accepts.iter() {
-                if !(*accept)(state, frame, active_constraint) {
-                    continue 'main;
+            if profiling {
+                frame.counters.instructions += 1;
+                frame.counters.iter_next += 1;
+                for (accept_ix, accept) in self.accepts.iter().enumerate() {
+                    let start_ns = time::precise_time_ns();
+                    let accepted = (*accept)(state, frame, active_constraint);
+                    let accept_ns = time::precise_time_ns() - start_ns;
+                    frame.counters.accept += 1;
+                    if let Some(ref mut profile) = state.profile {
+                        profile.accept(self.block, self.accept_constraints[accept_ix], accepted, accept_ns);
+                    }
+                    if !accepted {
+                        frame.counters.accept_bail += 1;
+                        continue 'main;
+                    }
+                }
+            } else {
+                for accept in self.accepts.iter() {
+                    if !(*accept)(state, frame, active_constraint) {
+                        continue 'main;
+                    }
                 }
             }
             frame.row.put_solved(ix);
@@ -463,6 +502,62 @@ impl Solver {
         }
     }
 
+    pub fn explain(&self, interner:&Interner, names:&HashMap<usize, String>, constraints:&Vec<Constraint>) -> String {
+        let f = |field:&Field| explain_field(interner, names, field);
+        let fs = |fields:&Vec<Field>| explain_fields(interner, names, fields);
+        let describe = |ix:usize| {
+            match constraints.get(ix) {
+                Some(constraint) => format!("[{}] {}", ix, constraint.explain(interner, names)),
+                None => "the input".to_string(),
+            }
+        };
+        let mut out = String::new();
+        if self.moves.len() > 0 {
+            let moves:Vec<String> = self.moves.iter().map(|&(from, to)| format!("input[{}] -> {}", from, f(&Field::Register(to)))).collect();
+            out.push_str(&format!("      moves: {}\n", moves.join(", ")));
+        }
+        for &(from, value) in self.intermediate_accepts.iter() {
+            out.push_str(&format!("      input check: input[{}] = {}\n", from, f(&Field::Value(value))));
+        }
+        for &(check, value) in self.input_checks.iter() {
+            let field = match check {
+                InputField::Transaction => "transaction",
+                InputField::Round => "round",
+                InputField::Type => "type",
+                InputField::Count => "count",
+            };
+            out.push_str(&format!("      input check: {} = {}\n", field, value));
+        }
+        out.push_str(&format!("      proposers: {}\n", self.get_iters.len()));
+        for ix in self.accept_constraints.iter() {
+            out.push_str(&format!("      accept: {}\n", describe(*ix)));
+        }
+        for &(e, a, v) in self.binds.iter() {
+            out.push_str(&format!("      output bind: ( {}, {}, {} )\n", f(&e), f(&a), f(&v)));
+        }
+        for &(e, a, v, change_type) in self.commits.iter() {
+            let kind = if change_type == ChangeType::Remove { "remove" } else { "commit" };
+            out.push_str(&format!("      output {}: ( {}, {}, {} )\n", kind, f(&e), f(&a), f(&v)));
+        }
+        for &(e, a, v, _type) in self.dynamic_commits.iter() {
+            out.push_str(&format!("      output dynamic commit: ( {}, {}, {}, type: {} )\n", f(&e), f(&a), f(&v), f(&_type)));
+        }
+        for &(ref key, ref value, negate) in self.intermediates.iter() {
+            out.push_str(&format!("      output intermediate: {} -> {}{}\n", fs(key), fs(value), if negate { " (negated)" } else { "" }));
+        }
+        for &(ref group, ref projection, ref params, ref output_key, _, _, kind) in self.aggregates.iter() {
+            out.push_str(&format!("      output aggregate {:?}: {} per: {} for: {} with: {}\n", kind, fs(output_key), fs(group), fs(projection), fs(params)));
+        }
+        for &(ref name, ref registers) in self.watch_registers.iter() {
+            out.push_str(&format!("      output watch {}: {}\n", name, fs(registers)));
+        }
+        if self.project_fields.len() > 0 {
+            let fields = self.project_fields.iter().map(|reg| Field::Register(*reg)).collect();
+            out.push_str(&format!("      output project: {}\n", fs(&fields)));
+        }
+        out
+    }
+
 
 
 }
@@ -869,7 +964,7 @@ pub fn do_commit(me: &Solver, state: &mut RuntimeState, frame: &mut Frame) {
             let correct_count = if change_type == ChangeType::Remove { count * -1 } else { count };
             let output = Change { e: frame.resolve(&e), a: frame.resolve(&a), v:frame.resolve(&v), n, round:0, transaction: 0, count:correct_count };
             frame.counters.inserts += 1;
-            state.rounds.commit(output, change_type)
+            state.rounds.commit(me.block, output, change_type)
         }
     }
 }
@@ -881,7 +976,7 @@ pub fn do_dynamic_commit(me: &Solver, state: &mut RuntimeState, frame: &mut Fram
             let (correct_count, change_type) = if frame.resolve(&_type) == me.interned_remove { (count * -1, ChangeType::Remove) } else { (count, ChangeType::Insert) };
             let output = Change { e: frame.resolve(&e), a: frame.resolve(&a), v:frame.resolve(&v), n, round:0, transaction: 0, count:correct_count };
             frame.counters.inserts += 1;
-            state.rounds.commit(output, change_type)
+            state.rounds.commit(me.block, output, change_type)
         }
     }
 }
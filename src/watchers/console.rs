@@ -1,6 +1,9 @@
 use super::super::indexes::{WatchDiff};
-use super::super::ops::{Internable, Interner};
-use super::Watcher;
+use super::super::ops::{Internable, Interner, Interned};
+use super::super::logging::{self, Level};
+use super::{Watcher, WatchSchema, WatchRow, WatchType};
+use std::collections::HashMap;
+use std::io::{self, Write};
 
 extern crate term_painter;
 use self::term_painter::ToStyle;
@@ -28,6 +31,13 @@ impl Watcher for ConsoleWatcher {
     fn set_name(&mut self, name: &str) {
         self.name = name.to_string();
     }
+    fn schema(&self) -> Option<WatchSchema> {
+        Some(WatchSchema::new(vec![
+            WatchRow::new("log", vec![("text", WatchType::Any)]),
+            WatchRow::new("warn", vec![("text", WatchType::Any)]),
+            WatchRow::new("error", vec![("text", WatchType::Any)]),
+        ]))
+    }
     fn on_diff(&mut self, interner:&mut Interner, diff:WatchDiff) {
         for add in diff.adds {
             let kind = Internable::to_string(interner.get_value(add[0]));
@@ -35,13 +45,66 @@ impl Watcher for ConsoleWatcher {
             match (&kind[..], text) {
                 ("log", text) => println!("{}", text),
                 ("warn", text) => println!("{} {}", BrightYellow.paint("Warn:"), text),
-                ("error", text) => println!("{} {}", BrightRed.paint("Error:"), text),
+                ("error", text) => { writeln!(io::stderr(), "{} {}", BrightRed.paint("Error:"), text).ok(); }
                 _ => {},
             }
         }
     }
 }
 
+//-------------------------------------------------------------------------
+// Log Watcher
+//-------------------------------------------------------------------------
+
+pub struct LogWatcher {
+    name: String,
+    program: String,
+}
+
+impl LogWatcher {
+    pub fn new(program: &str) -> LogWatcher {
+        LogWatcher{name: "log".to_string(), program: program.to_string()}
+    }
+}
+
+impl Watcher for LogWatcher {
+    fn get_name(& self) -> String {
+        self.name.clone()
+    }
+    fn set_name(&mut self, name: &str) {
+        self.name = name.to_string();
+    }
+    fn schema(&self) -> Option<WatchSchema> {
+        Some(WatchSchema::new(vec![
+            WatchRow::new("entry", vec![("entry", WatchType::Any), ("level", WatchType::String), ("message", WatchType::Any)]),
+            WatchRow::new("field", vec![("entry", WatchType::Any), ("name", WatchType::String), ("value", WatchType::Any)]),
+        ]))
+    }
+    fn on_diff(&mut self, interner:&mut Interner, diff:WatchDiff) {
+        // Fields have to be gathered before the entries they belong to are written out.
+        let mut fields:HashMap<Interned, Vec<(String, String)>> = HashMap::new();
+        let mut entries = vec![];
+        for add in diff.adds {
+            if let &Internable::String(ref kind) = interner.get_value(add[0]) {
+                match (kind.as_ref(), &add[1..]) {
+                    ("entry", &[entry, level, message]) => entries.push((entry, level, message)),
+                    ("field", &[entry, name, value]) => {
+                        let field = (Internable::to_string(interner.get_value(name)), Internable::to_string(interner.get_value(value)));
+                        fields.entry(entry).or_insert_with(|| vec![]).push(field);
+                    }
+                    _ => {}
+                }
+            }
+        }
+        for (entry, level, message) in entries {
+            let level = Level::from_name(&Internable::to_string(interner.get_value(level))).unwrap_or(Level::Info);
+            let mut fields = fields.remove(&entry).unwrap_or_else(|| vec![]);
+            fields.sort();
+            logging::log(level, &self.program, &Internable::to_string(interner.get_value(message)), &fields);
+        }
+    }
+}
+
 //-------------------------------------------------------------------------
 // Print Diff Watcher
 //-------------------------------------------------------------------------
@@ -173,7 +173,7 @@ impl EditorWatcher {
                     },
                     Ok(msg) => panic!("Unknown meta message: {:?}", msg),
                     Err(_) => {
-                        println!("Closing meta channel.");
+                        log_debug!("editor", "Closing meta channel");
                         break;
                     }
                 }
@@ -231,6 +231,7 @@ impl Watcher for EditorHostWatcher {
                     ("pause", &[]) => {
                         self.client_out.send(RunLoopMessage::Resume);
                     }
+                    ("step", &[]) => {}
                     _ => unimplemented!()
                 }
             }
@@ -241,6 +242,9 @@ impl Watcher for EditorHostWatcher {
                     ("pause", &[]) => {
                         self.client_out.send(RunLoopMessage::Pause);
                     }
+                    ("step", &[]) => {
+                        self.client_out.send(RunLoopMessage::Step);
+                    }
                     _ => unimplemented!()
                 }
             }
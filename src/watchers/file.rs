@@ -1,30 +1,479 @@
+extern crate csv;
+extern crate notify;
+extern crate time;
+
+use self::notify::{RecommendedWatcher, RecursiveMode, DebouncedEvent};
+use self::notify::Watcher as NotifyWatcher;
 use super::super::indexes::{WatchDiff};
-use super::super::ops::{Internable, Interner, RawChange, RunLoopMessage};
-use std::sync::mpsc::{Sender};
-use std::fs::File;
-use std::io::Error;
+use super::super::ops::{Interned, Internable, Interner, RawChange, RunLoopMessage, s, eve_parse_value};
+use std::sync::mpsc::{self, Sender, Receiver, TryRecvError, RecvTimeoutError};
+use std::collections::HashMap;
+use std::mem;
+use std::str;
+use std::fs::{self, File, FileType, Metadata, OpenOptions};
 use std::io::prelude::*;
+use std::io::SeekFrom;
 use std::path::Path;
-use super::Watcher;
+use std::thread;
+use std::time::{Duration, UNIX_EPOCH};
+use super::{Watcher, WatchSchema, WatchRow, WatchType};
 
 pub struct FileWatcher {
     name: String,
     outgoing: Sender<RunLoopMessage>,
+    watches: HashMap<Interned, Sender<()>>,
+    tables: HashMap<Interned, CsvTable>,
 }
 
 impl FileWatcher {
     pub fn new(outgoing: Sender<RunLoopMessage>) -> FileWatcher {
-        FileWatcher { name: "file".to_string(), outgoing }
+        FileWatcher { name: "file".to_string(), outgoing, watches: HashMap::new(), tables: HashMap::new() }
+    }
+
+    fn table(&mut self, file: Interned) -> &mut CsvTable {
+        self.tables.entry(file).or_insert_with(|| CsvTable { columns: vec![], rows: HashMap::new() })
     }
 }
 
-fn file_error(changes: &mut Vec<RawChange>, id: String, why: Error) {
+fn file_error<E: ToString>(changes: &mut Vec<RawChange>, id: String, why: E) {
     let err_id = Internable::String(format!("file/error/{}", id));
     changes.push(RawChange {e: err_id.clone(), a: Internable::String("tag".to_string()), v: Internable::String("file/error".to_string()), n: Internable::String("file/error".to_string()), count: 1});
     changes.push(RawChange {e: err_id.clone(), a: Internable::String("message".to_string()), v: Internable::String(why.to_string()), n: Internable::String("file/error".to_string()), count: 1});
     changes.push(RawChange {e: err_id.clone(), a: Internable::String("file".to_string()), v: Internable::String(id.to_string()), n: Internable::String("file/error".to_string()), count: 1});
 }
 
+fn file_kind(file_type: FileType) -> &'static str {
+    if file_type.is_dir() { "directory" }
+    else if file_type.is_symlink() { "symlink" }
+    else { "file" }
+}
+
+// Modification times are reported as RFC 3339 strings, since seconds since the epoch don't fit in
+// an Eve number without losing minutes of precision.
+fn modified(metadata: &Metadata) -> Option<String> {
+    let since_epoch = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some(format!("{}", time::at_utc(time::Timespec::new(since_epoch.as_secs() as i64, 0)).rfc3339()))
+}
+
+// Sizes, byte offsets and positions in a stream can go past 2^24, where an Eve number stops being
+// exact, so they're given as decimal strings too.
+fn exact(value: u64) -> Internable {
+    s(&value.to_string())
+}
+
+fn metadata_changes(changes: &mut Vec<RawChange>, id: &Internable, n: &str, file_type: FileType, metadata: &Metadata) {
+    changes.push(RawChange {e: id.clone(), a: s("kind"), v: s(file_kind(file_type)), n: s(n), count: 1});
+    changes.push(RawChange {e: id.clone(), a: s("size"), v: exact(metadata.len()), n: s(n), count: 1});
+    if let Some(modified) = modified(metadata) {
+        changes.push(RawChange {e: id.clone(), a: s("modified"), v: s(&modified), n: s(n), count: 1});
+    }
+}
+
+fn list_directory(changes: &mut Vec<RawChange>, record_id: String, path: &Path) {
+    let entries = match fs::read_dir(path) {
+        Err(why) => return file_error(changes, record_id, why),
+        Ok(entries) => entries,
+    };
+    let mut count = 0;
+    for entry in entries {
+        let (entry, file_type, metadata) = match entry.and_then(|entry| Ok((entry.file_type()?, entry.metadata()?, entry))) {
+            Err(why) => return file_error(changes, record_id, why),
+            Ok((file_type, metadata, entry)) => (entry, file_type, metadata),
+        };
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let id = s(&format!("file/list/{}/{}", record_id, name));
+        changes.push(RawChange {e: id.clone(), a: s("tag"), v: s("file/list/entry"), n: s("file/list"), count: 1});
+        changes.push(RawChange {e: id.clone(), a: s("file"), v: s(&record_id), n: s("file/list"), count: 1});
+        changes.push(RawChange {e: id.clone(), a: s("name"), v: s(&name), n: s("file/list"), count: 1});
+        changes.push(RawChange {e: id.clone(), a: s("path"), v: s(&entry.path().to_string_lossy()), n: s("file/list"), count: 1});
+        metadata_changes(changes, &id, "file/list", file_type, &metadata);
+        count += 1;
+    }
+    let id = s(&format!("file/list/change/{}", record_id));
+    changes.push(RawChange {e: id.clone(), a: s("tag"), v: s("file/list/change"), n: s("file/list"), count: 1});
+    changes.push(RawChange {e: id.clone(), a: s("file"), v: s(&record_id), n: s("file/list"), count: 1});
+    changes.push(RawChange {e: id.clone(), a: s("count"), v: Internable::from_number(count as f32), n: s("file/list"), count: 1});
+}
+
+// Each watch gets its own thread and notifier. Dropping `stop` shuts both down.
+fn watch_path(outgoing: Sender<RunLoopMessage>, record_id: String, path: String, stop: Receiver<()>) {
+    thread::Builder::new().name(format!("file/watch {}", path)).spawn(move || {
+        let (events_out, events) = mpsc::channel();
+        let watcher:Result<RecommendedWatcher, _> = NotifyWatcher::new(events_out, Duration::from_millis(250));
+        let mut watcher = match watcher.and_then(|mut watcher| watcher.watch(&path, RecursiveMode::Recursive).map(|_| watcher)) {
+            Ok(watcher) => watcher,
+            Err(why) => {
+                let mut changes = vec![];
+                file_error(&mut changes, record_id, why);
+                outgoing.send(RunLoopMessage::Transaction(changes)).ok();
+                return;
+            }
+        };
+        let mut ix = 0;
+        loop {
+            match stop.try_recv() {
+                Err(TryRecvError::Empty) => (),
+                _ => break,
+            }
+            let event = match events.recv_timeout(Duration::from_millis(250)) {
+                Ok(event) => event,
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => break,
+            };
+            let mut changes = vec![];
+            let (kind, changed, from) = match event {
+                DebouncedEvent::Create(changed) => ("create", changed, None),
+                DebouncedEvent::Write(changed) => ("write", changed, None),
+                DebouncedEvent::Chmod(changed) => ("chmod", changed, None),
+                DebouncedEvent::Remove(changed) => ("remove", changed, None),
+                DebouncedEvent::Rename(from, changed) => ("rename", changed, Some(from)),
+                DebouncedEvent::Error(why, _) => {
+                    file_error(&mut changes, record_id.to_owned(), why);
+                    if outgoing.send(RunLoopMessage::Transaction(changes)).is_err() { break; }
+                    continue;
+                }
+                _ => continue,
+            };
+            ix += 1;
+            let id = s(&format!("file/watch/change/{}/{}", record_id, ix));
+            changes.push(RawChange {e: id.clone(), a: s("tag"), v: s("file/watch/change"), n: s("file/watch"), count: 1});
+            changes.push(RawChange {e: id.clone(), a: s("file"), v: s(&record_id), n: s("file/watch"), count: 1});
+            changes.push(RawChange {e: id.clone(), a: s("kind"), v: s(kind), n: s("file/watch"), count: 1});
+            changes.push(RawChange {e: id.clone(), a: s("path"), v: s(&changed.to_string_lossy()), n: s("file/watch"), count: 1});
+            changes.push(RawChange {e: id.clone(), a: s("ix"), v: Internable::from_number(ix as f32), n: s("file/watch"), count: 1});
+            if let Some(from) = from {
+                changes.push(RawChange {e: id.clone(), a: s("from"), v: s(&from.to_string_lossy()), n: s("file/watch"), count: 1});
+            }
+            if outgoing.send(RunLoopMessage::Transaction(changes)).is_err() { break; }
+        }
+        watcher.unwatch(&path).ok();
+    }).unwrap();
+}
+
+//-------------------------------------------------------------------------
+// Streaming reads
+//-------------------------------------------------------------------------
+
+// Lines and chunks go out in transactions of at most this many records so that a big file doesn't
+// turn into one enormous transaction.
+const STREAM_BATCH:usize = 1000;
+
+fn is_utf8(encoding: &str) -> bool {
+    encoding == "utf-8" || encoding == "utf-8-lossy"
+}
+
+fn check_encoding(encoding: &str) -> Result<(), String> {
+    match encoding {
+        "utf-8" | "utf-8-lossy" | "latin1" => Ok(()),
+        _ => Err(format!("Unknown encoding '{}', expected 'utf-8', 'utf-8-lossy' or 'latin1'", encoding)),
+    }
+}
+
+fn decode(bytes: &[u8], encoding: &str) -> Result<String, String> {
+    match encoding {
+        "utf-8-lossy" => Ok(String::from_utf8_lossy(bytes).into_owned()),
+        "latin1" => Ok(bytes.iter().map(|&byte| byte as char).collect()),
+        _ => str::from_utf8(bytes).map(|text| text.to_owned()).map_err(|why| why.to_string()),
+    }
+}
+
+fn send_batch(outgoing: &Sender<RunLoopMessage>, changes: &mut Vec<RawChange>) -> bool {
+    if changes.len() == 0 { return true; }
+    outgoing.send(RunLoopMessage::Transaction(mem::replace(changes, vec![]))).is_ok()
+}
+
+fn stream_error<E: ToString>(outgoing: &Sender<RunLoopMessage>, record_id: String, why: E) {
+    let mut changes = vec![];
+    file_error(&mut changes, record_id, why);
+    send_batch(outgoing, &mut changes);
+}
+
+fn stream_done(changes: &mut Vec<RawChange>, record_id: &str, count: usize) {
+    let id = s(&format!("file/read/done/{}", record_id));
+    changes.push(RawChange {e: id.clone(), a: s("tag"), v: s("file/read/done"), n: s("file/read"), count: 1});
+    changes.push(RawChange {e: id.clone(), a: s("file"), v: s(record_id), n: s("file/read"), count: 1});
+    changes.push(RawChange {e: id.clone(), a: s("count"), v: exact(count as u64), n: s("file/read"), count: 1});
+}
+
+// Splits incoming bytes into `#file/line`s, holding on to a trailing partial line until the rest
+// of it shows up.
+struct Lines {
+    record_id: String,
+    encoding: String,
+    pending: Vec<u8>,
+    offset: u64,
+    ix: usize,
+}
+
+impl Lines {
+    fn new(record_id: String, encoding: String, offset: u64) -> Lines {
+        Lines { record_id, encoding, pending: vec![], offset, ix: 0 }
+    }
+
+    fn push(&mut self, changes: &mut Vec<RawChange>, bytes: &[u8]) -> Result<(), String> {
+        let mut pending = mem::replace(&mut self.pending, vec![]);
+        pending.extend_from_slice(bytes);
+        let mut start = 0;
+        while let Some(len) = pending[start..].iter().position(|&byte| byte == b'\n') {
+            self.line(changes, &pending[start..start + len + 1])?;
+            start += len + 1;
+        }
+        self.pending = pending.split_off(start);
+        Ok(())
+    }
+
+    fn finish(&mut self, changes: &mut Vec<RawChange>) -> Result<(), String> {
+        let pending = mem::replace(&mut self.pending, vec![]);
+        if pending.len() > 0 {
+            self.line(changes, &pending)?;
+        }
+        Ok(())
+    }
+
+    fn line(&mut self, changes: &mut Vec<RawChange>, raw: &[u8]) -> Result<(), String> {
+        let offset = self.offset;
+        self.offset += raw.len() as u64;
+        self.ix += 1;
+        let mut end = raw.len();
+        if end > 0 && raw[end - 1] == b'\n' { end -= 1; }
+        if end > 0 && raw[end - 1] == b'\r' { end -= 1; }
+        let text = decode(&raw[..end], &self.encoding).map_err(|why| format!("Line {} at byte {}: {}", self.ix, offset, why))?;
+        let id = s(&format!("file/line/{}/{}", self.record_id, self.ix));
+        changes.push(RawChange {e: id.clone(), a: s("tag"), v: s("file/line"), n: s("file/read"), count: 1});
+        changes.push(RawChange {e: id.clone(), a: s("file"), v: s(&self.record_id), n: s("file/read"), count: 1});
+        changes.push(RawChange {e: id.clone(), a: s("ix"), v: exact(self.ix as u64), n: s("file/read"), count: 1});
+        changes.push(RawChange {e: id.clone(), a: s("offset"), v: exact(offset), n: s("file/read"), count: 1});
+        changes.push(RawChange {e: id.clone(), a: s("text"), v: s(&text), n: s("file/read"), count: 1});
+        Ok(())
+    }
+}
+
+fn read_lines(outgoing: Sender<RunLoopMessage>, record_id: String, path: String, encoding: String) {
+    thread::Builder::new().name(format!("file/read-lines {}", path)).spawn(move || {
+        let mut file = match File::open(&path) {
+            Ok(file) => file,
+            Err(why) => return stream_error(&outgoing, record_id, why),
+        };
+        let mut changes = vec![];
+        let mut lines = Lines::new(record_id.to_owned(), encoding, 0);
+        let mut buffer = vec![0; 64 * 1024];
+        loop {
+            let read = match file.read(&mut buffer) {
+                Ok(read) => read,
+                Err(why) => return stream_error(&outgoing, record_id, why),
+            };
+            let result = if read == 0 { lines.finish(&mut changes) } else { lines.push(&mut changes, &buffer[..read]) };
+            if let Err(why) = result {
+                send_batch(&outgoing, &mut changes);
+                return stream_error(&outgoing, record_id, why);
+            }
+            if read == 0 { break; }
+            if changes.len() >= STREAM_BATCH * 5 && !send_batch(&outgoing, &mut changes) { return; }
+        }
+        stream_done(&mut changes, &record_id, lines.ix);
+        send_batch(&outgoing, &mut changes);
+    }).unwrap();
+}
+
+// Chunks are `size` bytes, except that a UTF-8 chunk never ends partway through a character.
+fn read_chunks(outgoing: Sender<RunLoopMessage>, record_id: String, path: String, size: usize, encoding: String) {
+    thread::Builder::new().name(format!("file/read-chunks {}", path)).spawn(move || {
+        let mut file = match File::open(&path) {
+            Ok(file) => file,
+            Err(why) => return stream_error(&outgoing, record_id, why),
+        };
+        let mut changes = vec![];
+        let mut carry = vec![];
+        let mut offset = 0;
+        let mut ix = 0;
+        loop {
+            let mut chunk = mem::replace(&mut carry, vec![]);
+            let wanted = size.saturating_sub(chunk.len()).max(1) as u64;
+            let read = match (&mut file).take(wanted).read_to_end(&mut chunk) {
+                Ok(read) => read,
+                Err(why) => return stream_error(&outgoing, record_id, why),
+            };
+            if chunk.len() == 0 { break; }
+            if read > 0 && is_utf8(&encoding) {
+                if let Err(why) = str::from_utf8(&chunk) {
+                    if why.error_len().is_none() {
+                        carry = chunk.split_off(why.valid_up_to());
+                    }
+                }
+            }
+            if chunk.len() == 0 { continue; }
+            let contents = match decode(&chunk, &encoding) {
+                Ok(contents) => contents,
+                Err(why) => {
+                    send_batch(&outgoing, &mut changes);
+                    return stream_error(&outgoing, record_id, format!("Chunk {} at byte {}: {}", ix + 1, offset, why));
+                }
+            };
+            ix += 1;
+            let id = s(&format!("file/chunk/{}/{}", record_id, ix));
+            changes.push(RawChange {e: id.clone(), a: s("tag"), v: s("file/chunk"), n: s("file/read"), count: 1});
+            changes.push(RawChange {e: id.clone(), a: s("file"), v: s(&record_id), n: s("file/read"), count: 1});
+            changes.push(RawChange {e: id.clone(), a: s("ix"), v: exact(ix as u64), n: s("file/read"), count: 1});
+            changes.push(RawChange {e: id.clone(), a: s("offset"), v: exact(offset as u64), n: s("file/read"), count: 1});
+            changes.push(RawChange {e: id.clone(), a: s("contents"), v: s(&contents), n: s("file/read"), count: 1});
+            offset += chunk.len();
+            if changes.len() >= STREAM_BATCH * 5 && !send_batch(&outgoing, &mut changes) { return; }
+        }
+        stream_done(&mut changes, &record_id, ix);
+        send_batch(&outgoing, &mut changes);
+    }).unwrap();
+}
+
+// Follows a file from its current end like `tail -f`. If the file shrinks it's assumed to have
+// been truncated or rotated and is followed again from the start.
+fn follow(outgoing: Sender<RunLoopMessage>, record_id: String, path: String, encoding: String, stop: Receiver<()>) {
+    thread::Builder::new().name(format!("file/follow {}", path)).spawn(move || {
+        let mut position = fs::metadata(&path).map(|metadata| metadata.len()).unwrap_or(0);
+        let mut lines = Lines::new(record_id.to_owned(), encoding, position);
+        loop {
+            match stop.recv_timeout(Duration::from_millis(250)) {
+                Err(RecvTimeoutError::Timeout) => (),
+                _ => break,
+            }
+            let len = match fs::metadata(&path) {
+                Ok(metadata) => metadata.len(),
+                Err(_) => continue,
+            };
+            if len < position {
+                position = 0;
+                lines.pending.clear();
+                lines.offset = 0;
+            }
+            if len == position { continue; }
+
+            let mut bytes = vec![];
+            let read = File::open(&path)
+                .and_then(|mut file| file.seek(SeekFrom::Start(position)).map(|_| file))
+                .and_then(|mut file| file.read_to_end(&mut bytes));
+            let mut changes = vec![];
+            let result = read.map_err(|why| why.to_string()).and_then(|read| {
+                position += read as u64;
+                lines.push(&mut changes, &bytes)
+            });
+            if let Err(why) = result {
+                send_batch(&outgoing, &mut changes);
+                return stream_error(&outgoing, record_id, why);
+            }
+            if !send_batch(&outgoing, &mut changes) { break; }
+        }
+    }).unwrap();
+}
+
+//-------------------------------------------------------------------------
+// CSV
+//-------------------------------------------------------------------------
+
+// The columns and cells of a `#file/csv-write`, gathered up until the write itself comes along.
+struct CsvTable {
+    columns: Vec<(f32, String)>,
+    rows: HashMap<Interned, (f32, HashMap<String, String>)>,
+}
+
+impl CsvTable {
+    // The header in column order, and each row's cells lined up under it. Rows are ordered by their
+    // `ix`, falling back on the row record itself so the output is the same from run to run.
+    fn layout(&self, interner: &Interner) -> (Vec<String>, Vec<Vec<String>>) {
+        let mut columns = self.columns.clone();
+        columns.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        let header:Vec<String> = columns.into_iter().map(|(_, name)| name).collect();
+        let mut rows:Vec<(f32, String, Vec<String>)> = self.rows.iter().filter(|&(_, &(_, ref cells))| cells.len() > 0).map(|(row, &(ix, ref cells))| {
+            let line = header.iter().map(|name| cells.get(name).cloned().unwrap_or_else(|| "".to_string())).collect();
+            (ix, Internable::to_string(interner.get_value(*row)), line)
+        }).collect();
+        rows.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap().then_with(|| a.1.cmp(&b.1)));
+        (header, rows.into_iter().map(|(_, _, line)| line).collect())
+    }
+}
+
+fn csv_delimiter(delimiter: &str) -> Result<u8, String> {
+    match delimiter.as_bytes() {
+        &[byte] => Ok(byte),
+        _ => Err(format!("CSV delimiters have to be a single byte, got '{}'", delimiter)),
+    }
+}
+
+// Attributes every row already has, a column with one of these names comes in as `csv/<name>`.
+const CSV_RESERVED:[&'static str; 3] = ["tag", "file", "ix"];
+
+fn csv_attribute(header: &str) -> String {
+    if CSV_RESERVED.contains(&header) { format!("csv/{}", header) } else { header.to_owned() }
+}
+
+// The first row is the header, and every row after it becomes a `#file/csv/row` with one attribute
+// per column. Cells are typed the same way `eve/parse-value` does it, and empty cells are left out.
+fn read_csv(outgoing: Sender<RunLoopMessage>, record_id: String, path: String, delimiter: u8) {
+    thread::Builder::new().name(format!("file/csv-read {}", path)).spawn(move || {
+        let mut reader = match csv::Reader::from_file(&path) {
+            Ok(reader) => reader.has_headers(true).delimiter(delimiter),
+            Err(why) => return stream_error(&outgoing, record_id, why),
+        };
+        let headers:Vec<String> = match reader.headers() {
+            Ok(headers) => headers.iter().map(|header| csv_attribute(header.trim())).collect(),
+            Err(why) => return stream_error(&outgoing, record_id, why),
+        };
+        let mut changes = vec![];
+        let mut ix = 0;
+        for row in reader.records() {
+            let row = match row {
+                Ok(row) => row,
+                Err(why) => {
+                    send_batch(&outgoing, &mut changes);
+                    return stream_error(&outgoing, record_id, format!("Row {}: {}", ix + 1, why));
+                }
+            };
+            ix += 1;
+            let id = s(&format!("file/csv/row/{}/{}", record_id, ix));
+            changes.push(RawChange {e: id.clone(), a: s("tag"), v: s("file/csv/row"), n: s("file/csv-read"), count: 1});
+            changes.push(RawChange {e: id.clone(), a: s("file"), v: s(&record_id), n: s("file/csv-read"), count: 1});
+            changes.push(RawChange {e: id.clone(), a: s("ix"), v: exact(ix as u64), n: s("file/csv-read"), count: 1});
+            for (header, cell) in headers.iter().zip(row.into_iter()) {
+                if header == "" || cell == "" { continue; }
+                let value = eve_parse_value(vec![&Internable::String(cell)]).unwrap();
+                changes.push(RawChange {e: id.clone(), a: s(header), v: value, n: s("file/csv-read"), count: 1});
+            }
+            if changes.len() >= STREAM_BATCH * 5 && !send_batch(&outgoing, &mut changes) { return; }
+        }
+        stream_done(&mut changes, &record_id, ix);
+        send_batch(&outgoing, &mut changes);
+    }).unwrap();
+}
+
+// The whole table is gathered up in the watcher before the write goes out, since its columns and
+// cells only show up as part of the same transaction, so it has to fit in memory. Only the writing
+// itself happens off of the run loop.
+fn write_csv(outgoing: Sender<RunLoopMessage>, record_id: String, path: String, delimiter: u8, header: Vec<String>, rows: Vec<Vec<String>>) {
+    thread::Builder::new().name(format!("file/csv-write {}", path)).spawn(move || {
+        let mut writer = match csv::Writer::from_file(&path) {
+            Ok(writer) => writer.delimiter(delimiter),
+            Err(why) => return stream_error(&outgoing, record_id, why),
+        };
+        let count = rows.len();
+        if let Err(why) = writer.write(header.iter()) {
+            return stream_error(&outgoing, record_id, why);
+        }
+        for row in rows {
+            if let Err(why) = writer.write(row.iter()) {
+                return stream_error(&outgoing, record_id, why);
+            }
+        }
+        if let Err(why) = writer.flush() {
+            return stream_error(&outgoing, record_id, why);
+        }
+        let id = s(&format!("file/write/done/{}", record_id));
+        let changes = vec![
+            RawChange {e: id.clone(), a: s("tag"), v: s("file/write/done"), n: s("file/csv-write"), count: 1},
+            RawChange {e: id.clone(), a: s("file"), v: s(&record_id), n: s("file/csv-write"), count: 1},
+            RawChange {e: id.clone(), a: s("count"), v: Internable::from_number(count as f32), n: s("file/csv-write"), count: 1},
+        ];
+        outgoing.send(RunLoopMessage::Transaction(changes)).ok();
+    }).unwrap();
+}
+
 impl Watcher for FileWatcher {
     fn get_name(& self) -> String {
         self.name.clone()
@@ -32,8 +481,64 @@ impl Watcher for FileWatcher {
     fn set_name(&mut self, name: &str) {
         self.name = name.to_string();
     }
+    fn schema(&self) -> Option<WatchSchema> {
+        Some(WatchSchema::new(vec![
+            WatchRow::new("read", vec![("file", WatchType::Any), ("path", WatchType::String)]),
+            WatchRow::new("write", vec![("file", WatchType::Any), ("path", WatchType::String), ("contents", WatchType::Any)]),
+            WatchRow::new("append", vec![("file", WatchType::Any), ("path", WatchType::String), ("contents", WatchType::Any)]),
+            WatchRow::new("delete", vec![("file", WatchType::Any), ("path", WatchType::String)]),
+            WatchRow::new("stat", vec![("file", WatchType::Any), ("path", WatchType::String)]),
+            WatchRow::new("list", vec![("file", WatchType::Any), ("path", WatchType::String)]),
+            WatchRow::new("watch", vec![("file", WatchType::Any), ("path", WatchType::String)]),
+            WatchRow::new("read-lines", vec![("file", WatchType::Any), ("path", WatchType::String), ("encoding", WatchType::String)]),
+            WatchRow::new("read-chunks", vec![("file", WatchType::Any), ("path", WatchType::String), ("size", WatchType::Number), ("encoding", WatchType::String)]),
+            WatchRow::new("follow", vec![("file", WatchType::Any), ("path", WatchType::String), ("encoding", WatchType::String)]),
+            WatchRow::new("csv-read", vec![("file", WatchType::Any), ("path", WatchType::String), ("delimiter", WatchType::String)]),
+            WatchRow::new("csv-write", vec![("file", WatchType::Any), ("path", WatchType::String), ("delimiter", WatchType::String)]),
+            WatchRow::new("csv-column", vec![("file", WatchType::Any), ("ix", WatchType::Number), ("name", WatchType::String)]),
+            WatchRow::new("csv-cell", vec![("file", WatchType::Any), ("row", WatchType::Any), ("ix", WatchType::Number), ("column", WatchType::String), ("value", WatchType::Any)]),
+        ]))
+    }
     fn on_diff(&mut self, interner:&mut Interner, diff:WatchDiff) {
+        for remove in diff.removes {
+            let kind = Internable::to_string(interner.get_value(remove[0]));
+            if kind == "watch" || kind == "follow" {
+                self.watches.remove(&remove[1]);
+            } else if kind == "csv-write" {
+                self.tables.remove(&remove[1]);
+            } else if kind == "csv-column" {
+                let column = (Internable::to_number(interner.get_value(remove[2])), Internable::to_string(interner.get_value(remove[3])));
+                if let Some(table) = self.tables.get_mut(&remove[1]) {
+                    table.columns.retain(|existing| *existing != column);
+                }
+            } else if kind == "csv-cell" {
+                let column = Internable::to_string(interner.get_value(remove[4]));
+                if let Some(row) = self.tables.get_mut(&remove[1]).and_then(|table| table.rows.get_mut(&remove[2])) {
+                    row.1.remove(&column);
+                }
+            }
+        }
+
+        // Columns and cells have to be in place before the write they belong to goes out.
+        let mut adds = vec![];
         for add in diff.adds {
+            let kind = Internable::to_string(interner.get_value(add[0]));
+            if kind == "csv-column" {
+                let column = (Internable::to_number(interner.get_value(add[2])), Internable::to_string(interner.get_value(add[3])));
+                self.table(add[1]).columns.push(column);
+            } else if kind == "csv-cell" {
+                let ix = Internable::to_number(interner.get_value(add[3]));
+                let column = Internable::to_string(interner.get_value(add[4]));
+                let value = Internable::to_string(interner.get_value(add[5]));
+                let row = self.table(add[1]).rows.entry(add[2]).or_insert_with(|| (ix, HashMap::new()));
+                row.0 = ix;
+                row.1.insert(column, value);
+            } else {
+                adds.push(add);
+            }
+        }
+
+        for add in adds {
             let kind = Internable::to_string(interner.get_value(add[0]));
             let record_id = Internable::to_string(interner.get_value(add[1]));
             let id = Internable::String(format!("file/{}/change/{}", kind, record_id));
@@ -69,6 +574,80 @@ impl Watcher for FileWatcher {
                         },
                     };
                 },
+                "append" => {
+                    let contents = Internable::to_string(interner.get_value(add[3]));
+                    match OpenOptions::new().append(true).create(true).open(&path) {
+                        Err(why) => file_error(&mut changes, record_id, why),
+                        Ok(ref mut file) => {
+                            if let Err(why) = file.write_all(contents.as_bytes()) {
+                                file_error(&mut changes, record_id, why);
+                            }
+                        },
+                    };
+                },
+                "delete" => {
+                    // Directories are only removed when they're empty.
+                    let deleted = fs::symlink_metadata(&path).and_then(|metadata| {
+                        if metadata.is_dir() { fs::remove_dir(&path) } else { fs::remove_file(&path) }
+                    });
+                    if let Err(why) = deleted {
+                        file_error(&mut changes, record_id, why);
+                    }
+                },
+                "stat" => {
+                    match fs::metadata(&path) {
+                        Err(why) => file_error(&mut changes, record_id, why),
+                        Ok(metadata) => {
+                            changes.push(RawChange {e: id.clone(), a: s("tag"), v: s("file/stat/change"), n: s("file/stat"), count: 1});
+                            changes.push(RawChange {e: id.clone(), a: s("file"), v: s(&record_id), n: s("file/stat"), count: 1});
+                            metadata_changes(&mut changes, &id, "file/stat", metadata.file_type(), &metadata);
+                        },
+                    };
+                },
+                "list" => list_directory(&mut changes, record_id, &path),
+                "watch" => {
+                    let (stop, stopped) = mpsc::channel();
+                    self.watches.insert(add[1], stop);
+                    watch_path(self.outgoing.clone(), record_id, raw_path.to_owned(), stopped);
+                },
+                "read-lines" | "follow" => {
+                    let encoding = Internable::to_string(interner.get_value(add[3]));
+                    if let Err(why) = check_encoding(&encoding) {
+                        file_error(&mut changes, record_id, why);
+                    } else if kind == "follow" {
+                        let (stop, stopped) = mpsc::channel();
+                        self.watches.insert(add[1], stop);
+                        follow(self.outgoing.clone(), record_id, raw_path.to_owned(), encoding, stopped);
+                    } else {
+                        read_lines(self.outgoing.clone(), record_id, raw_path.to_owned(), encoding);
+                    }
+                },
+                "read-chunks" => {
+                    let size = Internable::to_number(interner.get_value(add[3]));
+                    let encoding = Internable::to_string(interner.get_value(add[4]));
+                    if let Err(why) = check_encoding(&encoding) {
+                        file_error(&mut changes, record_id, why);
+                    } else if size < 1.0 {
+                        file_error(&mut changes, record_id, format!("Chunk size must be at least 1 byte, got {}", size));
+                    } else {
+                        read_chunks(self.outgoing.clone(), record_id, raw_path.to_owned(), size as usize, encoding);
+                    }
+                },
+                "csv-read" => {
+                    match csv_delimiter(&Internable::to_string(interner.get_value(add[3]))) {
+                        Err(why) => file_error(&mut changes, record_id, why),
+                        Ok(delimiter) => read_csv(self.outgoing.clone(), record_id, raw_path.to_owned(), delimiter),
+                    }
+                },
+                "csv-write" => {
+                    match csv_delimiter(&Internable::to_string(interner.get_value(add[3]))) {
+                        Err(why) => file_error(&mut changes, record_id, why),
+                        Ok(delimiter) => {
+                            let (header, rows) = self.table(add[1]).layout(interner);
+                            write_csv(self.outgoing.clone(), record_id, raw_path.to_owned(), delimiter, header, rows);
+                        }
+                    }
+                },
                 _ => {},
             }
             match self.outgoing.send(RunLoopMessage::Transaction(changes)) {
@@ -77,4 +656,8 @@ impl Watcher for FileWatcher {
             }
         }
     }
+    fn on_shutdown(&mut self) {
+        self.watches.clear();
+        self.tables.clear();
+    }
 }
@@ -0,0 +1,343 @@
+extern crate hyper;
+
+use self::hyper::Client;
+use self::hyper::method::Method;
+use self::hyper::header::Headers;
+use super::super::indexes::{WatchDiff};
+use super::super::ops::{Interned, Internable, Interner, RawChange, RunLoopMessage, s};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::collections::{HashMap};
+use std::io::prelude::*;
+use std::io;
+use std::thread;
+use std::time::Duration;
+use super::{Watcher, WatchSchema, WatchRow, WatchType};
+
+//-------------------------------------------------------------------------
+// Http Watcher
+//-------------------------------------------------------------------------
+
+pub struct HttpWatcher {
+    name: String,
+    outgoing: Sender<RunLoopMessage>,
+    headers: HashMap<Interned, Vec<(String, String)>>,
+    timeout: Duration,
+}
+
+// How long a request can go without reading or writing anything before it's reported as an error.
+const REQUEST_TIMEOUT:u64 = 30;
+
+impl HttpWatcher {
+    pub fn new(outgoing: Sender<RunLoopMessage>) -> HttpWatcher {
+        HttpWatcher::with_timeout(outgoing, Duration::from_secs(REQUEST_TIMEOUT))
+    }
+
+    pub fn with_timeout(outgoing: Sender<RunLoopMessage>, timeout: Duration) -> HttpWatcher {
+        HttpWatcher { name: "http".to_string(), outgoing, headers: HashMap::new(), timeout }
+    }
+}
+
+fn http_error(changes: &mut Vec<RawChange>, id: &str, request: &Internable, why: String) {
+    let err_id = s(&format!("http/error/{}", id));
+    changes.push(RawChange {e: err_id.clone(), a: s("tag"), v: s("http/error"), n: s("http/error"), count: 1});
+    changes.push(RawChange {e: err_id.clone(), a: s("message"), v: s(&why), n: s("http/error"), count: 1});
+    changes.push(RawChange {e: err_id.clone(), a: s("request"), v: request.clone(), n: s("http/error"), count: 1});
+}
+
+fn io_error_message(why: &io::Error) -> String {
+    match why.kind() {
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => "request timed out".to_string(),
+        _ => why.to_string(),
+    }
+}
+
+fn send_request(id: &str, request: &Internable, method: &str, url: &str, headers: &Vec<(String, String)>, body: &str, timeout: Duration) -> Vec<RawChange> {
+    let mut changes = vec![];
+    let method = match method.to_uppercase().parse::<Method>() {
+        Ok(method) => method,
+        Err(why) => {
+            http_error(&mut changes, id, request, why.to_string());
+            return changes;
+        }
+    };
+    let mut raw_headers = Headers::new();
+    for &(ref name, ref value) in headers.iter() {
+        raw_headers.append_raw(name.to_string(), value.as_bytes().to_vec());
+    }
+
+    let mut client = Client::new();
+    client.set_read_timeout(Some(timeout));
+    client.set_write_timeout(Some(timeout));
+    let mut request_builder = client.request(method.clone(), url).headers(raw_headers);
+    if method != Method::Get && method != Method::Head {
+        request_builder = request_builder.body(body);
+    }
+    match request_builder.send() {
+        Err(hyper::Error::Io(why)) => http_error(&mut changes, id, request, io_error_message(&why)),
+        Err(why) => http_error(&mut changes, id, request, why.to_string()),
+        Ok(mut response) => {
+            let mut contents = String::new();
+            if let Err(why) = response.read_to_string(&mut contents) {
+                http_error(&mut changes, id, request, io_error_message(&why));
+                return changes;
+            }
+            let response_id = format!("http/response/{}", id);
+            let e = s(&response_id);
+            changes.push(RawChange {e: e.clone(), a: s("tag"), v: s("http/response"), n: s("http/response"), count: 1});
+            changes.push(RawChange {e: e.clone(), a: s("request"), v: request.clone(), n: s("http/response"), count: 1});
+            changes.push(RawChange {e: e.clone(), a: s("status"), v: Internable::from_number(response.status.to_u16() as f32), n: s("http/response"), count: 1});
+            changes.push(RawChange {e: e.clone(), a: s("body"), v: s(&contents), n: s("http/response"), count: 1});
+            for header in response.headers.iter() {
+                let header_id = s(&format!("{}|header|{}", response_id, header.name()));
+                changes.push(RawChange {e: e.clone(), a: s("header"), v: header_id.clone(), n: s("http/response"), count: 1});
+                changes.push(RawChange {e: header_id.clone(), a: s("name"), v: s(header.name()), n: s("http/response"), count: 1});
+                changes.push(RawChange {e: header_id.clone(), a: s("value"), v: s(&header.value_string()), n: s("http/response"), count: 1});
+            }
+        }
+    }
+    changes
+}
+
+impl Watcher for HttpWatcher {
+    fn get_name(& self) -> String {
+        self.name.clone()
+    }
+    fn set_name(&mut self, name: &str) {
+        self.name = name.to_string();
+    }
+    fn schema(&self) -> Option<WatchSchema> {
+        Some(WatchSchema::new(vec![
+            WatchRow::new("request", vec![("request", WatchType::Any), ("method", WatchType::String), ("url", WatchType::String), ("body", WatchType::Any)]),
+            WatchRow::new("header", vec![("request", WatchType::Any), ("name", WatchType::String), ("value", WatchType::Any)]),
+        ]))
+    }
+    fn on_diff(&mut self, interner:&mut Interner, diff:WatchDiff) {
+        for remove in diff.removes {
+            if let &Internable::String(ref kind) = interner.get_value(remove[0]) {
+                match (kind.as_ref(), &remove[1..]) {
+                    ("header", &[request, name, value]) => {
+                        let pair = (Internable::to_string(interner.get_value(name)), Internable::to_string(interner.get_value(value)));
+                        let now_empty = match self.headers.get_mut(&request) {
+                            Some(headers) => {
+                                headers.retain(|header| *header != pair);
+                                headers.len() == 0
+                            }
+                            None => false,
+                        };
+                        if now_empty { self.headers.remove(&request); }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        // Headers have to be in place before we send the request they belong to.
+        let mut requests = vec![];
+        for add in diff.adds {
+            if let &Internable::String(ref kind) = interner.get_value(add[0]) {
+                match (kind.as_ref(), &add[1..]) {
+                    ("header", &[request, name, value]) => {
+                        let pair = (Internable::to_string(interner.get_value(name)), Internable::to_string(interner.get_value(value)));
+                        self.headers.entry(request).or_insert_with(|| vec![]).push(pair);
+                    }
+                    ("request", &[request, method, url, body]) => {
+                        requests.push((request, method, url, body));
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        for (request, method, url, body) in requests {
+            let request_record = interner.get_value(request).clone();
+            let id = Internable::to_string(&request_record);
+            let method = Internable::to_string(interner.get_value(method));
+            let url = Internable::to_string(interner.get_value(url));
+            let body = Internable::to_string(interner.get_value(body));
+            let headers = self.headers.get(&request).cloned().unwrap_or_else(|| vec![]);
+            let outgoing = self.outgoing.clone();
+            let timeout = self.timeout;
+            // Requests can take a while, so they're made off of the run loop's thread.
+            thread::spawn(move || {
+                let changes = send_request(&id, &request_record, &method, &url, &headers, &body, timeout);
+                outgoing.send(RunLoopMessage::Transaction(changes)).ok();
+            });
+        }
+    }
+}
+
+//-------------------------------------------------------------------------
+// Http Server Watcher
+//-------------------------------------------------------------------------
+
+pub struct HttpServerResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+type PendingResponses = Arc<Mutex<HashMap<String, Sender<HttpServerResponse>>>>;
+
+// The server's side of routing requests through Eve. Each request is added to the program as an
+// #http/server/request and we wait for the program to bind an #http/server/response for it.
+#[derive(Clone)]
+pub struct HttpRoutes {
+    outgoing: Arc<Mutex<Sender<RunLoopMessage>>>,
+    pending: PendingResponses,
+    next_id: Arc<AtomicUsize>,
+    timeout: Duration,
+}
+
+impl HttpRoutes {
+    pub fn new(outgoing: Sender<RunLoopMessage>, timeout: Duration) -> HttpRoutes {
+        HttpRoutes { outgoing: Arc::new(Mutex::new(outgoing)), pending: Arc::new(Mutex::new(HashMap::new())), next_id: Arc::new(AtomicUsize::new(0)), timeout }
+    }
+
+    pub fn watcher(&self) -> HttpServerWatcher {
+        let outgoing = self.outgoing.lock().unwrap().clone();
+        HttpServerWatcher { name: "http/server".to_string(), outgoing, pending: self.pending.clone(), headers: HashMap::new() }
+    }
+
+    fn request_changes(id: &str, method: &str, path: &str, query: &Option<String>, headers: &Vec<(String, String)>, body: &str, count: i32) -> Vec<RawChange> {
+        let e = s(id);
+        let mut changes = vec![
+            RawChange {e: e.clone(), a: s("tag"), v: s("http/server/request"), n: s("http/server"), count},
+            RawChange {e: e.clone(), a: s("method"), v: s(method), n: s("http/server"), count},
+            RawChange {e: e.clone(), a: s("path"), v: s(path), n: s("http/server"), count},
+            RawChange {e: e.clone(), a: s("body"), v: s(body), n: s("http/server"), count},
+        ];
+        if let &Some(ref query) = query {
+            changes.push(RawChange {e: e.clone(), a: s("query"), v: s(query), n: s("http/server"), count});
+        }
+        for &(ref name, ref value) in headers.iter() {
+            let header_id = s(&format!("{}|header|{}", id, name));
+            changes.push(RawChange {e: e.clone(), a: s("header"), v: header_id.clone(), n: s("http/server"), count});
+            changes.push(RawChange {e: header_id.clone(), a: s("name"), v: s(name), n: s("http/server"), count});
+            changes.push(RawChange {e: header_id.clone(), a: s("value"), v: s(value), n: s("http/server"), count});
+        }
+        changes
+    }
+
+    // Blocks until the program responds or the timeout passes. Either way the request record is
+    // removed again afterwards.
+    pub fn handle(&self, method: &str, path: &str, query: Option<String>, headers: Vec<(String, String)>, body: String) -> HttpServerResponse {
+        let id = format!("http/server/request|{}", self.next_id.fetch_add(1, Ordering::SeqCst));
+        let (sender, receiver) = mpsc::channel();
+        self.pending.lock().unwrap().insert(id.to_string(), sender);
+
+        let changes = HttpRoutes::request_changes(&id, method, path, &query, &headers, &body, 1);
+        let sent = self.outgoing.lock().unwrap().send(RunLoopMessage::Transaction(changes)).is_ok();
+        let response = if sent { receiver.recv_timeout(self.timeout).ok() } else { None };
+
+        self.pending.lock().unwrap().remove(&id);
+        if sent {
+            let changes = HttpRoutes::request_changes(&id, method, path, &query, &headers, &body, -1);
+            self.outgoing.lock().unwrap().send(RunLoopMessage::Transaction(changes)).ok();
+        }
+        response.unwrap_or_else(|| {
+            HttpServerResponse { status: 504, headers: vec![], body: format!("No response for {} {}", method, path) }
+        })
+    }
+}
+
+pub struct HttpServerWatcher {
+    name: String,
+    outgoing: Sender<RunLoopMessage>,
+    pending: PendingResponses,
+    headers: HashMap<Interned, Vec<(String, String)>>,
+}
+
+fn response_status(status: &Internable) -> Result<u16, String> {
+    match status {
+        &Internable::Number(_) => {
+            let number = Internable::to_number(status);
+            if number >= 100.0 && number <= 599.0 && number.fract() == 0.0 {
+                Ok(number as u16)
+            } else {
+                Err(format!("Invalid response status {}, expected a number from 100 to 599", number))
+            }
+        }
+        other => Err(format!("Invalid response status {}, expected a number from 100 to 599", other.print())),
+    }
+}
+
+fn http_server_error(request: &Internable, why: &str) -> Vec<RawChange> {
+    let err_id = s(&format!("http/server/error/{}", Internable::to_string(request)));
+    vec![
+        RawChange {e: err_id.clone(), a: s("tag"), v: s("http/server/error"), n: s("http/server"), count: 1},
+        RawChange {e: err_id.clone(), a: s("message"), v: s(why), n: s("http/server"), count: 1},
+        RawChange {e: err_id.clone(), a: s("request"), v: request.clone(), n: s("http/server"), count: 1},
+    ]
+}
+
+impl Watcher for HttpServerWatcher {
+    fn get_name(& self) -> String {
+        self.name.clone()
+    }
+    fn set_name(&mut self, name: &str) {
+        self.name = name.to_string();
+    }
+    fn schema(&self) -> Option<WatchSchema> {
+        Some(WatchSchema::new(vec![
+            WatchRow::new("response", vec![("request", WatchType::Any), ("status", WatchType::Number), ("body", WatchType::Any)]),
+            WatchRow::new("header", vec![("request", WatchType::Any), ("name", WatchType::String), ("value", WatchType::Any)]),
+        ]))
+    }
+    fn on_diff(&mut self, interner:&mut Interner, diff:WatchDiff) {
+        for remove in diff.removes {
+            if let &Internable::String(ref kind) = interner.get_value(remove[0]) {
+                match (kind.as_ref(), &remove[1..]) {
+                    ("header", &[request, name, value]) => {
+                        let pair = (Internable::to_string(interner.get_value(name)), Internable::to_string(interner.get_value(value)));
+                        let now_empty = match self.headers.get_mut(&request) {
+                            Some(headers) => {
+                                headers.retain(|header| *header != pair);
+                                headers.len() == 0
+                            }
+                            None => false,
+                        };
+                        if now_empty { self.headers.remove(&request); }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        let mut responses = vec![];
+        for add in diff.adds {
+            if let &Internable::String(ref kind) = interner.get_value(add[0]) {
+                match (kind.as_ref(), &add[1..]) {
+                    ("header", &[request, name, value]) => {
+                        let pair = (Internable::to_string(interner.get_value(name)), Internable::to_string(interner.get_value(value)));
+                        self.headers.entry(request).or_insert_with(|| vec![]).push(pair);
+                    }
+                    ("response", &[request, status, body]) => {
+                        responses.push((request, status, body));
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        for (request, status, body) in responses {
+            let request_record = interner.get_value(request).clone();
+            let id = Internable::to_string(&request_record);
+            // The request may have already timed out, in which case there's no one left to answer.
+            let sender = match self.pending.lock().unwrap().remove(&id) {
+                Some(sender) => sender,
+                None => continue,
+            };
+            let headers = self.headers.remove(&request).unwrap_or_else(|| vec![]);
+            let response = match response_status(interner.get_value(status)) {
+                Ok(status) => HttpServerResponse { status, headers, body: Internable::to_string(interner.get_value(body)) },
+                Err(why) => {
+                    self.outgoing.send(RunLoopMessage::Transaction(http_server_error(&request_record, &why))).ok();
+                    HttpServerResponse { status: 500, headers: vec![], body: why }
+                }
+            };
+            sender.send(response).ok();
+        }
+    }
+}
@@ -1,13 +1,121 @@
 use indexes::{WatchDiff};
-use ops::{Interner};
+use ops::{Internable, Interner, RunLoopMessage};
+use std::collections::HashMap;
+use std::sync::mpsc::Sender;
+
+//-------------------------------------------------------------------------
+// Watch schemas
+//-------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WatchType {
+    Any,
+    String,
+    Number,
+}
+
+impl WatchType {
+    pub fn accepts(&self, value:&Internable) -> bool {
+        match (*self, value) {
+            (WatchType::Any, _) => true,
+            (WatchType::String, &Internable::String(_)) => true,
+            (WatchType::Number, &Internable::Number(_)) => true,
+            _ => false,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            WatchType::Any => "value",
+            WatchType::String => "string",
+            WatchType::Number => "number",
+        }
+    }
+}
+
+// Most watchers tag each row with its kind in the first column, which isn't counted as a field.
+#[derive(Debug, Clone)]
+pub struct WatchRow {
+    pub kind: Option<String>,
+    pub fields: Vec<(String, WatchType)>,
+}
+
+impl WatchRow {
+    pub fn new(kind:&str, fields:Vec<(&str, WatchType)>) -> WatchRow {
+        WatchRow { kind: Some(kind.to_string()), fields: fields.into_iter().map(|(name, kind)| (name.to_string(), kind)).collect() }
+    }
+
+    pub fn untagged(fields:Vec<(&str, WatchType)>) -> WatchRow {
+        WatchRow { kind: None, fields: fields.into_iter().map(|(name, kind)| (name.to_string(), kind)).collect() }
+    }
+
+    pub fn arity(&self) -> usize {
+        self.fields.len() + if self.kind.is_some() { 1 } else { 0 }
+    }
+
+    pub fn describe(&self) -> String {
+        let mut columns:Vec<String> = self.kind.iter().map(|kind| format!("\"{}\"", kind)).collect();
+        columns.extend(self.fields.iter().map(|&(ref name, _)| name.to_owned()));
+        format!("({})", columns.join(", "))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct WatchSchema {
+    pub rows: Vec<WatchRow>,
+}
+
+impl WatchSchema {
+    pub fn new(rows:Vec<WatchRow>) -> WatchSchema {
+        WatchSchema { rows }
+    }
+
+    pub fn is_tagged(&self) -> bool {
+        self.rows.iter().all(|row| row.kind.is_some())
+    }
+
+    pub fn row(&self, kind:&str) -> Option<&WatchRow> {
+        self.rows.iter().find(|row| row.kind.as_ref().map_or(false, |row_kind| row_kind == kind))
+    }
+
+    pub fn kinds(&self) -> Vec<String> {
+        self.rows.iter().filter_map(|row| row.kind.clone()).collect()
+    }
+}
+
+// The schemas of a program's watchers by name, which its blocks are compiled against.
+pub type WatchSchemas = HashMap<String, WatchSchema>;
+
+//-------------------------------------------------------------------------
+// Watcher
+//-------------------------------------------------------------------------
 
 pub trait Watcher {
     fn get_name(& self) -> String;
     fn set_name(&mut self, &str);
     fn on_diff(&mut self, interner:&mut Interner, diff:WatchDiff);
+    // The rows this watcher accepts. Watch sections are checked against it when they're compiled,
+    // watchers without one take whatever they're given.
+    fn schema(&self) -> Option<WatchSchema> {
+        None
+    }
+    // Called when the watcher is attached to a program. `current` holds the rows the program is
+    // already watching, which won't show up as adds again, so by default they're handled like any
+    // other diff.
+    fn on_attach(&mut self, interner:&mut Interner, _outgoing:Sender<RunLoopMessage>, current:WatchDiff) {
+        if current.adds.len() > 0 {
+            self.on_diff(interner, current);
+        }
+    }
+    // Called when the watcher is detached or its program stops. Anything the watcher started, like
+    // threads or open files, should be torn down here.
+    fn on_shutdown(&mut self) {}
 }
 
 pub mod file;
+pub mod http;
+pub mod process;
+pub mod plugin;
 pub mod console;
 pub mod system;
 pub mod compiler;
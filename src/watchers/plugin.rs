@@ -0,0 +1,201 @@
+extern crate libc;
+
+use super::super::indexes::{WatchDiff};
+use super::super::ops::{Interned, Internable, Interner, RawChange, RunLoopMessage, s};
+use super::{Watcher, WatchSchema, WatchRow, WatchType};
+use std::collections::HashMap;
+use std::sync::mpsc::Sender;
+
+//-------------------------------------------------------------------------
+// Plugins
+//-------------------------------------------------------------------------
+
+// A plugin is a shared library that exports a registration function and the version it was built
+// for, through `eve_plugin!`. Watchers are handed over as trait objects, so the plugin has to be
+// built against the same version of this crate with the same compiler as the binary loading it,
+// and libraries that say otherwise are refused.
+pub const PLUGIN_REGISTER_SYMBOL: &'static str = "eve_plugin_register";
+pub const PLUGIN_VERSION_SYMBOL: &'static str = "eve_plugin_version";
+
+// Nul terminated so it can be handed across as a C string.
+pub const PLUGIN_VERSION: &'static str = concat!(env!("CARGO_PKG_VERSION"), " (", env!("EVE_RUSTC_VERSION"), ")\0");
+
+// Returns false if registering panicked.
+pub type PluginRegisterFn = extern "C" fn(&mut PluginRegistrar) -> bool;
+pub type PluginVersionFn = extern "C" fn() -> *const libc::c_char;
+
+pub fn plugin_version() -> &'static str {
+    &PLUGIN_VERSION[..PLUGIN_VERSION.len() - 1]
+}
+
+pub struct PluginRegistrar {
+    outgoing: Sender<RunLoopMessage>,
+    watchers: Vec<Box<Watcher + Send>>,
+}
+
+impl PluginRegistrar {
+    pub fn new(outgoing: Sender<RunLoopMessage>) -> PluginRegistrar {
+        PluginRegistrar { outgoing, watchers: vec![] }
+    }
+
+    // The channel of the program the watchers are being registered with.
+    pub fn outgoing(&self) -> Sender<RunLoopMessage> {
+        self.outgoing.clone()
+    }
+
+    pub fn register(&mut self, watcher: Box<Watcher + Send>) {
+        self.watchers.push(watcher);
+    }
+
+    pub fn watchers(self) -> Vec<Box<Watcher + Send>> {
+        self.watchers
+    }
+}
+
+// Panics can't unwind across the library boundary, so they're caught on the plugin's side.
+#[macro_export]
+macro_rules! eve_plugin (($register:expr) => (
+    #[no_mangle]
+    pub extern "C" fn eve_plugin_version() -> *const ::std::os::raw::c_char {
+        $crate::watchers::plugin::PLUGIN_VERSION.as_ptr() as *const ::std::os::raw::c_char
+    }
+
+    #[no_mangle]
+    pub extern "C" fn eve_plugin_register(registrar: &mut $crate::watchers::plugin::PluginRegistrar) -> bool {
+        ::std::panic::catch_unwind(::std::panic::AssertUnwindSafe(|| ($register)(registrar))).is_ok()
+    }
+));
+
+// Libraries are never unloaded, since the watchers they registered can outlive any one program.
+#[cfg(unix)]
+fn open_library(path: &str) -> Result<PluginRegisterFn, String> {
+    use std::ffi::{CStr, CString};
+    use std::mem;
+
+    let c_path = CString::new(path).map_err(|_| format!("Invalid plugin path '{}'", path))?;
+    let symbol = CString::new(PLUGIN_REGISTER_SYMBOL).unwrap();
+    let version_symbol = CString::new(PLUGIN_VERSION_SYMBOL).unwrap();
+    unsafe {
+        let handle = libc::dlopen(c_path.as_ptr(), libc::RTLD_NOW);
+        if handle.is_null() {
+            let error = libc::dlerror();
+            let why = if error.is_null() { "unknown error".to_string() } else { CStr::from_ptr(error).to_string_lossy().into_owned() };
+            return Err(format!("Unable to load plugin '{}': {}", path, why));
+        }
+        let version = libc::dlsym(handle, version_symbol.as_ptr());
+        if version.is_null() {
+            return Err(format!("Plugin '{}' doesn't say which version of Eve it was built for", path));
+        }
+        let version = mem::transmute::<*mut libc::c_void, PluginVersionFn>(version)();
+        let version = if version.is_null() { "unknown".into() } else { CStr::from_ptr(version).to_string_lossy() };
+        if version != plugin_version() {
+            return Err(format!("Plugin '{}' was built for Eve {}, but this is Eve {}", path, version, plugin_version()));
+        }
+        let register = libc::dlsym(handle, symbol.as_ptr());
+        if register.is_null() {
+            return Err(format!("Plugin '{}' doesn't export `{}`", path, PLUGIN_REGISTER_SYMBOL));
+        }
+        Ok(mem::transmute::<*mut libc::c_void, PluginRegisterFn>(register))
+    }
+}
+
+#[cfg(not(unix))]
+fn open_library(path: &str) -> Result<PluginRegisterFn, String> {
+    Err(format!("Unable to load plugin '{}': plugins are only supported on unix", path))
+}
+
+// Each call registers a fresh set of watchers for the program behind `outgoing`.
+pub fn load_plugin(path: &str, outgoing: Sender<RunLoopMessage>) -> Result<Vec<Box<Watcher + Send>>, String> {
+    let register = open_library(path)?;
+    let mut registrar = PluginRegistrar::new(outgoing);
+    if !register(&mut registrar) {
+        return Err(format!("Plugin '{}' panicked while registering its watchers", path));
+    }
+    Ok(registrar.watchers())
+}
+
+//-------------------------------------------------------------------------
+// Plugin Watcher
+//-------------------------------------------------------------------------
+
+// Loads plugins from `#plugin/load` records. The watchers are attached once the current
+// transaction is done and detached again when the record goes away.
+pub struct PluginWatcher {
+    name: String,
+    outgoing: Sender<RunLoopMessage>,
+    loaded: HashMap<Interned, Vec<String>>,
+}
+
+impl PluginWatcher {
+    pub fn new(outgoing: Sender<RunLoopMessage>) -> PluginWatcher {
+        PluginWatcher { name: "plugin".to_string(), outgoing, loaded: HashMap::new() }
+    }
+}
+
+fn plugin_loaded(plugin: &Internable, watchers: &Vec<String>) -> Vec<RawChange> {
+    let id = s(&format!("plugin/loaded/{}", Internable::to_string(plugin)));
+    let mut changes = vec![
+        RawChange {e: id.clone(), a: s("tag"), v: s("plugin/loaded"), n: s("plugin"), count: 1},
+        RawChange {e: id.clone(), a: s("plugin"), v: plugin.clone(), n: s("plugin"), count: 1},
+    ];
+    for watcher in watchers.iter() {
+        changes.push(RawChange {e: id.clone(), a: s("watcher"), v: s(watcher), n: s("plugin"), count: 1});
+    }
+    changes
+}
+
+fn plugin_error(plugin: &Internable, why: String) -> Vec<RawChange> {
+    let id = s(&format!("plugin/error/{}", Internable::to_string(plugin)));
+    vec![
+        RawChange {e: id.clone(), a: s("tag"), v: s("plugin/error"), n: s("plugin"), count: 1},
+        RawChange {e: id.clone(), a: s("message"), v: s(&why), n: s("plugin"), count: 1},
+        RawChange {e: id.clone(), a: s("plugin"), v: plugin.clone(), n: s("plugin"), count: 1},
+    ]
+}
+
+impl Watcher for PluginWatcher {
+    fn get_name(& self) -> String {
+        self.name.clone()
+    }
+    fn set_name(&mut self, name: &str) {
+        self.name = name.to_string();
+    }
+    fn schema(&self) -> Option<WatchSchema> {
+        Some(WatchSchema::new(vec![
+            WatchRow::new("load", vec![("plugin", WatchType::Any), ("path", WatchType::String)]),
+        ]))
+    }
+    fn on_diff(&mut self, interner:&mut Interner, diff:WatchDiff) {
+        for remove in diff.removes {
+            if let Some(watchers) = self.loaded.remove(&remove[1]) {
+                for watcher in watchers {
+                    self.outgoing.send(RunLoopMessage::DetachWatcher(watcher)).ok();
+                }
+            }
+        }
+
+        for add in diff.adds {
+            if let &Internable::String(ref kind) = interner.get_value(add[0]) {
+                match (kind.as_ref(), &add[1..]) {
+                    ("load", &[plugin, path]) => {
+                        let plugin_record = interner.get_value(plugin).clone();
+                        let changes = match load_plugin(&Internable::to_string(interner.get_value(path)), self.outgoing.clone()) {
+                            Ok(watchers) => {
+                                let names:Vec<String> = watchers.iter().map(|watcher| watcher.get_name()).collect();
+                                for watcher in watchers {
+                                    self.outgoing.send(RunLoopMessage::AttachWatcher(watcher)).ok();
+                                }
+                                let changes = plugin_loaded(&plugin_record, &names);
+                                self.loaded.insert(plugin, names);
+                                changes
+                            }
+                            Err(why) => plugin_error(&plugin_record, why),
+                        };
+                        self.outgoing.send(RunLoopMessage::Transaction(changes)).ok();
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+}
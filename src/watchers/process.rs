@@ -0,0 +1,304 @@
+use super::super::indexes::{WatchDiff};
+use super::super::ops::{Interned, Internable, Interner, RawChange, RunLoopMessage, s};
+use std::sync::mpsc::{self, Sender, Receiver, RecvTimeoutError};
+use std::collections::{HashMap};
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::process::{Child, ChildStdin, Command, ExitStatus, Stdio};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use super::{Watcher, WatchSchema, WatchRow, WatchType};
+
+//-------------------------------------------------------------------------
+// Process Watcher
+//-------------------------------------------------------------------------
+
+struct RunningProcess {
+    // Dropping this kills the child if it's still running.
+    _stop: Sender<()>,
+    // Writes are handed off to a thread so a child that isn't reading can't stall the run loop.
+    // Dropping this closes the child's stdin once everything already written has gone out.
+    stdin: Option<Sender<String>>,
+}
+
+pub struct ProcessWatcher {
+    name: String,
+    outgoing: Sender<RunLoopMessage>,
+    args: HashMap<Interned, Vec<(f32, String)>>,
+    env: HashMap<Interned, Vec<(String, String)>>,
+    processes: HashMap<Interned, RunningProcess>,
+}
+
+impl ProcessWatcher {
+    pub fn new(outgoing: Sender<RunLoopMessage>) -> ProcessWatcher {
+        ProcessWatcher { name: "process".to_string(), outgoing, args: HashMap::new(), env: HashMap::new(), processes: HashMap::new() }
+    }
+
+    fn spawn(&mut self, interner:&Interner, process:Interned, command:Interned, cwd:Interned) {
+        let process_record = interner.get_value(process).clone();
+        let id = Internable::to_string(&process_record);
+        let mut args = self.args.get(&process).cloned().unwrap_or_else(|| vec![]);
+        args.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        let cwd = Internable::to_string(interner.get_value(cwd));
+
+        let mut command = Command::new(Internable::to_string(interner.get_value(command)));
+        command.args(args.into_iter().map(|(_, arg)| arg))
+               .stdin(Stdio::piped())
+               .stdout(Stdio::piped())
+               .stderr(Stdio::piped());
+        for &(ref name, ref value) in self.env.get(&process).unwrap_or(&vec![]) {
+            command.env(name, value);
+        }
+        if cwd != "" {
+            command.current_dir(&cwd);
+        }
+
+        let mut child = match command.spawn() {
+            Ok(child) => child,
+            Err(why) => {
+                self.outgoing.send(RunLoopMessage::Transaction(process_error(&id, &process_record, why.to_string()))).ok();
+                return;
+            }
+        };
+        let stdin = write_input(self.outgoing.clone(), id.to_owned(), process_record.clone(), child.stdin.take());
+        let readers = vec![
+            read_output(self.outgoing.clone(), id.to_owned(), process_record.clone(), "stdout", child.stdout.take()),
+            read_output(self.outgoing.clone(), id.to_owned(), process_record.clone(), "stderr", child.stderr.take()),
+        ];
+        let (stop, stopped) = mpsc::channel();
+        wait_for_exit(self.outgoing.clone(), id, process_record, child, readers, stopped);
+        self.processes.insert(process, RunningProcess { _stop: stop, stdin });
+    }
+
+    fn write(&mut self, interner:&Interner, process:Interned, text:Interned) {
+        let process_record = interner.get_value(process).clone();
+        let id = Internable::to_string(&process_record);
+        let written = match self.processes.get(&process).and_then(|running| running.stdin.as_ref()) {
+            Some(stdin) => stdin.send(Internable::to_string(interner.get_value(text))).is_ok(),
+            None => false,
+        };
+        if !written {
+            self.outgoing.send(RunLoopMessage::Transaction(process_error(&id, &process_record, "The process isn't running or its stdin has been closed".to_string()))).ok();
+        }
+    }
+
+    // Args are ordered by their index, anything else is reported rather than taken down the run loop.
+    fn arg_index(&self, interner:&Interner, process:Interned, ix:Interned) -> Option<f32> {
+        match interner.get_value(ix) {
+            number @ &Internable::Number(_) => Some(Internable::to_number(number)),
+            other => {
+                let process_record = interner.get_value(process).clone();
+                let why = format!("Argument indexes must be numbers, got '{}'", Internable::to_string(other));
+                self.outgoing.send(RunLoopMessage::Transaction(process_error(&Internable::to_string(&process_record), &process_record, why))).ok();
+                None
+            }
+        }
+    }
+}
+
+fn process_error(id: &str, process: &Internable, why: String) -> Vec<RawChange> {
+    let err_id = s(&format!("process/error/{}", id));
+    vec![
+        RawChange {e: err_id.clone(), a: s("tag"), v: s("process/error"), n: s("process/error"), count: 1},
+        RawChange {e: err_id.clone(), a: s("message"), v: s(&why), n: s("process/error"), count: 1},
+        RawChange {e: err_id.clone(), a: s("process"), v: process.clone(), n: s("process/error"), count: 1},
+    ]
+}
+
+fn write_input(outgoing: Sender<RunLoopMessage>, id: String, process: Internable, stdin: Option<ChildStdin>) -> Option<Sender<String>> {
+    let mut stdin = match stdin {
+        Some(stdin) => stdin,
+        None => return None,
+    };
+    let (writes, incoming) = mpsc::channel::<String>();
+    thread::spawn(move || {
+        for text in incoming {
+            if let Err(why) = stdin.write_all(text.as_bytes()).and_then(|_| stdin.flush()) {
+                outgoing.send(RunLoopMessage::Transaction(process_error(&id, &process, why.to_string()))).ok();
+                break;
+            }
+        }
+    });
+    Some(writes)
+}
+
+// Each line of output goes out as soon as it's read, so long running commands can be watched as
+// they go.
+fn read_output<R: Read + Send + 'static>(outgoing: Sender<RunLoopMessage>, id: String, process: Internable, stream: &'static str, output: Option<R>) -> JoinHandle<()> {
+    thread::spawn(move || {
+        let mut reader = match output {
+            Some(output) => BufReader::new(output),
+            None => return,
+        };
+        let mut ix = 0;
+        let mut line = vec![];
+        loop {
+            line.clear();
+            match reader.read_until(b'\n', &mut line) {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {}
+            }
+            while line.last() == Some(&b'\n') || line.last() == Some(&b'\r') {
+                line.pop();
+            }
+            ix += 1;
+            let line_id = s(&format!("process/output/{}/{}/{}", id, stream, ix));
+            let changes = vec![
+                RawChange {e: line_id.clone(), a: s("tag"), v: s("process/output"), n: s("process/output"), count: 1},
+                RawChange {e: line_id.clone(), a: s("process"), v: process.clone(), n: s("process/output"), count: 1},
+                RawChange {e: line_id.clone(), a: s("stream"), v: s(stream), n: s("process/output"), count: 1},
+                RawChange {e: line_id.clone(), a: s("ix"), v: Internable::from_number(ix as f32), n: s("process/output"), count: 1},
+                RawChange {e: line_id.clone(), a: s("text"), v: s(&String::from_utf8_lossy(&line)), n: s("process/output"), count: 1},
+            ];
+            if outgoing.send(RunLoopMessage::Transaction(changes)).is_err() { break; }
+        }
+    })
+}
+
+#[cfg(unix)]
+fn exit_signal(status: &ExitStatus) -> Option<i32> {
+    use std::os::unix::process::ExitStatusExt;
+    status.signal()
+}
+
+#[cfg(not(unix))]
+fn exit_signal(_: &ExitStatus) -> Option<i32> {
+    None
+}
+
+// Polls the child so that it can be killed as soon as `stop` goes away. The exit is only reported
+// once all of its output has been.
+fn wait_for_exit(outgoing: Sender<RunLoopMessage>, id: String, process: Internable, mut child: Child, readers: Vec<JoinHandle<()>>, stop: Receiver<()>) {
+    thread::spawn(move || {
+        let mut killed = false;
+        let status = loop {
+            if killed {
+                thread::sleep(Duration::from_millis(50));
+            } else if let Err(RecvTimeoutError::Disconnected) = stop.recv_timeout(Duration::from_millis(50)) {
+                child.kill().ok();
+                killed = true;
+            }
+            match child.try_wait() {
+                Ok(Some(status)) => break Ok(status),
+                Ok(None) => {}
+                Err(why) => break Err(why),
+            }
+        };
+        for reader in readers {
+            reader.join().ok();
+        }
+        let changes = match status {
+            Err(why) => process_error(&id, &process, why.to_string()),
+            Ok(status) => {
+                let exit_id = s(&format!("process/exit/{}", id));
+                let mut changes = vec![
+                    RawChange {e: exit_id.clone(), a: s("tag"), v: s("process/exit"), n: s("process/exit"), count: 1},
+                    RawChange {e: exit_id.clone(), a: s("process"), v: process.clone(), n: s("process/exit"), count: 1},
+                ];
+                if let Some(code) = status.code() {
+                    changes.push(RawChange {e: exit_id.clone(), a: s("code"), v: Internable::from_number(code as f32), n: s("process/exit"), count: 1});
+                }
+                if let Some(signal) = exit_signal(&status) {
+                    changes.push(RawChange {e: exit_id.clone(), a: s("signal"), v: Internable::from_number(signal as f32), n: s("process/exit"), count: 1});
+                }
+                changes
+            }
+        };
+        outgoing.send(RunLoopMessage::Transaction(changes)).ok();
+    });
+}
+
+impl Watcher for ProcessWatcher {
+    fn get_name(& self) -> String {
+        self.name.clone()
+    }
+    fn set_name(&mut self, name: &str) {
+        self.name = name.to_string();
+    }
+    fn schema(&self) -> Option<WatchSchema> {
+        Some(WatchSchema::new(vec![
+            WatchRow::new("spawn", vec![("process", WatchType::Any), ("command", WatchType::String), ("cwd", WatchType::String)]),
+            WatchRow::new("arg", vec![("process", WatchType::Any), ("ix", WatchType::Number), ("value", WatchType::Any)]),
+            WatchRow::new("env", vec![("process", WatchType::Any), ("name", WatchType::String), ("value", WatchType::Any)]),
+            WatchRow::new("write", vec![("write", WatchType::Any), ("process", WatchType::Any), ("text", WatchType::Any)]),
+            WatchRow::new("close", vec![("close", WatchType::Any), ("process", WatchType::Any)]),
+        ]))
+    }
+    fn on_diff(&mut self, interner:&mut Interner, diff:WatchDiff) {
+        for remove in diff.removes {
+            if let &Internable::String(ref kind) = interner.get_value(remove[0]) {
+                match (kind.as_ref(), &remove[1..]) {
+                    ("spawn", &[process, _, _]) => {
+                        self.processes.remove(&process);
+                    }
+                    ("arg", &[process, ix, value]) => {
+                        // Args without a numeric index were never added in the first place.
+                        let ix = match interner.get_value(ix) {
+                            number @ &Internable::Number(_) => Internable::to_number(number),
+                            _ => continue,
+                        };
+                        let arg = (ix, Internable::to_string(interner.get_value(value)));
+                        if let Some(args) = self.args.get_mut(&process) {
+                            args.retain(|existing| *existing != arg);
+                        }
+                        if self.args.get(&process).map_or(false, |args| args.len() == 0) { self.args.remove(&process); }
+                    }
+                    ("env", &[process, name, value]) => {
+                        let pair = (Internable::to_string(interner.get_value(name)), Internable::to_string(interner.get_value(value)));
+                        if let Some(env) = self.env.get_mut(&process) {
+                            env.retain(|existing| *existing != pair);
+                        }
+                        if self.env.get(&process).map_or(false, |env| env.len() == 0) { self.env.remove(&process); }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        // Args and env have to be in place before we spawn the process they belong to, and the
+        // process has to be running before we can write to it.
+        let mut spawns = vec![];
+        let mut writes = vec![];
+        for add in diff.adds {
+            if let &Internable::String(ref kind) = interner.get_value(add[0]) {
+                match (kind.as_ref(), &add[1..]) {
+                    ("arg", &[process, ix, value]) => {
+                        let ix = match self.arg_index(interner, process, ix) {
+                            Some(ix) => ix,
+                            None => continue,
+                        };
+                        let arg = (ix, Internable::to_string(interner.get_value(value)));
+                        self.args.entry(process).or_insert_with(|| vec![]).push(arg);
+                    }
+                    ("env", &[process, name, value]) => {
+                        let pair = (Internable::to_string(interner.get_value(name)), Internable::to_string(interner.get_value(value)));
+                        self.env.entry(process).or_insert_with(|| vec![]).push(pair);
+                    }
+                    ("spawn", &[process, command, cwd]) => spawns.push((process, command, cwd)),
+                    ("write", &[_, process, text]) => writes.push((process, Some(text))),
+                    ("close", &[_, process]) => writes.push((process, None)),
+                    _ => {}
+                }
+            }
+        }
+
+        for (process, command, cwd) in spawns {
+            self.spawn(interner, process, command, cwd);
+        }
+        for (process, text) in writes {
+            match text {
+                Some(text) => self.write(interner, process, text),
+                None => {
+                    if let Some(running) = self.processes.get_mut(&process) {
+                        running.stdin = None;
+                    }
+                }
+            }
+        }
+    }
+    fn on_shutdown(&mut self) {
+        self.processes.clear();
+        self.args.clear();
+        self.env.clear();
+    }
+}
@@ -2,87 +2,534 @@ extern crate serde_json;
 
 use super::super::indexes::{WatchDiff, RawRemoteChange};
 use super::super::ops::{Internable, Interner, Interned, RunLoopMessage, RawChange, s, JSONInternable};
-use super::Watcher;
+use super::{Watcher, WatchSchema, WatchRow, WatchType};
 
 use std::sync::mpsc::{self, Sender, SendError};
 use std::sync::{Arc, Mutex};
 use std::thread;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
+use std::time::Duration;
 
 extern crate ws;
 use self::ws::Message;
 
-extern crate term_painter;
-use self::term_painter::ToStyle;
-use self::term_painter::Color::*;
-
 //-------------------------------------------------------------------------
 // Router
 //-------------------------------------------------------------------------
 
 pub enum RouterMessage {
     Remote(Vec<RawRemoteChange>),
-    Local(String, Vec<RawChange>)
+    Local(String, Vec<RawChange>),
+    PeerConnected(String, u64, ws::Sender),
+    PeerDisconnected(String),
+    FromPeer(String, PeerMessage),
+}
+
+// What Eve servers say to each other. Every connection starts with a `Hello` naming the server
+// and carrying the secret shared by all the peers, followed by the programs it hosts. The
+// instance changes whenever a server restarts.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum PeerMessage {
+    Hello { name: String, instance: u64, secret: String },
+    Clients { names: Vec<String> },
+    Register { name: String },
+    Unregister { name: String },
+    // Each (from, to) pair is numbered separately. A reset replaces everything the receiver has
+    // from the sender with the given changes.
+    Remote { from: String, to: String, seq: u64, reset: bool, changes: Vec<RawRemoteChange> },
+    Ack { from: String, to: String, seq: u64 },
+    // Asks the sender for a reset, e.g. when the receiver missed something or started over.
+    Resync { from: String, to: String },
+    Undeliverable { from: Internable, to: Internable, message: String },
+}
+
+// How many messages are held for a disconnected peer before the oldest are dropped.
+const MAX_PEER_QUEUE:usize = 1000;
+// How many unacked messages an outbox holds before they're collapsed into a single reset.
+const MAX_UNACKED:usize = 1000;
+
+struct Peer {
+    // None while the peer is disconnected, anything sent to it in the meantime is queued up and
+    // flushed in order once it comes back.
+    out: Option<ws::Sender>,
+    queue: Vec<PeerMessage>,
+    clients: HashSet<String>,
+    instance: Option<u64>,
+}
+
+impl Peer {
+    fn new() -> Peer {
+        Peer { out: None, queue: vec![], clients: HashSet::new(), instance: None }
+    }
+
+    fn send(&mut self, message: PeerMessage) {
+        let sent = match self.out {
+            Some(ref out) => out.send(Message::Text(serde_json::to_string(&message).unwrap())).is_ok(),
+            None => false,
+        };
+        if !sent {
+            self.out = None;
+            self.enqueue(message);
+        }
+    }
+
+    fn enqueue(&mut self, message: PeerMessage) {
+        match message {
+            // Remote changes wait in their outbox until they're acked, so they'll be resent anyway.
+            PeerMessage::Remote { .. } => return,
+            // The peer is sent our full list of programs when it comes back.
+            PeerMessage::Clients { .. } | PeerMessage::Register { .. } | PeerMessage::Unregister { .. } => return,
+            // Only the latest ack or resync for a pair matters.
+            PeerMessage::Ack { ref from, ref to, .. } => {
+                self.queue.retain(|queued| match queued {
+                    &PeerMessage::Ack { from: ref f, to: ref t, .. } => f != from || t != to,
+                    _ => true,
+                });
+            }
+            PeerMessage::Resync { ref from, ref to } => {
+                self.queue.retain(|queued| match queued {
+                    &PeerMessage::Resync { from: ref f, to: ref t } => f != from || t != to,
+                    _ => true,
+                });
+            }
+            _ => {}
+        }
+        if self.queue.len() >= MAX_PEER_QUEUE {
+            log_warn!("router", "Too many messages queued for a disconnected peer, dropping the oldest");
+            let overflow = self.queue.len() + 1 - MAX_PEER_QUEUE;
+            self.queue.drain(..overflow);
+        }
+        self.queue.push(message);
+    }
+}
+
+fn client_name(value:&Internable) -> String {
+    match value {
+        &Internable::Null => "null".to_string(),
+        _ => Internable::to_string(value),
+    }
+}
+
+// e, a, v and _for of a remote fact.
+type RemoteFact = (Internable, Internable, Internable, Internable);
+
+fn apply_facts(facts:&mut HashSet<RemoteFact>, changes:&Vec<RawRemoteChange>) {
+    for change in changes.iter() {
+        let fact = (change.e.clone(), change.a.clone(), change.v.clone(), change._for.clone());
+        if change._type == s("remove") {
+            facts.remove(&fact);
+        } else {
+            facts.insert(fact);
+        }
+    }
+}
+
+fn fact_changes(facts:&HashSet<RemoteFact>, _type:&str, from:&Internable, to:&Internable) -> Vec<RawRemoteChange> {
+    facts.iter().map(|&(ref e, ref a, ref v, ref _for)| {
+        RawRemoteChange { e: e.clone(), a: a.clone(), v: v.clone(), _for: _for.clone(), _type: s(_type), from: from.clone(), to: to.clone() }
+    }).collect()
+}
+
+// The sending side of a (from, to) pair. Everything sent to a peer stays here until the receiver
+// acks it, along with the sender's current set of facts for when the receiver needs to start over.
+// Local receivers get their changes straight away, so their outboxes only keep the facts.
+struct Outbox {
+    seq: u64,
+    unacked: Vec<PeerMessage>,
+    facts: HashSet<RemoteFact>,
+}
+
+// The receiving side of a (from, to) pair.
+struct Inbox {
+    seq: u64,
+    facts: HashSet<RemoteFact>,
+    resyncing: bool,
+}
+
+// Programs on a peer can be addressed as `client@peer`, or just by name if no local program has it.
+// Changes we pass on to a peer have their `from` qualified with our name so replies find their way
+// back.
+struct RouterState {
+    me: Option<String>,
+    instance: u64,
+    clients: HashMap<String, Sender<RunLoopMessage>>,
+    peers: HashMap<String, Peer>,
+    // Keyed by our local sender and either a local receiver or `client@peer`.
+    outboxes: HashMap<(String, String), Outbox>,
+    // Keyed by the qualified sender and our local receiver.
+    inboxes: HashMap<(String, String), Inbox>,
+}
+
+impl RouterState {
+    // Strips our own name off of a qualified client.
+    fn local_name(&self, name:String) -> String {
+        if let Some(ref me) = self.me {
+            let suffix = format!("@{}", me);
+            if name.ends_with(&suffix) {
+                return name[..name.len() - suffix.len()].to_owned();
+            }
+        }
+        name
+    }
+
+    fn qualify(&self, name:&str) -> String {
+        match self.me {
+            Some(ref me) if !name.contains('@') => format!("{}@{}", name, me),
+            _ => name.to_owned(),
+        }
+    }
+
+    // The peer hosting a client along with the name the peer knows it by.
+    fn resolve(&self, name:&str) -> Option<(String, String)> {
+        if let Some(ix) = name.rfind('@') {
+            let (client, peer) = (&name[..ix], &name[ix + 1..]);
+            return if self.peers.contains_key(peer) { Some((peer.to_owned(), client.to_owned())) } else { None };
+        }
+        self.peers.iter().find(|&(_, peer)| peer.clients.contains(name)).map(|(peer, _)| (peer.to_owned(), name.to_owned()))
+    }
+
+    fn send_to_peer(&mut self, peer:&str, message:PeerMessage) {
+        if let Some(peer) = self.peers.get_mut(peer) {
+            peer.send(message);
+        }
+    }
+
+    fn broadcast(&mut self, message: PeerMessage) {
+        for peer in self.peers.values_mut() {
+            peer.send(message.clone());
+        }
+    }
+
+    fn register(&mut self, name:&str, channel:Sender<RunLoopMessage>) {
+        // Local programs that were sending to one by this name pick up where they left off.
+        for (&(ref from, ref to), outbox) in self.outboxes.iter() {
+            if to == name && outbox.facts.len() > 0 {
+                channel.send(RunLoopMessage::RemoteTransaction(fact_changes(&outbox.facts, "add", &s(from), &s(name)))).ok();
+            }
+        }
+        self.clients.insert(name.to_string(), channel);
+        // Peers that were sending to a program by this name start over with it when they see this.
+        self.broadcast(PeerMessage::Register { name: name.to_string() });
+
+        // Anyone who tried to reach it before it showed up is asked for everything.
+        let remotes:Vec<String> = self.inboxes.keys().filter(|&&(_, ref to)| to == name).map(|&(ref from, _)| from.to_owned()).collect();
+        for from in remotes {
+            if let Some(inbox) = self.inboxes.get_mut(&(from.to_owned(), name.to_owned())) {
+                inbox.facts.clear();
+                inbox.resyncing = false;
+            }
+            self.resync(from, name.to_owned());
+        }
+    }
+
+    // Everything the program was sending or being sent goes with it, except what local programs
+    // were sending it, which is replayed if it comes back.
+    fn unregister(&mut self, name:&str) {
+        self.clients.remove(name);
+        self.outboxes.retain(|&(ref from, _), _| from != name);
+        self.inboxes.retain(|&(_, ref to), _| to != name);
+        self.broadcast(PeerMessage::Unregister { name: name.to_string() });
+    }
+
+    // Forgets what we've received from a remote sender and asks it for everything again.
+    fn resync(&mut self, from:String, to:String) {
+        if let Some((peer, client)) = self.resolve(&from) {
+            if let Some(inbox) = self.inboxes.get_mut(&(from.to_owned(), to.to_owned())) {
+                if inbox.resyncing { return; }
+                inbox.resyncing = true;
+            }
+            self.send_to_peer(&peer, PeerMessage::Resync { from: client, to });
+        }
+    }
+
+    // Tell the sending program that nobody is listening, instead of taking the router down.
+    fn undeliverable(&mut self, from:&Internable, to:&Internable, message:&str) {
+        let from_name = self.local_name(client_name(from));
+        if let Some(channel) = self.clients.get(&from_name) {
+            let id = s(&format!("router/error|{}|{}", from_name, client_name(to)));
+            channel.send(RunLoopMessage::Transaction(vec![
+                RawChange { e: id.clone(), a: s("tag"), v: s("router/error"), n: s("router"), count: 1 },
+                RawChange { e: id.clone(), a: s("from"), v: from.clone(), n: s("router"), count: 1 },
+                RawChange { e: id.clone(), a: s("to"), v: to.clone(), n: s("router"), count: 1 },
+                RawChange { e: id.clone(), a: s("message"), v: s(message), n: s("router"), count: 1 },
+            ])).ok();
+            return;
+        }
+        if let Some((peer, client)) = self.resolve(&from_name) {
+            self.send_to_peer(&peer, PeerMessage::Undeliverable { from: Internable::String(client), to: to.clone(), message: message.to_owned() });
+            return;
+        }
+        log_error!("router", "{} (from '{}')", message, from_name);
+    }
+
+    fn route(&mut self, remotes:Vec<RawRemoteChange>) {
+        // Changes are grouped by destination in the order they arrived, so each (from, to) pair
+        // sees them in the order they were sent.
+        let mut grouping:Vec<((Internable, Internable), Vec<RawRemoteChange>)> = vec![];
+        for remote in remotes {
+            let key = (remote.from.clone(), remote.to.clone());
+            let existing = grouping.iter().position(|&(ref pair, _)| *pair == key);
+            match existing {
+                Some(ix) => grouping[ix].1.push(remote),
+                None => grouping.push((key, vec![remote])),
+            }
+        }
+        for ((from, to), changes) in grouping {
+            let from_name = client_name(&from);
+            let name = self.local_name(client_name(&to));
+            // A paused program queues these like any other transaction, so whatever its pause queue
+            // drops is only made up for if it restarts.
+            if self.clients.contains_key(&name) {
+                apply_facts(&mut self.outboxes.entry((from_name.to_owned(), name.to_owned())).or_insert_with(Outbox::new).facts, &changes);
+                self.clients[&name].send(RunLoopMessage::RemoteTransaction(changes)).ok();
+                continue;
+            }
+            if let Some((peer, client)) = self.resolve(&name) {
+                let message = {
+                    let outbox = self.outboxes.entry((from_name.to_owned(), format!("{}@{}", client, peer))).or_insert_with(Outbox::new);
+                    apply_facts(&mut outbox.facts, &changes);
+                    outbox.seq += 1;
+                    if outbox.unacked.len() < MAX_UNACKED {
+                        let message = PeerMessage::Remote { from: from_name.to_owned(), to: client.to_owned(), seq: outbox.seq, reset: false, changes };
+                        outbox.unacked.push(message.clone());
+                        message
+                    } else {
+                        // The receiver is too far behind to catch up one message at a time, so we
+                        // send it everything we have instead.
+                        let changes = fact_changes(&outbox.facts, "add", &s(&from_name), &s(&client));
+                        let message = PeerMessage::Remote { from: from_name.to_owned(), to: client.to_owned(), seq: outbox.seq, reset: true, changes };
+                        outbox.unacked = vec![message.clone()];
+                        message
+                    }
+                };
+                let message = self.qualify_remote(message);
+                self.send_to_peer(&peer, message);
+                continue;
+            }
+            self.undeliverable(&from, &to, &format!("Unable to send remote changes to unknown client '{}'", name));
+        }
+    }
+
+    // Outboxes are keyed by our local names, peers need to know who the changes are from.
+    fn qualify_remote(&self, message:PeerMessage) -> PeerMessage {
+        match message {
+            PeerMessage::Remote { from, to, seq, reset, changes } => {
+                let qualified = Internable::String(self.qualify(&from));
+                let changes = changes.into_iter().map(|mut change| {
+                    change.from = qualified.clone();
+                    change.to = Internable::String(to.to_owned());
+                    change
+                }).collect();
+                PeerMessage::Remote { from: self.qualify(&from), to, seq, reset, changes }
+            }
+            message => message,
+        }
+    }
+
+    // Changes from a peer are delivered in order exactly once, anything out of order means we
+    // missed something and need to start over.
+    fn receive(&mut self, peer:String, from:String, to:String, seq:u64, reset:bool, changes:Vec<RawRemoteChange>) {
+        let to = self.local_name(to);
+        let key = (from.to_owned(), to.to_owned());
+        if !self.clients.contains_key(&to) {
+            // Keep track of the sender so that it's resynced once the receiver shows up.
+            self.inboxes.entry(key).or_insert_with(Inbox::new);
+            if let Some(change) = changes.first() {
+                self.undeliverable(&change.from, &change.to, &format!("Unable to send remote changes to unknown client '{}'", to));
+            }
+            return;
+        }
+
+        let delivery = {
+            let inbox = self.inboxes.entry(key).or_insert_with(Inbox::new);
+            if reset {
+                let mut facts = HashSet::new();
+                apply_facts(&mut facts, &changes);
+                let mut delivery = fact_changes(&(&inbox.facts - &facts), "remove", &s(&from), &s(&to));
+                delivery.extend(fact_changes(&(&facts - &inbox.facts), "add", &s(&from), &s(&to)));
+                inbox.facts = facts;
+                inbox.seq = seq;
+                inbox.resyncing = false;
+                Some(delivery)
+            } else if seq <= inbox.seq {
+                // We've already got it, the ack must've gotten lost.
+                None
+            } else if seq == inbox.seq + 1 && !inbox.resyncing {
+                apply_facts(&mut inbox.facts, &changes);
+                inbox.seq = seq;
+                Some(changes)
+            } else {
+                Some(vec![])
+            }
+        };
+        let acked = self.inboxes[&(from.to_owned(), to.to_owned())].seq;
+        match delivery {
+            Some(ref changes) if changes.len() == 0 && acked < seq => {
+                self.resync(from, to);
+                return;
+            }
+            Some(changes) => {
+                if changes.len() > 0 {
+                    self.clients[&to].send(RunLoopMessage::RemoteTransaction(changes)).ok();
+                }
+            }
+            None => {}
+        }
+        self.send_to_peer(&peer, PeerMessage::Ack { from, to, seq: acked });
+    }
+
+    fn acked(&mut self, peer:&str, from:String, to:String, seq:u64) {
+        let key = (self.local_name(from), format!("{}@{}", to, peer));
+        if let Some(outbox) = self.outboxes.get_mut(&key) {
+            outbox.unacked.retain(|message| match message {
+                &PeerMessage::Remote { seq: sent, .. } => sent > seq,
+                _ => true,
+            });
+        }
+    }
+
+    // The receiver is starting over, so we replace everything it has with our current facts.
+    fn reset(&mut self, peer:&str, from:String, to:String) {
+        let from = self.local_name(from);
+        let key = (from.to_owned(), format!("{}@{}", to, peer));
+        let message = {
+            let outbox = self.outboxes.entry(key).or_insert_with(Outbox::new);
+            outbox.seq += 1;
+            let changes = fact_changes(&outbox.facts, "add", &s(&from), &s(&to));
+            let message = PeerMessage::Remote { from, to, seq: outbox.seq, reset: true, changes };
+            outbox.unacked = vec![message.clone()];
+            message
+        };
+        let message = self.qualify_remote(message);
+        self.send_to_peer(peer, message);
+    }
+
+    fn handle(&mut self, message:RouterMessage) {
+        match message {
+            RouterMessage::Remote(remotes) => self.route(remotes),
+            RouterMessage::Local(name, changes) => {
+                if let Some(channel) = self.clients.get(&name) {
+                    match channel.send(RunLoopMessage::Transaction(changes)) {
+                        Ok(_) => (),
+                        Err(SendError(se)) => {
+                            log_error!("router", "Failed to send {}", se.format_error());
+                        }
+                    }
+                } else {
+                    log_error!("router", "Failed to send local TX to nonexistent or unregistered client: '{}'", &name);
+                }
+            }
+            RouterMessage::PeerConnected(name, instance, out) => {
+                log_info!("router", "Peer connected: {}", &name);
+                let restarted = self.peers.get(&name).and_then(|peer| peer.instance).map(|known| known != instance).unwrap_or(false);
+                let names:Vec<String> = self.clients.keys().cloned().collect();
+                // Anything the peer hasn't acked yet goes out again, in order.
+                let suffix = format!("@{}", name);
+                let mut unacked:Vec<PeerMessage> = vec![];
+                for (&(_, ref to), outbox) in self.outboxes.iter() {
+                    if to.ends_with(&suffix) {
+                        unacked.extend(outbox.unacked.iter().cloned());
+                    }
+                }
+                let unacked:Vec<PeerMessage> = unacked.into_iter().map(|message| self.qualify_remote(message)).collect();
+                {
+                    let peer = self.peers.entry(name.to_owned()).or_insert_with(Peer::new);
+                    peer.out = Some(out);
+                    peer.instance = Some(instance);
+                    let queued:Vec<PeerMessage> = peer.queue.drain(..).collect();
+                    peer.send(PeerMessage::Clients { names });
+                    for message in queued.into_iter().chain(unacked.into_iter()) {
+                        peer.send(message);
+                    }
+                }
+                // A restarted peer numbers everything from scratch and has lost whatever it was
+                // sending us, so we start over with it.
+                if restarted {
+                    let suffix = format!("@{}", name);
+                    let from_peer:Vec<(String, String)> = self.inboxes.keys().filter(|&&(ref from, _)| from.ends_with(&suffix)).cloned().collect();
+                    for (from, to) in from_peer {
+                        if let Some(inbox) = self.inboxes.get_mut(&(from.to_owned(), to.to_owned())) {
+                            inbox.seq = 0;
+                            inbox.resyncing = false;
+                        }
+                        self.resync(from, to);
+                    }
+                }
+            }
+            RouterMessage::PeerDisconnected(name) => {
+                log_warn!("router", "Peer disconnected: {}", &name);
+                if let Some(peer) = self.peers.get_mut(&name) {
+                    peer.out = None;
+                }
+            }
+            RouterMessage::FromPeer(name, message) => {
+                match message {
+                    PeerMessage::Clients { names } => {
+                        self.peers.entry(name).or_insert_with(Peer::new).clients = names.into_iter().collect();
+                    }
+                    PeerMessage::Register { name: client } => {
+                        self.peers.entry(name.to_owned()).or_insert_with(Peer::new).clients.insert(client.to_owned());
+                        // The program may be starting over, so it needs everything we've sent it again.
+                        let to = format!("{}@{}", client, name);
+                        let senders:Vec<String> = self.outboxes.keys().filter(|&&(_, ref other)| *other == to).map(|&(ref from, _)| from.to_owned()).collect();
+                        for from in senders {
+                            self.reset(&name, from, client.to_owned());
+                        }
+                    }
+                    PeerMessage::Unregister { name: client } => {
+                        self.peers.entry(name).or_insert_with(Peer::new).clients.remove(&client);
+                    }
+                    PeerMessage::Remote { from, to, seq, reset, changes } => self.receive(name, from, to, seq, reset, changes),
+                    PeerMessage::Ack { from, to, seq } => self.acked(&name, from, to, seq),
+                    PeerMessage::Resync { from, to } => self.reset(&name, from, to),
+                    PeerMessage::Undeliverable { from, to, message } => self.undeliverable(&from, &to, &message),
+                    PeerMessage::Hello { .. } => {}
+                }
+            }
+        }
+    }
+}
+
+impl Outbox {
+    fn new() -> Outbox {
+        Outbox { seq: 0, unacked: vec![], facts: HashSet::new() }
+    }
+}
+
+impl Inbox {
+    fn new() -> Inbox {
+        Inbox { seq: 0, facts: HashSet::new(), resyncing: false }
+    }
 }
 
 pub struct Router {
     manager: Sender<RunLoopMessage>,
     outgoing: Sender<RouterMessage>,
-    clients: Arc<Mutex<HashMap<String, Sender<RunLoopMessage>>>>
+    state: Arc<Mutex<RouterState>>,
 }
 
 impl Router {
     pub fn new(manager: Sender<RunLoopMessage>) -> Router {
         let (outgoing, incoming) = mpsc::channel();
-        let clients = Arc::new(Mutex::new(HashMap::new()));
-        let clients2:Arc<Mutex<HashMap<String, Sender<RunLoopMessage>>>> = clients.clone();
+        let state = Arc::new(Mutex::new(RouterState { me: None, instance: ::rand::random(), clients: HashMap::new(), peers: HashMap::new(), outboxes: HashMap::new(), inboxes: HashMap::new() }));
+        let state2 = state.clone();
         thread::spawn(move || {
-            let mut grouping:HashMap<Internable, Vec<RawRemoteChange>> = HashMap::new();
             loop {
                 match incoming.recv() {
-                    Ok(RouterMessage::Remote(remotes)) => {
-                        for remote in remotes {
-                            let vs = grouping.entry(remote.to.clone()).or_insert_with(|| vec![]);
-                            vs.push(remote);
-                        }
-                        for (key, changes) in grouping.drain() {
-                            if let Internable::String(ref name) = key {
-                                if let Some(channel) = clients2.lock().unwrap().get(name) {
-                                    channel.send(RunLoopMessage::RemoteTransaction(changes)).unwrap();
-                                } else {
-                                    panic!("Failed to send remote TX to nonexistent or unregistered client: '{}'", &name);
-                                }
-                            }
-                        }
-                    }
-                    Ok(RouterMessage::Local(name, changes)) => {
-                        if let Some(channel) = clients2.lock().unwrap().get(&name) {
-                            match channel.send(RunLoopMessage::Transaction(changes)) {
-                                Ok(_) => (),
-                                Err(SendError(se)) => {
-                                    println!("{} Failed to send {}",
-                                             BrightRed.paint("Error:"), se.format_error());
-                                }
-                            }
-                        } else {
-                            panic!("Failed to send local TX to nonexistent or unregistered client: '{}'", &name);
-                        }
-                    }
+                    Ok(message) => state2.lock().unwrap().handle(message),
                     Err(err) => {
                         if let Some(cause) = err.cause() {
-                            println!("{} Receiving failed: {} due to {}\n",
-                                     BrightRed.paint("Error: "), err.description(),
-                                     cause);
+                            log_error!("router", "Receiving failed: {} due to {}", err.description(), cause);
                         } else {
-                            println!("{} Receiving failed: {}\n",
-                                     BrightRed.paint("Error: "), err.description());
+                            log_error!("router", "Receiving failed: {}", err.description());
                         }
+                        break;
                     }
                 }
             }
         });
-        Router { outgoing, clients, manager }
+        Router { outgoing, state, manager }
     }
 
     pub fn register(&mut self, name:&str, channel: Sender<RunLoopMessage>) {
@@ -90,7 +537,7 @@ impl Router {
             RawChange { e: s(name), a: s("tag"), v: s("router/event/add-client"), n: s("router"), count: 1 },
             RawChange { e: s(name), a: s("name"), v: s(name), n: s("router"), count: 1 },
         ])).unwrap();
-        self.clients.lock().unwrap().insert(name.to_string(), channel);
+        self.state.lock().unwrap().register(name, channel);
     }
 
     pub fn unregister(&mut self, name:&str) {
@@ -98,14 +545,121 @@ impl Router {
             RawChange { e: s(name), a: s("tag"), v: s("router/event/remove-client"), n: s("router"), count: 1 },
             RawChange { e: s(name), a: s("name"), v: s(name), n: s("router"), count: 1 },
         ])).unwrap();
-        self.clients.lock().unwrap().remove(name);
+        self.state.lock().unwrap().unregister(name);
     }
 
     pub fn get_channel(&self) -> Sender<RouterMessage> {
         self.outgoing.clone()
     }
+
+    // Whether a program can currently be reached, either here or through a peer.
+    pub fn is_reachable(&self, name:&str) -> bool {
+        let state = self.state.lock().unwrap();
+        let name = state.local_name(name.to_owned());
+        state.clients.contains_key(&name) || state.resolve(&name).is_some()
+    }
+
+    fn set_name(&self, name:&str) -> u64 {
+        let mut state = self.state.lock().unwrap();
+        state.me = Some(name.to_owned());
+        state.instance
+    }
+
+    // Accepts connections from other Eve servers, `name` is how we introduce ourselves to them.
+    // Peers that don't know the shared `secret` are turned away.
+    pub fn listen_for_peers(&self, name:&str, address:&str, secret:&str) {
+        let instance = self.set_name(name);
+        let name = name.to_owned();
+        let address = address.to_owned();
+        let secret = secret.to_owned();
+        let router = self.outgoing.clone();
+        thread::Builder::new().name("router peer listener".to_owned()).spawn(move || {
+            log_info!("router", "Starting peer connections at {}...", &address);
+            if let Err(why) = ws::listen(&address[..], |out| PeerHandler::new(&name, instance, &secret, out, router.clone())) {
+                log_error!("router", "Failed to listen for peers: {}", why);
+            }
+        }).unwrap();
+    }
+
+    // Connects to another Eve server, reconnecting whenever the connection drops.
+    pub fn connect_peer(&self, name:&str, url:&str, secret:&str) {
+        let instance = self.set_name(name);
+        let name = name.to_owned();
+        let url = url.to_owned();
+        let secret = secret.to_owned();
+        let router = self.outgoing.clone();
+        thread::Builder::new().name(format!("router peer {}", url)).spawn(move || {
+            loop {
+                if let Err(why) = ws::connect(&url[..], |out| PeerHandler::new(&name, instance, &secret, out, router.clone())) {
+                    log_warn!("router", "Unable to reach peer {}: {}", &url, why);
+                }
+                thread::sleep(Duration::from_secs(1));
+            }
+        }).unwrap();
+    }
+}
+
+struct PeerHandler {
+    name: String,
+    instance: u64,
+    secret: String,
+    peer: Option<String>,
+    out: ws::Sender,
+    router: Sender<RouterMessage>,
+}
+
+impl PeerHandler {
+    fn new(name:&str, instance:u64, secret:&str, out:ws::Sender, router:Sender<RouterMessage>) -> PeerHandler {
+        PeerHandler { name: name.to_owned(), instance, secret: secret.to_owned(), peer: None, out, router }
+    }
+}
+
+// Compares every byte so the time taken doesn't give away how much of the secret was right.
+fn same_secret(a:&str, b:&str) -> bool {
+    if a.len() != b.len() { return false; }
+    a.bytes().zip(b.bytes()).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
 }
 
+impl ws::Handler for PeerHandler {
+    fn on_open(&mut self, _: ws::Handshake) -> ws::Result<()> {
+        let hello = PeerMessage::Hello { name: self.name.to_owned(), instance: self.instance, secret: self.secret.to_owned() };
+        self.out.send(Message::Text(serde_json::to_string(&hello).unwrap()))
+    }
+
+    fn on_message(&mut self, msg: Message) -> ws::Result<()> {
+        let message:PeerMessage = match msg {
+            Message::Text(text) => match serde_json::from_str(&text) {
+                Ok(message) => message,
+                Err(why) => {
+                    log_error!("router", "Malformed peer message: {}", why);
+                    return Ok(());
+                }
+            },
+            Message::Binary(_) => return Ok(()),
+        };
+        match (message, self.peer.clone()) {
+            (PeerMessage::Hello { name, instance, secret }, _) => {
+                if !same_secret(&secret, &self.secret) {
+                    log_warn!("router", "Peer '{}' doesn't know the peer secret, closing the connection", name);
+                    return self.out.close(ws::CloseCode::Policy);
+                }
+                self.peer = Some(name.to_owned());
+                self.router.send(RouterMessage::PeerConnected(name, instance, self.out.clone())).ok();
+            }
+            (message, Some(peer)) => {
+                self.router.send(RouterMessage::FromPeer(peer, message)).ok();
+            }
+            (_, None) => log_warn!("router", "Peer sent a message before saying hello"),
+        }
+        Ok(())
+    }
+
+    fn on_close(&mut self, _: ws::CloseCode, _: &str) {
+        if let Some(peer) = self.peer.take() {
+            self.router.send(RouterMessage::PeerDisconnected(peer)).ok();
+        }
+    }
+}
 
 //-------------------------------------------------------------------------
 // Remote Watcher
@@ -154,28 +708,34 @@ impl Watcher for RemoteWatcher {
     fn set_name(&mut self, name: &str) {
         self.name = name.to_string();
     }
+    fn schema(&self) -> Option<WatchSchema> {
+        Some(WatchSchema::new(vec![
+            WatchRow::untagged(vec![("to", WatchType::Any), ("for", WatchType::Any), ("entity", WatchType::Any), ("attribute", WatchType::Any), ("value", WatchType::Any), ("allow-removes", WatchType::Any)]),
+        ]))
+    }
 
     fn on_diff(&mut self, interner:&mut Interner, diff:WatchDiff) {
         let mut changes = vec![];
         // Fields: [to, _for, entity, attribute, value, allow_removes (0 or 1)]
+        // Rows that don't fit are rejected by the compiler, but they can still come in from
+        // blocks that were compiled before the schema was known.
         for remove in diff.removes {
-            if remove[5] == 1 {
-                match remove.as_slice() {
-                    &[to, _for, entity, attribute, value, _] => {
+            match remove.as_slice() {
+                &[to, _for, entity, attribute, value, allow_removes] => {
+                    if allow_removes == 1 {
                         // println!("SEND REMOVE: ({:?}, {:?}, {:?}, {:?}, {:?})", to, _for, entity, attribute, value);
                         changes.push(self.to_raw_change(interner,
                                                         Internable::String("remove".to_string()),
                                                         to, _for, entity, attribute, value));
                     }
-                    s => {
-                        let slice_string = s.iter()
-                            .map(|i| format!("{}", i))
-                            .collect::<Vec<_>>()
-                            .join(", ");
-                        panic!(println!("{} Invalid remote remove: ({})", BrightRed.paint("Error:"), slice_string));
-                    }
                 }
-
+                s => {
+                    let slice_string = s.iter()
+                        .map(|i| format!("{}", i))
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    log_error!("router", "Invalid remote remove: ({})", slice_string);
+                }
             }
         }
         for add in diff.adds {
@@ -189,8 +749,7 @@ impl Watcher for RemoteWatcher {
                         .map(|i| format!("{}", i))
                         .collect::<Vec<_>>()
                         .join(", ");
-                    println!("{} Invalid remote add: ({})", BrightRed.paint("Error:"), slice_string);
-                    panic!();
+                    log_error!("router", "Invalid remote add: ({})", slice_string);
                 }
             }
         }
@@ -2,12 +2,54 @@ extern crate time;
 
 use super::super::indexes::{WatchDiff};
 use super::super::ops::{Interned, Internable, Interner, RawChange, RunLoopMessage};
-use std::sync::mpsc::{self, Sender};
+use std::sync::mpsc::{self, Sender, Receiver, RecvTimeoutError};
 use std::thread::{self};
 use std::time::*;
 use std::collections::{HashMap};
+use std::cmp;
+use std::env;
+use std::io::{self, BufRead};
 use std::collections::hash_map::{Entry};
-use super::Watcher;
+use super::{Watcher, WatchSchema, WatchRow, WatchType};
+
+//-------------------------------------------------------------------------
+// Waiting
+//-------------------------------------------------------------------------
+
+fn from_nanos(nanos: u64) -> Duration {
+    Duration::new(nanos / 1_000_000_000, (nanos % 1_000_000_000) as u32)
+}
+
+fn to_nanos(duration: Duration) -> u64 {
+    duration.as_secs() * 1_000_000_000 + duration.subsec_nanos() as u64
+}
+
+// Waits until `deadline` on the monotonic clock. Returns false if we were cancelled first, either
+// by a message on `stop` or by its sender going away.
+fn wait_until(stop: &Receiver<()>, deadline: Instant) -> bool {
+    loop {
+        let now = Instant::now();
+        if now >= deadline { return true; }
+        match stop.recv_timeout(deadline - now) {
+            Err(RecvTimeoutError::Timeout) => {}
+            _ => return false,
+        }
+    }
+}
+
+// Like `wait_until`, but against the wall clock. The clock can be changed out from under us, so
+// we never wait more than a second before checking it again.
+fn wait_until_wall_clock(stop: &Receiver<()>, target: time::Timespec) -> bool {
+    loop {
+        let remaining = target - time::get_time();
+        if remaining <= time::Duration::zero() { return true; }
+        let wait = remaining.to_std().unwrap_or(Duration::from_secs(1)).min(Duration::from_secs(1));
+        match stop.recv_timeout(wait) {
+            Err(RecvTimeoutError::Timeout) => {}
+            _ => return false,
+        }
+    }
+}
 
 //-------------------------------------------------------------------------
 // System Watcher
@@ -32,6 +74,11 @@ impl Watcher for SystemTimerWatcher {
     fn set_name(&mut self, name: &str) {
         self.name = name.to_string();
     }
+    fn schema(&self) -> Option<WatchSchema> {
+        Some(WatchSchema::new(vec![
+            WatchRow::untagged(vec![("timer", WatchType::Any), ("resolution", WatchType::Number)]),
+        ]))
+    }
     fn on_diff(&mut self, interner:&mut Interner, diff:WatchDiff) {
         for remove in diff.removes {
             if let Entry::Occupied(mut entry) = self.timers.entry(remove[1]) {
@@ -42,7 +89,7 @@ impl Watcher for SystemTimerWatcher {
                         *count -= 1;
                         false
                     } else {
-                        pair.1.send(()).unwrap();
+                        pair.1.send(()).ok();
                         true
                     }
                 };
@@ -59,21 +106,25 @@ impl Watcher for SystemTimerWatcher {
                 continue;
             }
 
-            println!("timer: {:?}", add.iter().map(|v| interner.get_value(*v).print()).collect::<Vec<String>>());
+            log_debug!("system/timer", "Adding timer {:?}", add.iter().map(|v| interner.get_value(*v).print()).collect::<Vec<String>>());
             let internable_resolution = interner.get_value(add[1]).clone();
             let resolution = Internable::to_number(&internable_resolution) as u64;
             let id = Internable::String(format!("system/timer/change/{}", add[0]));
 
-            let duration = Duration::from_millis(resolution);
+            let duration = Duration::from_millis(resolution.max(1));
             let (sender, receiver) = mpsc::channel();
             let outgoing = self.outgoing.clone();
             self.timers.insert(add[1], (1, sender));
 
             thread::spawn(move || {
-                let mut tick = 0;
+                // Ticks are scheduled against when the timer started rather than when the last one
+                // fired, so they don't drift. If we fall more than a tick behind, the missed ticks
+                // are skipped rather than fired all at once.
+                let start = Instant::now();
+                let period = to_nanos(duration);
+                let mut tick:u64 = 0;
                 loop {
-                    thread::sleep(duration);
-                    if receiver.try_recv().is_ok() {
+                    if !wait_until(&receiver, start + from_nanos(period * (tick + 1))) {
                         break;
                     }
                     let cur_time = time::now();
@@ -86,7 +137,7 @@ impl Watcher for SystemTimerWatcher {
                         RawChange {e: id.clone(), a: Internable::String("second".to_string()), v: Internable::from_number(cur_time.tm_sec as f32), n: Internable::String("System/timer".to_string()), count: 1},
                         RawChange {e: id.clone(), a: Internable::String("tick".to_string()), v: Internable::from_number(tick as f32), n: Internable::String("System/timer".to_string()), count: 1},
                     ];
-                    tick += 1;
+                    tick = cmp::max(tick + 1, to_nanos(start.elapsed()) / period);
                     match outgoing.send(RunLoopMessage::Transaction(changes)) {
                         Err(_) => break,
                         _ => {}
@@ -95,6 +146,225 @@ impl Watcher for SystemTimerWatcher {
             });
         }
     }
+    fn on_shutdown(&mut self) {
+        for (_, (_, stop)) in self.timers.drain() {
+            stop.send(()).ok();
+        }
+    }
+}
+
+//-------------------------------------------------------------------------
+// System Schedule Watcher
+//-------------------------------------------------------------------------
+
+// Unlike timers, timeouts and alarms belong to a single record and fire once.
+pub struct SystemScheduleWatcher {
+    name: String,
+    outgoing: Sender<RunLoopMessage>,
+    scheduled: HashMap<Interned, Sender<()>>,
+}
+
+impl SystemScheduleWatcher {
+    pub fn new(outgoing: Sender<RunLoopMessage>) -> SystemScheduleWatcher {
+        SystemScheduleWatcher { name: "system/schedule".to_string(), outgoing, scheduled: HashMap::new() }
+    }
+}
+
+// Alarms are given as UTC times like "2017-08-01T17:30:00Z".
+pub fn parse_alarm_time(at: &str) -> Result<time::Timespec, String> {
+    time::strptime(at, "%Y-%m-%dT%H:%M:%SZ")
+        .map(|tm| tm.to_timespec())
+        .map_err(|why| format!("Invalid alarm time '{}', expected something like '2017-08-01T17:30:00Z': {}", at, why))
+}
+
+fn schedule_changes(kind: &str, record: &Internable, changes: &mut Vec<RawChange>) {
+    let id = Internable::String(format!("system/{}/change/{}", kind, Internable::to_string(record)));
+    let tag = format!("system/{}/change", kind);
+    let n = Internable::String(format!("system/{}", kind));
+    changes.push(RawChange {e: id.clone(), a: Internable::String("tag".to_string()), v: Internable::String(tag), n: n.clone(), count: 1});
+    changes.push(RawChange {e: id.clone(), a: Internable::String(kind.to_string()), v: record.clone(), n: n.clone(), count: 1});
+}
+
+fn schedule_error(record: &Internable, why: String) -> Vec<RawChange> {
+    let id = Internable::String(format!("system/error/{}", Internable::to_string(record)));
+    let n = Internable::String("system/error".to_string());
+    vec![
+        RawChange {e: id.clone(), a: Internable::String("tag".to_string()), v: Internable::String("system/error".to_string()), n: n.clone(), count: 1},
+        RawChange {e: id.clone(), a: Internable::String("message".to_string()), v: Internable::String(why), n: n.clone(), count: 1},
+        RawChange {e: id.clone(), a: Internable::String("record".to_string()), v: record.clone(), n: n.clone(), count: 1},
+    ]
+}
+
+impl Watcher for SystemScheduleWatcher {
+    fn get_name(& self) -> String {
+        self.name.clone()
+    }
+    fn set_name(&mut self, name: &str) {
+        self.name = name.to_string();
+    }
+    fn schema(&self) -> Option<WatchSchema> {
+        Some(WatchSchema::new(vec![
+            WatchRow::new("timeout", vec![("timeout", WatchType::Any), ("duration", WatchType::Number)]),
+            WatchRow::new("alarm", vec![("alarm", WatchType::Any), ("at", WatchType::String)]),
+        ]))
+    }
+    fn on_diff(&mut self, interner:&mut Interner, diff:WatchDiff) {
+        // Dropping the sender cancels whatever hasn't fired yet.
+        for remove in diff.removes {
+            self.scheduled.remove(&remove[1]);
+        }
+
+        for add in diff.adds {
+            let kind = Internable::to_string(interner.get_value(add[0]));
+            let record = interner.get_value(add[1]).clone();
+            let (stop, stopped) = mpsc::channel();
+            let outgoing = self.outgoing.clone();
+            match &kind[..] {
+                "timeout" => {
+                    let delay = Internable::to_number(interner.get_value(add[2])).max(0.0) as u64;
+                    let deadline = Instant::now() + Duration::from_millis(delay);
+                    thread::spawn(move || {
+                        if wait_until(&stopped, deadline) {
+                            let mut changes = vec![];
+                            schedule_changes("timeout", &record, &mut changes);
+                            outgoing.send(RunLoopMessage::Transaction(changes)).ok();
+                        }
+                    });
+                }
+                "alarm" => {
+                    let target = match parse_alarm_time(&Internable::to_string(interner.get_value(add[2]))) {
+                        Ok(target) => target,
+                        Err(why) => {
+                            outgoing.send(RunLoopMessage::Transaction(schedule_error(&record, why))).ok();
+                            continue;
+                        }
+                    };
+                    thread::spawn(move || {
+                        if wait_until_wall_clock(&stopped, target) {
+                            let mut changes = vec![];
+                            schedule_changes("alarm", &record, &mut changes);
+                            outgoing.send(RunLoopMessage::Transaction(changes)).ok();
+                        }
+                    });
+                }
+                _ => continue,
+            }
+            self.scheduled.insert(add[1], stop);
+        }
+    }
+    fn on_shutdown(&mut self) {
+        self.scheduled.clear();
+    }
+}
+
+//-------------------------------------------------------------------------
+// Startup records
+//-------------------------------------------------------------------------
+
+fn startup_change(e: &str, a: &str, v: Internable) -> RawChange {
+    RawChange {e: Internable::String(e.to_string()), a: Internable::String(a.to_string()), v, n: Internable::String("system/startup".to_string()), count: 1}
+}
+
+// A `#system/env` for each environment variable the process was started with.
+pub fn env_changes() -> Vec<RawChange> {
+    let mut changes = vec![];
+    for (name, value) in env::vars_os() {
+        let (name, value) = (name.to_string_lossy().into_owned(), value.to_string_lossy().into_owned());
+        let id = format!("system/env/{}", name);
+        changes.push(startup_change(&id, "tag", Internable::String("system/env".to_string())));
+        changes.push(startup_change(&id, "name", Internable::String(name)));
+        changes.push(startup_change(&id, "value", Internable::String(value)));
+    }
+    changes
+}
+
+// A `#system/args` for each argument, numbered from 1 by `ix`.
+pub fn args_changes(args: &[String]) -> Vec<RawChange> {
+    let mut changes = vec![];
+    for (ix, arg) in args.iter().enumerate() {
+        let id = format!("system/args/{}", ix + 1);
+        changes.push(startup_change(&id, "tag", Internable::String("system/args".to_string())));
+        changes.push(startup_change(&id, "ix", Internable::from_number((ix + 1) as f32)));
+        changes.push(startup_change(&id, "value", Internable::String(arg.to_owned())));
+    }
+    changes
+}
+
+//-------------------------------------------------------------------------
+// System Stdin Watcher
+//-------------------------------------------------------------------------
+
+// Stdin can only be read once, so it starts being read when the first `#system/stdin` shows up
+// and keeps going until it runs out.
+pub struct SystemStdinWatcher {
+    name: String,
+    outgoing: Sender<RunLoopMessage>,
+    started: bool,
+}
+
+impl SystemStdinWatcher {
+    pub fn new(outgoing: Sender<RunLoopMessage>) -> SystemStdinWatcher {
+        SystemStdinWatcher { name: "system/stdin".to_string(), outgoing, started: false }
+    }
+}
+
+fn stdin_line_changes(ix: usize, text: String) -> Vec<RawChange> {
+    let id = Internable::String(format!("system/stdin/line/{}", ix));
+    let n = Internable::String("system/stdin".to_string());
+    vec![
+        RawChange {e: id.clone(), a: Internable::String("tag".to_string()), v: Internable::String("system/stdin/line".to_string()), n: n.clone(), count: 1},
+        RawChange {e: id.clone(), a: Internable::String("ix".to_string()), v: Internable::from_number(ix as f32), n: n.clone(), count: 1},
+        RawChange {e: id.clone(), a: Internable::String("text".to_string()), v: Internable::String(text), n: n.clone(), count: 1},
+    ]
+}
+
+fn stdin_done_changes(count: usize) -> Vec<RawChange> {
+    let id = Internable::String("system/stdin/done".to_string());
+    let n = Internable::String("system/stdin".to_string());
+    vec![
+        RawChange {e: id.clone(), a: Internable::String("tag".to_string()), v: Internable::String("system/stdin/done".to_string()), n: n.clone(), count: 1},
+        RawChange {e: id.clone(), a: Internable::String("count".to_string()), v: Internable::from_number(count as f32), n: n.clone(), count: 1},
+    ]
+}
+
+impl Watcher for SystemStdinWatcher {
+    fn get_name(& self) -> String {
+        self.name.clone()
+    }
+    fn set_name(&mut self, name: &str) {
+        self.name = name.to_string();
+    }
+    fn schema(&self) -> Option<WatchSchema> {
+        Some(WatchSchema::new(vec![
+            WatchRow::new("read", vec![("stdin", WatchType::Any)]),
+        ]))
+    }
+    fn on_diff(&mut self, _:&mut Interner, diff:WatchDiff) {
+        if self.started || diff.adds.len() == 0 { return; }
+        self.started = true;
+        let outgoing = self.outgoing.clone();
+        thread::Builder::new().name("system/stdin".to_owned()).spawn(move || {
+            let stdin = io::stdin();
+            let mut input = stdin.lock();
+            let mut line = vec![];
+            let mut ix = 0;
+            loop {
+                line.clear();
+                match input.read_until(b'\n', &mut line) {
+                    Ok(0) | Err(_) => break,
+                    Ok(_) => {}
+                }
+                while line.last() == Some(&b'\n') || line.last() == Some(&b'\r') {
+                    line.pop();
+                }
+                ix += 1;
+                if outgoing.send(RunLoopMessage::Transaction(stdin_line_changes(ix, String::from_utf8_lossy(&line).into_owned()))).is_err() {
+                    return;
+                }
+            }
+            outgoing.send(RunLoopMessage::Transaction(stdin_done_changes(ix))).ok();
+        }).unwrap();
+    }
 }
 
 //-------------------------------------------------------------------------
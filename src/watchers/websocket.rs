@@ -4,25 +4,266 @@ extern crate serde_json;
 extern crate ws;
 use self::ws::{Sender, Message};
 use super::super::indexes::{WatchDiff};
-use super::super::ops::{Interner, JSONInternable};
+use super::super::ops::{Count, Interner, JSONInternable};
+use super::super::wire::{WireFormat, WireEncoder};
+use std::collections::{HashMap, HashSet};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
 
 use super::{Watcher};
 
+//-------------------------------------------------------------------------
+// Client socket
+//-------------------------------------------------------------------------
+
+// What happens once more than `max_pending` rows are waiting to go out. Either way the pending diff
+// never grows past the cap.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OverflowPolicy {
+    // Diffs are folded into the pending one, where adds and removes of the same row cancel out. At
+    // the cap we try to send it right away instead of waiting for the tick, and only fall back to
+    // a resync if the client can't take it.
+    Merge,
+    // Throw the pending diff away and send the client the full state once it catches up.
+    Drop,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SocketConfig {
+    // How often pending diffs are flushed to the socket.
+    pub tick: Duration,
+    // How many pending rows we'll hold for a client that isn't keeping up.
+    pub max_pending: usize,
+    pub overflow: OverflowPolicy,
+}
+
+impl SocketConfig {
+    pub fn new() -> SocketConfig {
+        SocketConfig { tick: Duration::from_millis(16), max_pending: 10000, overflow: OverflowPolicy::Merge }
+    }
+}
+
+// Where a client's messages go, normally its websocket.
+pub trait SocketSink: Send {
+    fn send_message(&self, message: Message) -> Result<(), String>;
+}
+
+impl SocketSink for Sender {
+    fn send_message(&self, message: Message) -> Result<(), String> {
+        self.send(message).map_err(|why| why.to_string())
+    }
+}
+
+impl SocketSink for mpsc::Sender<Message> {
+    fn send_message(&self, message: Message) -> Result<(), String> {
+        self.send(message).map_err(|_| "The receiving end has gone away".to_string())
+    }
+}
+
+struct SocketState {
+    out: Box<SocketSink>,
+    format: WireFormat,
+    encoder: WireEncoder,
+    client_name: String,
+    config: SocketConfig,
+    // Everything the client should currently have.
+    rows: HashSet<Vec<JSONInternable>>,
+    // The net change since the last flush.
+    pending: HashMap<Vec<JSONInternable>, Count>,
+    // Set when we've dropped diffs and owe the client the full state.
+    resync: bool,
+    failing: bool,
+}
+
+fn diff_message(format: WireFormat, encoder: &mut WireEncoder, client_name: &str, adds: &Vec<&Vec<JSONInternable>>, removes: &Vec<&Vec<JSONInternable>>, reset: bool) -> Message {
+    match format {
+        WireFormat::Json => {
+            let text = serde_json::to_string(&json!({"type": "diff", "adds": adds, "removes": removes, "reset": reset, "client": client_name})).unwrap();
+            Message::Text(text)
+        }
+        WireFormat::Binary => Message::Binary(encoder.diff(client_name, adds, removes, reset)),
+    }
+}
+
+impl SocketState {
+    fn send(&mut self, message: Message) -> bool {
+        match self.out.send_message(message) {
+            Ok(_) => {
+                self.failing = false;
+                true
+            }
+            Err(why) => {
+                if !self.failing {
+                    log_warn!(&self.client_name, "Unable to send, will retry: {}", why);
+                }
+                self.failing = true;
+                false
+            }
+        }
+    }
+
+    fn init(&mut self, session: Option<&str>) -> bool {
+        let text = serde_json::to_string(&json!({"type": "init", "client": &self.client_name, "session": session})).unwrap();
+        self.send(Message::Text(text))
+    }
+
+    // Strings are only committed once the diff carrying them has gone out, so a retry sends them
+    // again.
+    fn send_diff(&mut self, message: Message) -> bool {
+        let sent = self.send(message);
+        if sent { self.encoder.commit(); }
+        sent
+    }
+
+    fn full_state(&mut self, reset: bool) -> bool {
+        let message = {
+            let adds:Vec<&Vec<JSONInternable>> = self.rows.iter().collect();
+            diff_message(self.format, &mut self.encoder, &self.client_name, &adds, &vec![], reset)
+        };
+        self.send_diff(message)
+    }
+
+    fn flush(&mut self) {
+        if self.resync {
+            if self.full_state(true) {
+                self.resync = false;
+            }
+        } else if self.pending.len() > 0 {
+            let message = {
+                let adds:Vec<&Vec<JSONInternable>> = self.pending.iter().filter(|&(_, count)| *count > 0).map(|(row, _)| row).collect();
+                let removes:Vec<&Vec<JSONInternable>> = self.pending.iter().filter(|&(_, count)| *count < 0).map(|(row, _)| row).collect();
+                diff_message(self.format, &mut self.encoder, &self.client_name, &adds, &removes, false)
+            };
+            if self.send_diff(message) {
+                self.pending.clear();
+            }
+        }
+    }
+}
+
+// The socket a client program is talking to along with everything it has been sent so far. Diffs
+// are batched up and flushed once per tick. When a session is resumed on a new connection we swap
+// the socket out and replay the current state.
+#[derive(Clone)]
+pub struct ClientSocket {
+    state: Arc<Mutex<SocketState>>,
+}
+
+impl ClientSocket {
+    pub fn new<S: SocketSink + 'static>(out: S, format: WireFormat, client_name: &str, config: SocketConfig) -> ClientSocket {
+        let state = SocketState { out: Box::new(out), format, encoder: WireEncoder::new(), client_name: client_name.to_owned(), config, rows: HashSet::new(), pending: HashMap::new(), resync: false, failing: false };
+        let socket = ClientSocket { state: Arc::new(Mutex::new(state)) };
+        let weak = Arc::downgrade(&socket.state);
+        thread::Builder::new().name(format!("{} socket", client_name)).spawn(move || {
+            loop {
+                thread::sleep(config.tick);
+                // Once the watcher and any detached session are gone, so are we.
+                match weak.upgrade() {
+                    Some(state) => state.lock().unwrap().flush(),
+                    None => break,
+                }
+            }
+        }).unwrap();
+        socket
+    }
+
+    pub fn init(&self, session: Option<&str>) {
+        self.state.lock().unwrap().init(session);
+    }
+
+    // The new connection starts with a fresh string dictionary, so we replay everything.
+    pub fn attach<S: SocketSink + 'static>(&self, out: S, format: WireFormat, session: Option<&str>) {
+        let mut state = self.state.lock().unwrap();
+        state.out = Box::new(out);
+        state.format = format;
+        state.encoder = WireEncoder::new();
+        state.pending.clear();
+        state.resync = false;
+        state.init(session);
+        if !state.full_state(false) {
+            state.resync = true;
+        }
+    }
+
+    fn diff(&self, adds: Vec<Vec<JSONInternable>>, removes: Vec<Vec<JSONInternable>>) {
+        let mut state = self.state.lock().unwrap();
+        for remove in removes {
+            state.rows.remove(&remove);
+            if !state.resync { *state.pending.entry(remove).or_insert(0) -= 1; }
+        }
+        for add in adds {
+            state.rows.insert(add.clone());
+            if !state.resync { *state.pending.entry(add).or_insert(0) += 1; }
+        }
+        state.pending.retain(|_, count| *count != 0);
+
+        let config = state.config;
+        if state.pending.len() > config.max_pending && config.overflow == OverflowPolicy::Merge {
+            state.flush();
+        }
+        if state.pending.len() > config.max_pending {
+            log_warn!(&state.client_name, "Dropping {} pending rows, the client will be resynced", state.pending.len());
+            state.pending.clear();
+            state.resync = true;
+        }
+    }
+}
+
+//-------------------------------------------------------------------------
+// Client messages
+//-------------------------------------------------------------------------
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum ClientMessage {
+    Block { id:String, code:String },
+    RemoveBlock { id:String },
+    Transaction { client:String, adds: Vec<(JSONInternable, JSONInternable, JSONInternable)>, removes: Vec<(JSONInternable, JSONInternable, JSONInternable)> },
+    Explain { block:String },
+    // Clients batch up everything they send within a frame. Each message is parsed on its own so
+    // that one we don't understand doesn't take the rest down with it.
+    Batch { messages: Vec<serde_json::Value> },
+}
+
+// Batches can't be nested, so a batch inside of a batch is thrown away along with its contents.
+pub fn unbatch(client_name: &str, message: ClientMessage) -> Vec<ClientMessage> {
+    match message {
+        ClientMessage::Batch { messages } => {
+            messages.into_iter().filter_map(|message| {
+                match serde_json::from_value(message) {
+                    Ok(ClientMessage::Batch { .. }) => {
+                        log_warn!(client_name, "Ignoring a nested batch");
+                        None
+                    }
+                    Ok(message) => Some(message),
+                    Err(why) => {
+                        log_debug!(client_name, "Ignoring a batched message: {}", why);
+                        None
+                    }
+                }
+            }).collect()
+        }
+        message => vec![message],
+    }
+}
+
 //-------------------------------------------------------------------------
 // Websocket client watcher
 //-------------------------------------------------------------------------
 
 pub struct WebsocketClientWatcher {
     name: String,
-    outgoing: Sender,
-    client_name: String,
+    socket: ClientSocket,
 }
 
 impl WebsocketClientWatcher {
     pub fn new(outgoing: Sender, client_name: &str) -> WebsocketClientWatcher {
-        let text = serde_json::to_string(&json!({"type": "init", "client": client_name})).unwrap();
-        outgoing.send(Message::Text(text)).unwrap();
-        WebsocketClientWatcher { name: "client/websocket".to_string(), outgoing, client_name: client_name.to_owned() }
+        WebsocketClientWatcher::with_socket(ClientSocket::new(outgoing, WireFormat::Json, client_name, SocketConfig::new()), None)
+    }
+
+    pub fn with_socket(socket: ClientSocket, session: Option<&str>) -> WebsocketClientWatcher {
+        socket.init(session);
+        WebsocketClientWatcher { name: "client/websocket".to_string(), socket }
     }
 }
 
@@ -40,7 +281,6 @@ impl Watcher for WebsocketClientWatcher {
         let removes:Vec<Vec<JSONInternable>> = diff.removes.iter().map(|row| {
             row.iter().map(|v| interner.get_value(*v).into()).collect()
         }).collect();
-        let text = serde_json::to_string(&json!({"type": "diff", "adds": adds, "removes": removes, "client": self.client_name})).unwrap();
-        self.outgoing.send(Message::Text(text)).unwrap();
+        self.socket.diff(adds, removes);
     }
 }
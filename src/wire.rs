@@ -0,0 +1,122 @@
+extern crate bincode;
+
+use ops::{JSONInternable};
+use std::collections::HashMap;
+
+//-------------------------------------------------------------------------
+// Wire format
+//-------------------------------------------------------------------------
+
+// Clients pick a format with the `format` parameter of the websocket handshake. JSON stays the
+// default, `binary` sends diffs and transactions as bincode with a per-connection string dictionary.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WireFormat {
+    Json,
+    Binary,
+}
+
+impl WireFormat {
+    pub fn from_name(name:Option<&str>) -> WireFormat {
+        match name {
+            Some("binary") => WireFormat::Binary,
+            _ => WireFormat::Json,
+        }
+    }
+}
+
+// Strings are sent in full the first time they're seen on a connection and by id after that.
+// Numbers keep the exact bits of their f32.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum WireValue {
+    Null,
+    String(u32),
+    Number(u32),
+}
+
+pub type WireEAV = (WireValue, WireValue, WireValue);
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum WireMessage {
+    Diff { client: String, strings: Vec<(u32, String)>, adds: Vec<Vec<WireValue>>, removes: Vec<Vec<WireValue>>, reset: bool },
+    Transaction { client: String, strings: Vec<(u32, String)>, adds: Vec<WireEAV>, removes: Vec<WireEAV> },
+}
+
+pub struct WireEncoder {
+    ids: HashMap<String, u32>,
+    // Strings that have been given an id but aren't known to have reached the client yet. They're
+    // sent along with every message until one of them goes out, see `commit`.
+    fresh: Vec<(u32, String)>,
+}
+
+impl WireEncoder {
+    pub fn new() -> WireEncoder {
+        WireEncoder { ids: HashMap::new(), fresh: vec![] }
+    }
+
+    pub fn value(&mut self, value:&JSONInternable) -> WireValue {
+        match value {
+            &JSONInternable::Null => WireValue::Null,
+            &JSONInternable::Number(bits) => WireValue::Number(bits),
+            &JSONInternable::String(ref string) => {
+                if let Some(&id) = self.ids.get(string) {
+                    return WireValue::String(id);
+                }
+                let id = self.ids.len() as u32;
+                self.ids.insert(string.to_owned(), id);
+                self.fresh.push((id, string.to_owned()));
+                WireValue::String(id)
+            }
+        }
+    }
+
+    pub fn row(&mut self, row:&Vec<JSONInternable>) -> Vec<WireValue> {
+        row.iter().map(|value| self.value(value)).collect()
+    }
+
+    // Called once a message has been sent, after which its strings are only sent by id.
+    pub fn commit(&mut self) {
+        self.fresh.clear();
+    }
+
+    pub fn diff(&mut self, client:&str, adds:&Vec<&Vec<JSONInternable>>, removes:&Vec<&Vec<JSONInternable>>, reset:bool) -> Vec<u8> {
+        let adds = adds.iter().map(|row| self.row(row)).collect();
+        let removes = removes.iter().map(|row| self.row(row)).collect();
+        let message = WireMessage::Diff { client: client.to_owned(), strings: self.fresh.clone(), adds, removes, reset };
+        bincode::serialize(&message, bincode::Infinite).unwrap()
+    }
+}
+
+pub struct WireDecoder {
+    strings: HashMap<u32, String>,
+}
+
+impl WireDecoder {
+    pub fn new() -> WireDecoder {
+        WireDecoder { strings: HashMap::new() }
+    }
+
+    pub fn decode(&mut self, bytes:&[u8]) -> Result<WireMessage, String> {
+        let message:WireMessage = bincode::deserialize(bytes).map_err(|why| why.to_string())?;
+        match message {
+            WireMessage::Diff { ref strings, .. } |
+            WireMessage::Transaction { ref strings, .. } => {
+                self.strings.extend(strings.iter().cloned());
+            }
+        }
+        Ok(message)
+    }
+
+    pub fn value(&self, value:&WireValue) -> Result<JSONInternable, String> {
+        match value {
+            &WireValue::Null => Ok(JSONInternable::Null),
+            &WireValue::Number(bits) => Ok(JSONInternable::Number(bits)),
+            &WireValue::String(id) => {
+                self.strings.get(&id).map(|string| JSONInternable::String(string.to_owned())).ok_or_else(|| format!("Unknown string id {}", id))
+            }
+        }
+    }
+
+    pub fn eav(&self, eav:&WireEAV) -> Result<(JSONInternable, JSONInternable, JSONInternable), String> {
+        Ok((self.value(&eav.0)?, self.value(&eav.1)?, self.value(&eav.2)?))
+    }
+}
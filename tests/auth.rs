@@ -0,0 +1,70 @@
+extern crate eve;
+
+use eve::auth::*;
+use eve::ops::{RawChange, s};
+use std::collections::HashMap;
+
+fn identity(clients:Vec<&str>, attributes:Vec<&str>) -> Identity {
+    Identity { name: "chris".to_string(), roles: vec![],
+               clients: clients.iter().map(|client| client.to_string()).collect(),
+               attributes: attributes.iter().map(|attribute| attribute.to_string()).collect() }
+}
+
+#[test]
+fn query_param_reads_the_handshake_resource() {
+    assert_eq!(query_param("/?token=abc", "token"), Some("abc".to_string()));
+    assert_eq!(query_param("/?session=1&token=abc", "token"), Some("abc".to_string()));
+    assert_eq!(query_param("/?session=1", "token"), None);
+    assert_eq!(query_param("/", "token"), None);
+}
+
+#[test]
+fn identity_limits_clients() {
+    let me = identity(vec!["self", "shared/*"], vec!["*"]);
+    assert!(me.can_send_to("ws_client_1", "ws_client_1"));
+    assert!(me.can_send_to("ws_client_1", "ws_client_1-editor"));
+    assert!(me.can_send_to("ws_client_1", "shared/chat"));
+    assert!(!me.can_send_to("ws_client_1", "ws_client_2"));
+    assert!(!me.can_send_to("ws_client_1", "server"));
+}
+
+#[test]
+fn nobody_sends_to_the_server_program() {
+    let everything = identity(vec!["*"], vec!["*"]);
+    assert!(everything.can_send_to("ws_client_1", "ws_client_2"));
+    assert!(!everything.can_send_to("ws_client_1", "server"));
+
+    let anonymous = Identity::anonymous();
+    assert!(anonymous.can_send_to("ws_client_1", "ws_client_1-editor"));
+    assert!(!anonymous.can_send_to("ws_client_1", "ws_client_2"));
+    assert!(!anonymous.can_send_to("ws_client_1", "server"));
+}
+
+#[test]
+fn identity_limits_attributes() {
+    let me = identity(vec!["self"], vec!["tag", "html/*"]);
+    assert!(me.can_write("tag"));
+    assert!(me.can_write("html/value"));
+    assert!(!me.can_write("password"));
+}
+
+#[test]
+fn identity_records_are_reserved() {
+    let change = |e:&str, a:&str, v:&str| RawChange { e: s(e), a: s(a), v: s(v), n: s("input"), count: 1 };
+    assert!(is_reserved(&change("client/identity|ws_client_1", "role", "admin")));
+    assert!(is_reserved(&change("client/identity|ws_client_2", "name", "chris")));
+    assert!(is_reserved(&change("sneaky", "tag", "client/identity")));
+    assert!(!is_reserved(&change("message1", "tag", "chat/message")));
+    assert!(!is_reserved(&change("message1", "author", "client/identity|ws_client_1")));
+}
+
+#[test]
+fn token_verifier_checks_tokens() {
+    let mut tokens = HashMap::new();
+    tokens.insert("abc".to_string(), identity(vec!["self"], vec!["*"]));
+    let verifier = TokenVerifier::new(tokens);
+    assert_eq!(verifier.verify(Some("abc")).map(|identity| identity.name), Some("chris".to_string()));
+    assert!(verifier.verify(Some("nope")).is_none());
+    assert!(verifier.verify(None).is_none());
+    assert!(AllowAll.verify(None).is_some());
+}
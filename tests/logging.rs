@@ -0,0 +1,86 @@
+extern crate eve;
+extern crate serde_json;
+extern crate clap;
+
+use eve::logging::*;
+use std::fs;
+use std::io::Read;
+
+fn read(path:&str) -> String {
+    let mut contents = String::new();
+    fs::File::open(path).unwrap().read_to_string(&mut contents).unwrap();
+    contents
+}
+
+fn file_config(path:&str, level:Level, format:LogFormat, max_bytes:u64, keep:usize) -> LogConfig {
+    LogConfig { level, format, sink: LogSink::File { path: path.to_owned(), max_bytes, keep } }
+}
+
+#[test]
+fn log_levels_by_name() {
+    assert_eq!(Level::from_name("warn"), Some(Level::Warn));
+    assert_eq!(Level::from_name("TRACE"), Some(Level::Trace));
+    assert_eq!(Level::from_name("loud"), None);
+    assert!(Level::Error < Level::Debug);
+}
+
+#[test]
+fn log_config_from_flags() {
+    let config = LogConfig::from_flags(Some("debug"), Some("json"), Some("eve.log"), None, Some("2")).unwrap();
+    assert_eq!(config.level, Level::Debug);
+    assert_eq!(config.format, LogFormat::Json);
+    assert_eq!(config.sink, LogSink::File { path: "eve.log".to_owned(), max_bytes: 10000000, keep: 2 });
+    assert_eq!(LogConfig::from_flags(None, None, None, None, None).unwrap().sink, LogSink::Stderr);
+    assert!(LogConfig::from_flags(Some("loud"), None, None, None, None).is_err());
+}
+
+#[test]
+fn log_config_from_shared_args() {
+    let matches = clap::App::new("eve").args(&log_args()).get_matches_from(vec!["eve", "--log-level", "warn", "--log-file", "eve.log", "--log-keep", "3"]);
+    let config = LogConfig::from_matches(&matches).unwrap();
+    assert_eq!(config.level, Level::Warn);
+    assert_eq!(config.sink, LogSink::File { path: "eve.log".to_owned(), max_bytes: 10000000, keep: 3 });
+}
+
+#[test]
+fn log_json_lines_with_fields() {
+    let path = std::env::temp_dir().join("eve-log-json.log");
+    let path = path.to_str().unwrap();
+    fs::remove_file(path).ok();
+    let mut logger = Logger::new(file_config(path, Level::Info, LogFormat::Json, 1_000_000, 1));
+    let fields = vec![("user".to_string(), "chris".to_string()), ("count".to_string(), "3".to_string())];
+    logger.write(&LogRecord { level: Level::Warn, target: "main", message: "hello", fields: &fields }).unwrap();
+    logger.write(&LogRecord { level: Level::Debug, target: "main", message: "too quiet", fields: &[] }).unwrap();
+
+    let contents = read(path);
+    fs::remove_file(path).ok();
+    let lines:Vec<&str> = contents.lines().collect();
+    assert_eq!(lines.len(), 1);
+    let entry:serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+    assert_eq!(entry["level"], "warn");
+    assert_eq!(entry["target"], "main");
+    assert_eq!(entry["message"], "hello");
+    assert_eq!(entry["fields"]["user"], "chris");
+    assert_eq!(entry["fields"]["count"], "3");
+}
+
+#[test]
+fn log_file_rotates() {
+    let path = std::env::temp_dir().join("eve-log-rotate.log");
+    let path = path.to_str().unwrap();
+    let rotated = format!("{}.1", path);
+    fs::remove_file(path).ok();
+    fs::remove_file(&rotated).ok();
+    let mut logger = Logger::new(file_config(path, Level::Info, LogFormat::Text, 100, 1));
+    for ix in 0..5 {
+        logger.write(&LogRecord { level: Level::Info, target: "main", message: &format!("message number {}", ix), fields: &[] }).unwrap();
+    }
+
+    let (current, old) = (read(path), read(&rotated));
+    fs::remove_file(path).ok();
+    fs::remove_file(&rotated).ok();
+    assert!(current.len() <= 100);
+    assert!(current.contains("message number 4"));
+    assert!(old.len() <= 100);
+    assert!(!old.contains("message number 4"));
+}
@@ -2,6 +2,14 @@ extern crate eve;
 
 use eve::ops::*;
 use eve::indexes::{DistinctIter, get_delta};
+use eve::compiler::{parse_string, parse_string_named};
+use eve::watchers::Watcher;
+use eve::indexes::WatchDiff;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Write;
+use std::sync::mpsc;
+use std::time::Duration;
 
 #[test]
 fn test_check_bits() {
@@ -45,3 +53,440 @@ fn round_holder_compute_output_rounds() {
     check_output_rounds(vec![(0,1), (6,-1)], vec![1,0,0,0,0,0,-1], vec![(0,1), (6,-1)]);
     check_output_rounds(vec![(4,-1)], vec![0,0,0,1,-1], vec![]);
 }
+
+#[test]
+fn profile_attributes_pipe_runs_to_blocks() {
+    let mut program = Program::new("test");
+    let blocks = parse_string(&mut program.state.interner, "search\n  [#foo woah]\nbind\n  [#bar baz: woah]\nend\n", "test", false);
+    let mut code = CodeTransaction::new();
+    code.exec(&mut program, blocks, vec![]);
+    program.state.profile = Some(Profile::new());
+
+    let mut iter_pool = EstimateIterPool::new();
+    let e = program.state.interner.string_id("foo1");
+    let tag = program.state.interner.string_id("tag");
+    let foo = program.state.interner.string_id("foo");
+    let woah = program.state.interner.string_id("woah");
+    let value = program.state.interner.number_id(10.0);
+    let mut txn = Transaction::new(&mut iter_pool);
+    txn.input(e, tag, foo, 1);
+    txn.input(e, woah, value, 1);
+    txn.exec(&mut program, &mut None);
+
+    let profile = program.state.profile.as_ref().unwrap();
+    assert!(profile.pipes.values().any(|pipe| pipe.runs > 0));
+    assert!(profile.pipes.values().any(|pipe| pipe.inserts > 0));
+}
+
+#[test]
+fn profile_records_constraint_accepts() {
+    let mut program = Program::new("test");
+    let blocks = parse_string(&mut program.state.interner, "search\n  [#foo woah]\n  woah > 5\nbind\n  [#bar baz: woah]\nend\n", "test", false);
+    let mut code = CodeTransaction::new();
+    code.exec(&mut program, blocks, vec![]);
+    program.state.profile = Some(Profile::new());
+
+    let mut iter_pool = EstimateIterPool::new();
+    let e = program.state.interner.string_id("foo1");
+    let tag = program.state.interner.string_id("tag");
+    let foo = program.state.interner.string_id("foo");
+    let woah = program.state.interner.string_id("woah");
+    let value = program.state.interner.number_id(10.0);
+    let mut txn = Transaction::new(&mut iter_pool);
+    txn.input(e, tag, foo, 1);
+    txn.input(e, woah, value, 1);
+    txn.exec(&mut program, &mut None);
+
+    let profile = program.state.profile.as_ref().unwrap();
+    assert!(profile.constraints.values().any(|constraint| constraint.accepts > 0));
+    let report = profile.report(&program.block_info, &program.state.interner);
+    assert!(report.contains("ms"));
+}
+
+#[test]
+fn constraints_explain_with_register_names() {
+    let mut interner = Interner::new();
+    let tag = interner.string_id("tag");
+    let mut names = HashMap::new();
+    names.insert(0, "person".to_string());
+    let scan = make_scan(Field::Register(0), Field::Value(tag), Field::Register(1));
+    assert_eq!(scan.explain(&interner, &names), "Scan ( person, \"tag\", ?1 )");
+}
+
+#[test]
+fn explain_matches_whole_block_names() {
+    let mut program = Program::new("test");
+    let mut names = HashMap::new();
+    names.insert(1, "foo".to_string());
+    names.insert(2, "barfoo".to_string());
+    let code = "search\n  [#foo woah]\nbind\n  [#bar baz: woah]\nend\n\nsearch\n  [#bar baz]\nbind\n  [#quux baz]\nend\n";
+    for block in parse_string_named(&mut program.state.interner, code, "test", false, &names, &HashMap::new()) {
+        program.register_block(block);
+    }
+
+    let foo = program.explain("foo").unwrap();
+    assert!(foo.starts_with("foo\n"));
+    assert!(!foo.contains("barfoo"));
+    // Registers are explained by the variable they came from.
+    assert!(foo.contains("woah"));
+    assert!(program.explain("barfoo").unwrap().starts_with("barfoo\n"));
+    assert!(program.explain("oo").is_none());
+
+    let mut program = Program::new("test");
+    for block in parse_string(&mut program.state.interner, code, "test", false) {
+        program.register_block(block);
+    }
+    assert!(program.explain("block|2").unwrap().starts_with("test|block|2\n"));
+    assert!(program.explain("lock|2").is_none());
+}
+
+#[test]
+fn runaway_commits_are_rolled_back() {
+    let mut program = Program::new("test");
+    let code = "search\n  f = [#flip]\n  not(f.on)\ncommit\n  f.on := true\nend\n\nsearch\n  f = [#flip on]\ncommit\n  f.on := none\nend\n";
+    let blocks = parse_string(&mut program.state.interner, code, "test", false);
+    let mut code_txn = CodeTransaction::new();
+    code_txn.exec(&mut program, blocks, vec![]);
+    program.state.limits = TransactionLimits { max_rounds: 100, max_frames: 10 };
+
+    let mut iter_pool = EstimateIterPool::new();
+    let e = program.state.interner.string_id("flip1");
+    let tag = program.state.interner.string_id("tag");
+    let flip = program.state.interner.string_id("flip");
+    let mut txn = Transaction::new(&mut iter_pool);
+    txn.input(e, tag, flip, 1);
+    txn.exec(&mut program, &mut None);
+
+    assert!(!program.state.index.check(e, tag, flip));
+    match program.incoming.try_recv() {
+        Ok(RunLoopMessage::Transaction(changes)) => {
+            assert!(changes.iter().any(|change| change.a == Internable::String("tag".to_string()) && change.v == Internable::String("eve/error/runaway".to_string())));
+            let mut blocks:Vec<Internable> = changes.iter().filter(|change| change.a == Internable::String("block".to_string())).map(|change| change.v.clone()).collect();
+            blocks.sort_by(|a, b| a.print().cmp(&b.print()));
+            assert_eq!(blocks, vec![Internable::String("test|block|1".to_string()), Internable::String("test|block|2".to_string())]);
+        }
+        _ => panic!("Expected an error record for the runaway transaction"),
+    }
+}
+
+// Stands in for a block function with a bug in it.
+fn plus_one_unchecked(params: Vec<&Internable>) -> Option<Internable> {
+    Some(Internable::from_number(Internable::to_number(params[0]) + 1.0))
+}
+
+#[test]
+fn block_panics_roll_back_the_transaction() {
+    let mut program = Program::new("test");
+    let code = "search\n  [#foo value]\n  x = math/absolute[value: value]\ncommit\n  [#bar x]\nend\n";
+    for block in parse_string(&mut program.state.interner, code, "test", false) {
+        let mut constraints = block.constraints.clone();
+        for constraint in constraints.iter_mut() {
+            if let &mut Constraint::Function { ref mut func, .. } = constraint {
+                *func = plus_one_unchecked;
+            }
+        }
+        let rebuilt = Block::new(&mut program.state.interner, &block.name, block.block_id, constraints);
+        program.register_block(rebuilt);
+    }
+
+    let mut iter_pool = EstimateIterPool::new();
+    let tag = program.state.interner.string_id("tag");
+    let foo = program.state.interner.string_id("foo");
+    let value = program.state.interner.string_id("value");
+    let (bad, oops) = (program.state.interner.string_id("foo1"), program.state.interner.string_id("oops"));
+    let mut txn = Transaction::new(&mut iter_pool);
+    txn.input(bad, tag, foo, 1);
+    txn.input(bad, value, oops, 1);
+    txn.exec(&mut program, &mut None);
+
+    assert!(!program.state.index.check(bad, tag, foo));
+    assert!(!program.state.index.check(bad, value, oops));
+    match program.incoming.try_recv() {
+        Ok(RunLoopMessage::Transaction(changes)) => {
+            assert!(changes.iter().any(|change| change.a == s("tag") && change.v == s("eve/error/panic")));
+            assert!(changes.iter().any(|change| change.a == s("block") && change.v == s("test|block|1")));
+        }
+        _ => panic!("Expected an error record for the panicking block"),
+    }
+
+    // The program carries on with the next transaction.
+    let (good, ten) = (program.state.interner.string_id("foo2"), program.state.interner.number_id(10.0));
+    let mut txn = Transaction::new(&mut iter_pool);
+    txn.input(good, tag, foo, 1);
+    txn.input(good, value, ten, 1);
+    txn.exec(&mut program, &mut None);
+    assert!(program.state.index.check(good, tag, foo));
+}
+
+#[test]
+fn block_panics_leave_later_transactions_solving_correctly() {
+    let mut program = Program::new("test");
+    let code = "search\n  [#foo value]\n  x = math/absolute[value: value]\ncommit\n  [#bar x]\nend\n";
+    for block in parse_string(&mut program.state.interner, code, "test", false) {
+        let mut constraints = block.constraints.clone();
+        for constraint in constraints.iter_mut() {
+            if let &mut Constraint::Function { ref mut func, .. } = constraint {
+                *func = plus_one_unchecked;
+            }
+        }
+        let rebuilt = Block::new(&mut program.state.interner, &block.name, block.block_id, constraints);
+        program.register_block(rebuilt);
+    }
+
+    let mut iter_pool = EstimateIterPool::new();
+    let tag = program.state.interner.string_id("tag");
+    let foo = program.state.interner.string_id("foo");
+    let bar = program.state.interner.string_id("bar");
+    let value = program.state.interner.string_id("value");
+    let x = program.state.interner.string_id("x");
+    let (bad, oops) = (program.state.interner.string_id("foo1"), program.state.interner.string_id("oops"));
+    let mut txn = Transaction::new(&mut iter_pool);
+    txn.input(bad, tag, foo, 1);
+    txn.input(bad, value, oops, 1);
+    txn.exec(&mut program, &mut None);
+    program.incoming.try_recv().ok();
+
+    // The pool is shared with the transaction that blew up, so this only derives the right
+    // thing if nothing it left behind leaks into the solve.
+    let (good, ten, eleven) = (program.state.interner.string_id("foo2"), program.state.interner.number_id(10.0), program.state.interner.number_id(11.0));
+    let mut txn = Transaction::new(&mut iter_pool);
+    txn.input(good, tag, foo, 1);
+    txn.input(good, value, ten, 1);
+    txn.exec(&mut program, &mut None);
+    let bars:Vec<Interned> = program.state.index.get(0, tag, bar).map(|bars| bars.collect()).unwrap_or(vec![]);
+    assert_eq!(bars.len(), 1);
+    assert!(program.state.index.check(bars[0], x, eleven));
+    assert!(program.incoming.try_recv().is_err());
+}
+
+struct ExplodingWatcher {
+    name: String,
+}
+
+impl Watcher for ExplodingWatcher {
+    fn get_name(&self) -> String {
+        self.name.clone()
+    }
+    fn set_name(&mut self, name: &str) {
+        self.name = name.to_string();
+    }
+    fn on_diff(&mut self, _:&mut Interner, _:WatchDiff) {
+        panic!("boom");
+    }
+}
+
+#[test]
+fn watcher_panics_are_reported_as_errors() {
+    let mut program = Program::new("test");
+    program.attach(Box::new(ExplodingWatcher { name: "explode".to_string() }));
+    let blocks = parse_string(&mut program.state.interner, "search\n  [#foo]\nwatch explode\n  (\"foo\")\nend\n", "test", false);
+    let mut code_txn = CodeTransaction::new();
+    code_txn.exec(&mut program, blocks, vec![]);
+
+    let mut iter_pool = EstimateIterPool::new();
+    let e = program.state.interner.string_id("foo1");
+    let tag = program.state.interner.string_id("tag");
+    let foo = program.state.interner.string_id("foo");
+    let mut txn = Transaction::new(&mut iter_pool);
+    txn.input(e, tag, foo, 1);
+    txn.exec(&mut program, &mut None);
+
+    // The watcher runs after the commit, so the transaction itself sticks.
+    assert!(program.state.index.check(e, tag, foo));
+    match program.incoming.try_recv() {
+        Ok(RunLoopMessage::Transaction(changes)) => {
+            assert!(changes.iter().any(|change| change.a == Internable::String("watcher".to_string()) && change.v == Internable::String("explode".to_string())));
+        }
+        _ => panic!("Expected an error record for the watcher panic"),
+    }
+}
+
+#[test]
+fn reload_keeps_unchanged_blocks() {
+    let path = std::env::temp_dir().join("eve-reload-keeps-unchanged-blocks.eve");
+    let path = path.to_str().unwrap();
+    let foo = "search\n  [#foo]\ncommit\n  [#bar]\nend\n";
+    let baz = "search\n  [#baz]\nbind\n  [#quux]\nend\n";
+    let quux = "search\n  [#quux]\nbind\n  [#zomg]\nend\n";
+
+    let mut program = Program::new("test");
+    let content = format!("{}\n{}", foo, baz);
+    for block in parse_string(&mut program.state.interner, &content, path, false) {
+        program.register_block(block);
+    }
+
+    // Moving the foo block and replacing the baz block should only swap out the latter.
+    File::create(path).unwrap().write_all(format!("{}\n{}", quux, foo).as_bytes()).unwrap();
+    let report = program.reload(path, false);
+    std::fs::remove_file(path).ok();
+
+    assert_eq!(report.unchanged, 1);
+    assert_eq!(report.removed_roots, vec![format!("{}|block|2", path)]);
+    assert_eq!(report.added_roots, vec![format!("{}|block|3", path)]);
+}
+
+#[test]
+fn reload_keeps_blocks_when_the_file_is_unreadable() {
+    let path = std::env::temp_dir().join("eve-reload-keeps-blocks-when-unreadable.eve");
+    let path = path.to_str().unwrap();
+    std::fs::remove_file(path).ok();
+
+    let mut program = Program::new("test");
+    for block in parse_string(&mut program.state.interner, "search\n  [#foo]\ncommit\n  [#bar]\nend\n", path, false) {
+        program.register_block(block);
+    }
+
+    let report = program.reload(path, false);
+    assert!(report.error.is_some());
+    assert!(!report.is_empty());
+    assert_eq!(report.removed.len(), 0);
+    assert_eq!(report.unchanged, 1);
+    assert!(report.to_raw_changes().iter().any(|change| change.a == s("error")));
+}
+
+fn foo(entity:&str) -> RunLoopMessage {
+    RunLoopMessage::Transaction(vec![RawChange { e: s(entity), a: s("tag"), v: s("foo"), n: s("test"), count: 1 }])
+}
+
+#[test]
+fn pause_queue_overflow() {
+    let mut newest = PauseQueue::new(PauseLimits { max_queued: 2, overflow: PauseOverflow::DropNewest });
+    let mut oldest = PauseQueue::new(PauseLimits { max_queued: 2, overflow: PauseOverflow::DropOldest });
+    for entity in vec!["foo1", "foo2", "foo3"] {
+        newest.push(foo(entity));
+        oldest.push(foo(entity));
+    }
+    assert_eq!((newest.len(), newest.dropped()), (2, 1));
+    match (newest.pop(), oldest.pop()) {
+        (Some(RunLoopMessage::Transaction(kept)), Some(RunLoopMessage::Transaction(shifted))) => {
+            assert_eq!(kept[0].e, s("foo1"));
+            assert_eq!(shifted[0].e, s("foo2"));
+        }
+        _ => panic!("Expected queued transactions"),
+    }
+}
+
+struct CountingWatcher {
+    name: String,
+    outgoing: mpsc::Sender<usize>,
+}
+
+impl Watcher for CountingWatcher {
+    fn get_name(&self) -> String {
+        self.name.clone()
+    }
+    fn set_name(&mut self, name: &str) {
+        self.name = name.to_string();
+    }
+    fn on_diff(&mut self, _:&mut Interner, diff:WatchDiff) {
+        if diff.adds.len() > 0 {
+            self.outgoing.send(diff.adds.len()).unwrap();
+        }
+    }
+}
+
+#[test]
+fn paused_programs_queue_and_step_transactions() {
+    let (outgoing, incoming) = mpsc::channel();
+    let mut runner = ProgramRunner::new("test");
+    runner.program.attach(Box::new(CountingWatcher { name: "count".to_string(), outgoing }));
+    for block in parse_string(&mut runner.program.state.interner, "search\n  foo = [#foo]\nwatch count\n  (\"foo\", foo)\nend\n", "test", false) {
+        runner.program.register_block(block);
+    }
+    let running = runner.run();
+
+    running.send(RunLoopMessage::Pause);
+    running.send(foo("foo1"));
+    running.send(foo("foo2"));
+    assert!(incoming.recv_timeout(Duration::from_millis(200)).is_err());
+
+    running.send(RunLoopMessage::Step);
+    assert_eq!(incoming.recv_timeout(Duration::from_secs(5)), Ok(1));
+    assert!(incoming.recv_timeout(Duration::from_millis(200)).is_err());
+
+    running.send(RunLoopMessage::Resume);
+    assert_eq!(incoming.recv_timeout(Duration::from_secs(5)), Ok(1));
+    running.close();
+}
+
+#[test]
+fn paused_programs_queue_reloads_in_order() {
+    let dir = std::env::temp_dir().canonicalize().unwrap();
+    let path = dir.join("eve-paused-programs-queue-reloads.eve");
+    let path_str = path.to_str().unwrap().to_owned();
+    let watch = |tag:&str| format!("search\n  {} = [#{}]\nwatch count\n  (\"{}\", {})\nend\n", tag, tag, tag, tag);
+    File::create(&path).unwrap().write_all(watch("foo").as_bytes()).unwrap();
+
+    let (outgoing, incoming) = mpsc::channel();
+    let mut runner = ProgramRunner::new("test");
+    runner.program.attach(Box::new(CountingWatcher { name: "count".to_string(), outgoing }));
+    for block in parse_string(&mut runner.program.state.interner, &watch("foo"), &path_str, false) {
+        runner.program.register_block(block);
+    }
+    let running = runner.run();
+
+    // The foo transaction has to see the old code and the bar one the new code.
+    running.send(RunLoopMessage::Pause);
+    running.send(foo("foo1"));
+    File::create(&path).unwrap().write_all(watch("bar").as_bytes()).unwrap();
+    running.send(RunLoopMessage::Reload(vec![path.clone()].into_iter().collect()));
+    running.send(RunLoopMessage::Transaction(vec![RawChange { e: s("bar1"), a: s("tag"), v: s("bar"), n: s("test"), count: 1 }]));
+    assert!(incoming.recv_timeout(Duration::from_millis(200)).is_err());
+
+    running.send(RunLoopMessage::Resume);
+    assert_eq!(incoming.recv_timeout(Duration::from_secs(5)), Ok(1));
+    assert_eq!(incoming.recv_timeout(Duration::from_secs(5)), Ok(1));
+    running.close();
+    std::fs::remove_file(&path).ok();
+}
+
+struct LifecycleWatcher {
+    name: String,
+    events: mpsc::Sender<String>,
+}
+
+impl Watcher for LifecycleWatcher {
+    fn get_name(&self) -> String {
+        self.name.clone()
+    }
+    fn set_name(&mut self, name: &str) {
+        self.name = name.to_string();
+    }
+    fn on_diff(&mut self, _:&mut Interner, diff:WatchDiff) {
+        self.events.send(format!("diff {}", diff.adds.len())).unwrap();
+    }
+    fn on_attach(&mut self, _:&mut Interner, _:mpsc::Sender<RunLoopMessage>, current:WatchDiff) {
+        self.events.send(format!("attach {}", current.adds.len())).unwrap();
+    }
+    fn on_shutdown(&mut self) {
+        self.events.send("shutdown".to_string()).unwrap();
+    }
+}
+
+#[test]
+fn watchers_attach_and_detach_while_running() {
+    let (events, incoming) = mpsc::channel();
+    let mut runner = ProgramRunner::new("test");
+    for block in parse_string(&mut runner.program.state.interner, "search\n  foo = [#foo]\nwatch lifecycle\n  (\"foo\", foo)\nend\n", "test", false) {
+        runner.program.register_block(block);
+    }
+    let running = runner.run();
+    let watcher = || Box::new(LifecycleWatcher { name: "lifecycle".to_string(), events: events.clone() });
+    let next = || incoming.recv_timeout(Duration::from_secs(5)).unwrap();
+
+    running.send(foo("foo1"));
+    running.send(RunLoopMessage::AttachWatcher(watcher()));
+    assert_eq!(next(), "attach 1");
+    running.send(foo("foo2"));
+    assert_eq!(next(), "diff 1");
+
+    running.send(RunLoopMessage::DetachWatcher("lifecycle".to_string()));
+    assert_eq!(next(), "shutdown");
+    running.send(foo("foo3"));
+    running.send(RunLoopMessage::AttachWatcher(watcher()));
+    assert_eq!(next(), "attach 3");
+
+    running.close();
+    assert_eq!(next(), "shutdown");
+    running.wait();
+}
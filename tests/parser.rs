@@ -3,6 +3,9 @@ use eve::ops::{Program};
 use eve::compiler::*;
 use eve::parser::*;
 use eve::combinators::*;
+use eve::watchers::{WatchSchema, WatchSchemas, WatchRow, WatchType};
+use eve::watchers::console::ConsoleWatcher;
+use std::collections::HashMap;
 
 //--------------------------------------------------------------------
 // Helper macros
@@ -54,6 +57,40 @@ test!(parse_error_empty_search, {
     end
 });
 
+//--------------------------------------------------------------------
+// Watch schemas
+//--------------------------------------------------------------------
+
+fn compile_watch(program:&mut Program, row:&str, schemas:&WatchSchemas) -> usize {
+    let code = format!("search\n  r = [#r]\nwatch test/schema\n  {}\nend\n", row);
+    parse_string_named(&mut program.state.interner, &code, "test", false, &HashMap::new(), schemas).len()
+}
+
+#[test]
+fn watch_rows_are_checked_against_schemas() {
+    let mut schemas = WatchSchemas::new();
+    schemas.insert("test/schema".to_string(), WatchSchema::new(vec![
+        WatchRow::new("send", vec![("record", WatchType::Any), ("count", WatchType::Number)]),
+    ]));
+    let mut program = Program::new("schema test");
+    assert_eq!(compile_watch(&mut program, "(\"send\", r, 3)", &schemas), 1);
+    assert_eq!(compile_watch(&mut program, "(\"send\", r, r)", &schemas), 1);
+    assert_eq!(compile_watch(&mut program, "(\"send\", r, \"three\")", &schemas), 0);
+    assert_eq!(compile_watch(&mut program, "(\"send\", r)", &schemas), 0);
+    assert_eq!(compile_watch(&mut program, "(\"sned\", r, 3)", &schemas), 0);
+    // Without a schema the watcher gets whatever it's sent.
+    assert_eq!(compile_watch(&mut program, "(\"send\", r)", &WatchSchemas::new()), 1);
+}
+
+#[test]
+fn watch_schemas_come_from_attached_watchers() {
+    let mut program = Program::new("schema test");
+    assert!(program.watch_schemas().get("console").is_none());
+    program.attach(Box::new(ConsoleWatcher::new()));
+    assert!(program.watch_schemas().get("console").is_some());
+    assert!(program.detach("console"));
+    assert!(program.watch_schemas().get("console").is_none());
+}
 
 #[test]
 pub fn parser_combinator() {
@@ -0,0 +1,69 @@
+extern crate eve;
+
+use eve::ops::*;
+use eve::record::{read_log, replay, RecordedMessage};
+use std::fs::{self, File};
+use std::io::Write;
+
+#[test]
+fn replaying_a_recording_rebuilds_the_index() {
+    let dir = std::env::temp_dir();
+    let source = dir.join("eve-record-source.eve");
+    let log = dir.join("eve-record-log.jsonl");
+    let (source, log) = (source.to_str().unwrap(), log.to_str().unwrap());
+    File::create(source).unwrap().write_all(b"search\n  foo = [#foo]\ncommit\n  foo.seen := \"yes\"\nend\n").unwrap();
+
+    let mut runner = ProgramRunner::new("test");
+    runner.load(source);
+    runner.record(log).unwrap();
+    let running = runner.run();
+    running.send(RunLoopMessage::Pause);
+    running.send(RunLoopMessage::Transaction(vec![RawChange { e: s("foo1"), a: s("tag"), v: s("foo"), n: s("test"), count: 1 }]));
+    running.send(RunLoopMessage::Resume);
+    running.send(RunLoopMessage::Transaction(vec![RawChange { e: s("foo2"), a: s("tag"), v: s("foo"), n: s("test"), count: 1 }]));
+    running.close();
+    running.wait();
+
+    let entries = read_log(log).unwrap();
+    fs::remove_file(source).ok();
+    fs::remove_file(log).ok();
+    assert_eq!(entries.len(), 3);
+    match entries[0].message {
+        RecordedMessage::Load { ref blocks, .. } => assert_eq!(blocks.len(), 1),
+        ref other => panic!("Expected a load, got {:?}", other),
+    }
+    assert!(entries[1..].iter().all(|entry| match entry.message { RecordedMessage::Transaction(_) => true, _ => false }));
+    assert!(entries.windows(2).all(|pair| pair[0].time <= pair[1].time));
+
+    let mut program = Program::new("replay");
+    replay(&mut program, entries);
+    let (seen, yes) = (program.state.interner.string_id("seen"), program.state.interner.string_id("yes"));
+    for entity in vec!["foo1", "foo2"] {
+        let entity = program.state.interner.string_id(entity);
+        assert!(program.state.index.check(entity, seen, yes));
+    }
+}
+
+#[test]
+fn replaying_a_recording_applies_its_limits() {
+    let log = std::env::temp_dir().join("eve-record-limits.jsonl");
+    let log = log.to_str().unwrap();
+    let limits = TransactionLimits { max_rounds: 7, max_frames: 3 };
+
+    let mut runner = ProgramRunner::new("test");
+    runner.limits(limits);
+    runner.record(log).unwrap();
+    let running = runner.run();
+    running.close();
+    running.wait();
+
+    let entries = read_log(log).unwrap();
+    fs::remove_file(log).ok();
+    match entries[0].message {
+        RecordedMessage::Load { limits: recorded, .. } => assert_eq!(recorded, limits),
+        ref other => panic!("Expected a load, got {:?}", other),
+    }
+    let mut program = Program::new("replay");
+    replay(&mut program, entries);
+    assert_eq!(program.state.limits, limits);
+}
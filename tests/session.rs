@@ -0,0 +1,78 @@
+extern crate eve;
+extern crate serde_json;
+extern crate ws;
+
+use eve::ops::Interner;
+use eve::indexes::WatchDiff;
+use eve::session::Sessions;
+use eve::watchers::Watcher;
+use eve::watchers::websocket::{ClientSocket, SocketConfig, WebsocketClientWatcher};
+use eve::wire::WireFormat;
+use std::sync::mpsc;
+use std::time::Duration;
+use ws::Message;
+
+fn next_message(incoming:&mpsc::Receiver<Message>) -> serde_json::Value {
+    match incoming.recv_timeout(Duration::from_secs(5)) {
+        Ok(Message::Text(text)) => serde_json::from_str(&text).unwrap(),
+        other => panic!("Expected a text message, got {:?}", other),
+    }
+}
+
+fn config() -> SocketConfig {
+    let mut config = SocketConfig::new();
+    config.tick = Duration::from_millis(5);
+    config
+}
+
+#[test]
+fn resumed_sessions_replay_their_state() {
+    let (first, first_incoming) = mpsc::channel();
+    let socket = ClientSocket::new(first, WireFormat::Json, "ws_client_1", config());
+    let mut watcher = WebsocketClientWatcher::with_socket(socket.clone(), Some("abc"));
+    assert_eq!(next_message(&first_incoming)["session"], "abc");
+
+    let mut interner = Interner::new();
+    let row = vec![interner.string_id("html/element"), interner.string_id("div1")];
+    watcher.on_diff(&mut interner, WatchDiff { adds: vec![row], removes: vec![] });
+    let diff = next_message(&first_incoming);
+    assert_eq!(diff["adds"], json_rows());
+
+    let sessions = Sessions::new(Duration::from_secs(60));
+    sessions.detach("abc", "chris", socket, |_| panic!("The session shouldn't expire"));
+    assert!(sessions.resume("abc", "mallory").is_none());
+    assert!(sessions.resume("nope", "chris").is_none());
+    let socket = sessions.resume("abc", "chris").unwrap();
+    assert_eq!(sessions.len(), 0);
+
+    let (second, second_incoming) = mpsc::channel();
+    socket.attach(second, WireFormat::Json, Some("abc"));
+    let init = next_message(&second_incoming);
+    assert_eq!(init["type"], "init");
+    assert_eq!(init["session"], "abc");
+    let replay = next_message(&second_incoming);
+    assert_eq!(replay["type"], "diff");
+    assert_eq!(replay["adds"], json_rows());
+}
+
+fn json_rows() -> serde_json::Value {
+    serde_json::from_str(r#"[["html/element", "div1"]]"#).unwrap()
+}
+
+#[test]
+fn detached_sessions_expire_after_the_grace_period() {
+    let sessions = Sessions::new(Duration::from_millis(50));
+    let (expired, expirations) = mpsc::channel();
+
+    let expire = expired.clone();
+    sessions.detach("old", "chris", "old program", move |session| expire.send(session).unwrap());
+    assert_eq!(expirations.recv_timeout(Duration::from_secs(5)), Ok("old program"));
+    assert!(sessions.resume("old", "chris").is_none());
+
+    // Resuming within the grace period keeps the session from being torn down.
+    let expire = expired.clone();
+    sessions.detach("kept", "chris", "kept program", move |session| expire.send(session).unwrap());
+    assert_eq!(sessions.resume("kept", "chris"), Some("kept program"));
+    assert!(expirations.recv_timeout(Duration::from_millis(200)).is_err());
+    assert!(!Sessions::<()>::new(Duration::from_secs(0)).resumable());
+}
@@ -0,0 +1,883 @@
+extern crate eve;
+
+use eve::ops::*;
+use eve::indexes::WatchDiff;
+use eve::watchers::Watcher;
+use eve::indexes::RawRemoteChange;
+use eve::watchers::file::FileWatcher;
+use eve::watchers::http::{HttpWatcher, HttpRoutes};
+use eve::watchers::process::ProcessWatcher;
+use eve::watchers::plugin::{PluginWatcher, load_plugin, plugin_version};
+use eve::watchers::system::{SystemTimerWatcher, SystemScheduleWatcher, parse_alarm_time, env_changes, args_changes};
+use eve::watchers::remote::{Router, RouterMessage, PeerMessage};
+use std::fs;
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+//-------------------------------------------------------------------------
+// Helpers
+//-------------------------------------------------------------------------
+
+fn find_value(changes:&Vec<RawChange>, attribute:&str) -> Option<Internable> {
+    changes.iter().find(|change| change.a == Internable::String(attribute.to_string())).map(|change| change.v.clone())
+}
+
+// The next transaction a watcher sends back to its program.
+fn next_transaction(incoming:&mpsc::Receiver<RunLoopMessage>) -> Vec<RawChange> {
+    match incoming.recv_timeout(Duration::from_secs(5)) {
+        Ok(RunLoopMessage::Transaction(changes)) => changes,
+        Ok(_) => panic!("Expected a transaction, got some other message"),
+        Err(_) => panic!("Timed out waiting for a transaction"),
+    }
+}
+
+//-------------------------------------------------------------------------
+// Http
+//-------------------------------------------------------------------------
+
+#[test]
+fn http_request_reports_response() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/hello", listener.local_addr().unwrap());
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut buffer = [0; 1024];
+        stream.read(&mut buffer).unwrap();
+        stream.write_all(b"HTTP/1.1 201 Created\r\nContent-Length: 5\r\nX-Stub: yes\r\n\r\nhello").unwrap();
+    });
+
+    let (outgoing, incoming) = mpsc::channel();
+    let mut watcher = HttpWatcher::new(outgoing);
+    let mut interner = Interner::new();
+    let request = vec![interner.string_id("request"), interner.string_id("my-request"), interner.string_id("POST"),
+                       interner.string_id(&url), interner.string_id("ping")];
+    watcher.on_diff(&mut interner, WatchDiff { adds: vec![request], removes: vec![] });
+
+    match incoming.recv_timeout(Duration::from_secs(5)) {
+        Ok(RunLoopMessage::Transaction(changes)) => {
+            assert_eq!(find_value(&changes, "status"), Some(Internable::from_number(201.0)));
+            assert_eq!(find_value(&changes, "body"), Some(Internable::String("hello".to_string())));
+            assert_eq!(find_value(&changes, "request"), Some(Internable::String("my-request".to_string())));
+            assert!(changes.iter().any(|change| change.a == Internable::String("value".to_string()) && change.v == Internable::String("yes".to_string())));
+        }
+        _ => panic!("Expected a response transaction"),
+    }
+}
+
+#[test]
+fn http_request_reports_errors() {
+    let (outgoing, incoming) = mpsc::channel();
+    let mut watcher = HttpWatcher::new(outgoing);
+    let mut interner = Interner::new();
+    let request = vec![interner.string_id("request"), interner.string_id("my-request"), interner.string_id("GET"),
+                       interner.string_id("not a url"), interner.string_id("")];
+    watcher.on_diff(&mut interner, WatchDiff { adds: vec![request], removes: vec![] });
+
+    match incoming.recv_timeout(Duration::from_secs(5)) {
+        Ok(RunLoopMessage::Transaction(changes)) => {
+            assert_eq!(find_value(&changes, "tag"), Some(Internable::String("http/error".to_string())));
+        }
+        _ => panic!("Expected an error transaction"),
+    }
+}
+
+#[test]
+fn http_request_times_out() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/slow", listener.local_addr().unwrap());
+    // Accepts the request and then sits on it.
+    thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        thread::sleep(Duration::from_secs(5));
+        drop(stream);
+    });
+
+    let (outgoing, incoming) = mpsc::channel();
+    let mut watcher = HttpWatcher::with_timeout(outgoing, Duration::from_millis(100));
+    let mut interner = Interner::new();
+    let request = vec![interner.string_id("request"), interner.string_id("my-request"), interner.string_id("GET"),
+                       interner.string_id(&url), interner.string_id("")];
+    watcher.on_diff(&mut interner, WatchDiff { adds: vec![request], removes: vec![] });
+
+    match incoming.recv_timeout(Duration::from_secs(3)) {
+        Ok(RunLoopMessage::Transaction(changes)) => {
+            assert_eq!(find_value(&changes, "tag"), Some(Internable::String("http/error".to_string())));
+            assert_eq!(find_value(&changes, "message"), Some(Internable::String("request timed out".to_string())));
+        }
+        _ => panic!("Expected a timeout error"),
+    }
+}
+
+#[test]
+fn http_routes_wait_for_a_response() {
+    let (outgoing, incoming) = mpsc::channel();
+    let routes = HttpRoutes::new(outgoing, Duration::from_secs(5));
+    let mut watcher = routes.watcher();
+    let handler = routes.clone();
+    let waiting = thread::spawn(move || handler.handle("GET", "/hello", None, vec![], "".to_string()));
+
+    let request = match incoming.recv_timeout(Duration::from_secs(5)) {
+        Ok(RunLoopMessage::Transaction(changes)) => {
+            assert_eq!(find_value(&changes, "path"), Some(Internable::String("/hello".to_string())));
+            changes[0].e.clone()
+        }
+        _ => panic!("Expected a request transaction"),
+    };
+
+    let mut interner = Interner::new();
+    let request = interner.internable_to_id(request);
+    let response = vec![interner.string_id("response"), request, interner.number_id(201.0), interner.string_id("hi")];
+    watcher.on_diff(&mut interner, WatchDiff { adds: vec![response], removes: vec![] });
+
+    let response = waiting.join().unwrap();
+    assert_eq!(response.status, 201);
+    assert_eq!(response.body, "hi");
+    // Once answered, the request is taken back out of the program.
+    match incoming.recv_timeout(Duration::from_secs(5)) {
+        Ok(RunLoopMessage::Transaction(changes)) => assert!(changes.iter().all(|change| change.count == -1)),
+        _ => panic!("Expected the request to be removed"),
+    }
+}
+
+#[test]
+fn http_routes_reject_invalid_statuses() {
+    let (outgoing, incoming) = mpsc::channel();
+    let routes = HttpRoutes::new(outgoing, Duration::from_secs(5));
+    let mut watcher = routes.watcher();
+    let handler = routes.clone();
+    let waiting = thread::spawn(move || handler.handle("GET", "/hello", None, vec![], "".to_string()));
+    let request = next_transaction(&incoming)[0].e.clone();
+
+    let mut interner = Interner::new();
+    let request = interner.internable_to_id(request);
+    let response = vec![interner.string_id("response"), request, interner.string_id("ok"), interner.string_id("hi")];
+    watcher.on_diff(&mut interner, WatchDiff { adds: vec![response], removes: vec![] });
+    let response = waiting.join().unwrap();
+    assert_eq!(response.status, 500);
+    let error = next_transaction(&incoming);
+    assert_eq!(find_value(&error, "tag"), Some(s("http/server/error")));
+}
+
+#[test]
+fn http_routes_remove_only_the_matching_header() {
+    let (outgoing, incoming) = mpsc::channel();
+    let routes = HttpRoutes::new(outgoing, Duration::from_secs(5));
+    let mut watcher = routes.watcher();
+    let handler = routes.clone();
+    let waiting = thread::spawn(move || handler.handle("GET", "/hello", None, vec![], "".to_string()));
+    let request = next_transaction(&incoming)[0].e.clone();
+
+    let mut interner = Interner::new();
+    let request = interner.internable_to_id(request);
+    let header = |interner:&mut Interner, name:&str| vec![interner.string_id("header"), request, interner.string_id(name), interner.string_id("yes")];
+    let (kept, removed) = (header(&mut interner, "X-Kept"), header(&mut interner, "X-Removed"));
+    watcher.on_diff(&mut interner, WatchDiff { adds: vec![kept, removed.clone()], removes: vec![] });
+    watcher.on_diff(&mut interner, WatchDiff { adds: vec![], removes: vec![removed] });
+
+    let response = vec![interner.string_id("response"), request, interner.number_id(200.0), interner.string_id("hi")];
+    watcher.on_diff(&mut interner, WatchDiff { adds: vec![response], removes: vec![] });
+    let response = waiting.join().unwrap();
+    assert_eq!(response.status, 200);
+    assert_eq!(response.headers, vec![("X-Kept".to_string(), "yes".to_string())]);
+}
+
+#[test]
+fn http_routes_time_out() {
+    let (outgoing, _incoming) = mpsc::channel();
+    let routes = HttpRoutes::new(outgoing, Duration::from_millis(10));
+    let response = routes.handle("GET", "/nobody-home", None, vec![], "".to_string());
+    assert_eq!(response.status, 504);
+}
+
+//-------------------------------------------------------------------------
+// File
+//-------------------------------------------------------------------------
+
+fn file_diff(interner:&mut Interner, row:Vec<&str>) -> WatchDiff {
+    WatchDiff { adds: vec![row.iter().map(|value| interner.string_id(value)).collect()], removes: vec![] }
+}
+
+#[test]
+fn file_append_stat_list_and_delete() {
+    let dir = std::env::temp_dir().join("eve-file-watcher-ops");
+    fs::remove_dir_all(&dir).ok();
+    fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("log.txt");
+    let (dir, file) = (dir.to_str().unwrap(), file.to_str().unwrap());
+
+    let (outgoing, incoming) = mpsc::channel();
+    let mut watcher = FileWatcher::new(outgoing);
+    let mut interner = Interner::new();
+    for _ in 0..2 {
+        let diff = file_diff(&mut interner, vec!["append", "my-append", file, "hello\n"]);
+        watcher.on_diff(&mut interner, diff);
+        assert!(next_transaction(&incoming).is_empty());
+    }
+    let mut contents = String::new();
+    fs::File::open(file).unwrap().read_to_string(&mut contents).unwrap();
+    assert_eq!(contents, "hello\nhello\n");
+
+    let diff = file_diff(&mut interner, vec!["stat", "my-stat", file]);
+    watcher.on_diff(&mut interner, diff);
+    let changes = next_transaction(&incoming);
+    assert_eq!(find_value(&changes, "tag"), Some(s("file/stat/change")));
+    assert_eq!(find_value(&changes, "kind"), Some(s("file")));
+    assert_eq!(find_value(&changes, "size"), Some(s("12")));
+    assert!(find_value(&changes, "modified").is_some());
+
+    let diff = file_diff(&mut interner, vec!["list", "my-list", dir]);
+    watcher.on_diff(&mut interner, diff);
+    let changes = next_transaction(&incoming);
+    assert_eq!(find_value(&changes, "name"), Some(s("log.txt")));
+    assert_eq!(find_value(&changes, "count"), Some(Internable::from_number(1.0)));
+
+    let diff = file_diff(&mut interner, vec!["delete", "my-delete", file]);
+    watcher.on_diff(&mut interner, diff);
+    assert!(next_transaction(&incoming).is_empty());
+    assert!(!std::path::Path::new(file).exists());
+
+    let diff = file_diff(&mut interner, vec!["stat", "my-missing-stat", file]);
+    watcher.on_diff(&mut interner, diff);
+    let changes = next_transaction(&incoming);
+    assert_eq!(find_value(&changes, "tag"), Some(s("file/error")));
+    assert_eq!(find_value(&changes, "file"), Some(s("my-missing-stat")));
+    fs::remove_dir_all(dir).ok();
+}
+
+#[test]
+fn file_watch_reports_changes_until_removed() {
+    let dir = std::env::temp_dir().join("eve-file-watcher-watch");
+    fs::remove_dir_all(&dir).ok();
+    fs::create_dir_all(&dir).unwrap();
+    let dir = dir.to_str().unwrap();
+
+    let (outgoing, incoming) = mpsc::channel();
+    let mut watcher = FileWatcher::new(outgoing);
+    let mut interner = Interner::new();
+    let diff = file_diff(&mut interner, vec!["watch", "my-watch", dir]);
+    let row = diff.adds[0].clone();
+    watcher.on_diff(&mut interner, diff);
+    assert!(next_transaction(&incoming).is_empty());
+
+    thread::sleep(Duration::from_millis(500));
+    fs::File::create(format!("{}/dropped.txt", dir)).unwrap();
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        assert!(Instant::now() < deadline, "Expected a change for the dropped file");
+        let changes = next_transaction(&incoming);
+        if find_value(&changes, "kind") == Some(s("create")) {
+            assert_eq!(find_value(&changes, "file"), Some(s("my-watch")));
+            assert!(find_value(&changes, "path").map_or(false, |path| Internable::to_string(&path).ends_with("dropped.txt")));
+            break;
+        }
+    }
+
+    watcher.on_diff(&mut interner, WatchDiff { adds: vec![], removes: vec![row] });
+    thread::sleep(Duration::from_millis(500));
+    while incoming.try_recv().is_ok() {}
+    fs::File::create(format!("{}/ignored.txt", dir)).unwrap();
+    assert!(incoming.recv_timeout(Duration::from_secs(1)).is_err());
+    fs::remove_dir_all(dir).ok();
+}
+
+// Streamed reads happen on their own thread, so they can arrive before or after the watcher's own
+// empty transaction.
+fn stream_transaction(incoming:&mpsc::Receiver<RunLoopMessage>) -> Vec<RawChange> {
+    loop {
+        let changes = next_transaction(incoming);
+        if changes.len() > 0 { return changes; }
+    }
+}
+
+fn values(changes:&Vec<RawChange>, attribute:&str) -> Vec<Internable> {
+    changes.iter().filter(|change| change.a == s(attribute)).map(|change| change.v.clone()).collect()
+}
+
+#[test]
+fn file_read_lines_streams_each_line() {
+    let path = std::env::temp_dir().join("eve-file-read-lines.txt");
+    let path = path.to_str().unwrap();
+    fs::File::create(path).unwrap().write_all(b"first\r\nsecond\ncaf\xe9").unwrap();
+
+    let (outgoing, incoming) = mpsc::channel();
+    let mut watcher = FileWatcher::new(outgoing);
+    let mut interner = Interner::new();
+    let diff = file_diff(&mut interner, vec!["read-lines", "my-lines", path, "latin1"]);
+    watcher.on_diff(&mut interner, diff);
+    let changes = stream_transaction(&incoming);
+    assert_eq!(values(&changes, "text"), vec![s("first"), s("second"), s("café")]);
+    assert_eq!(values(&changes, "offset"), vec![s("0"), s("7"), s("14")]);
+    assert_eq!(find_value(&changes, "count"), Some(s("3")));
+
+    let diff = file_diff(&mut interner, vec!["read-lines", "my-strict-lines", path, "utf-8"]);
+    watcher.on_diff(&mut interner, diff);
+    let mut changes = stream_transaction(&incoming);
+    if find_value(&changes, "tag") == Some(s("file/line")) {
+        changes = stream_transaction(&incoming);
+    }
+    assert_eq!(find_value(&changes, "tag"), Some(s("file/error")));
+    fs::remove_file(path).ok();
+}
+
+#[test]
+fn file_read_chunks_keep_characters_whole() {
+    let path = std::env::temp_dir().join("eve-file-read-chunks.txt");
+    let path = path.to_str().unwrap();
+    fs::File::create(path).unwrap().write_all("aé€z".as_bytes()).unwrap();
+
+    let (outgoing, incoming) = mpsc::channel();
+    let mut watcher = FileWatcher::new(outgoing);
+    let mut interner = Interner::new();
+    let mut diff = file_diff(&mut interner, vec!["read-chunks", "my-chunks", path]);
+    diff.adds[0].push(interner.number_id(2.0));
+    diff.adds[0].push(interner.string_id("utf-8"));
+    watcher.on_diff(&mut interner, diff);
+    let changes = stream_transaction(&incoming);
+    assert_eq!(values(&changes, "contents"), vec![s("a"), s("é"), s("€"), s("z")]);
+    assert_eq!(find_value(&changes, "count"), Some(s("4")));
+    fs::remove_file(path).ok();
+}
+
+#[test]
+fn file_follow_reports_appended_lines() {
+    let path = std::env::temp_dir().join("eve-file-follow.txt");
+    let path = path.to_str().unwrap();
+    fs::File::create(path).unwrap().write_all(b"old\n").unwrap();
+
+    let (outgoing, incoming) = mpsc::channel();
+    let mut watcher = FileWatcher::new(outgoing);
+    let mut interner = Interner::new();
+    let diff = file_diff(&mut interner, vec!["follow", "my-follow", path, "utf-8"]);
+    let row = diff.adds[0].clone();
+    watcher.on_diff(&mut interner, diff);
+    assert!(next_transaction(&incoming).is_empty());
+
+    thread::sleep(Duration::from_millis(300));
+    fs::OpenOptions::new().append(true).open(path).unwrap().write_all(b"new\npart").unwrap();
+    let changes = next_transaction(&incoming);
+    assert_eq!(values(&changes, "text"), vec![s("new")]);
+    assert_eq!(find_value(&changes, "offset"), Some(s("4")));
+
+    fs::OpenOptions::new().append(true).open(path).unwrap().write_all(b"ial\n").unwrap();
+    assert_eq!(values(&next_transaction(&incoming), "text"), vec![s("partial")]);
+
+    watcher.on_diff(&mut interner, WatchDiff { adds: vec![], removes: vec![row] });
+    thread::sleep(Duration::from_millis(500));
+    fs::OpenOptions::new().append(true).open(path).unwrap().write_all(b"ignored\n").unwrap();
+    assert!(incoming.recv_timeout(Duration::from_secs(1)).is_err());
+    fs::remove_file(path).ok();
+}
+
+#[test]
+fn file_follow_offsets_stay_exact_past_16mb() {
+    let path = std::env::temp_dir().join("eve-file-follow-large.txt");
+    let path = path.to_str().unwrap();
+    // One byte past where an Eve number can hold every integer.
+    fs::File::create(path).unwrap().set_len((1 << 24) + 1).unwrap();
+
+    let (outgoing, incoming) = mpsc::channel();
+    let mut watcher = FileWatcher::new(outgoing);
+    let mut interner = Interner::new();
+    let diff = file_diff(&mut interner, vec!["follow", "my-large-follow", path, "utf-8"]);
+    watcher.on_diff(&mut interner, diff);
+    assert!(next_transaction(&incoming).is_empty());
+
+    thread::sleep(Duration::from_millis(300));
+    fs::OpenOptions::new().append(true).open(path).unwrap().write_all(b"new\n").unwrap();
+    let changes = next_transaction(&incoming);
+    assert_eq!(values(&changes, "text"), vec![s("new")]);
+    assert_eq!(values(&changes, "offset"), vec![s("16777217")]);
+    fs::remove_file(path).ok();
+}
+
+#[test]
+fn file_csv_write_then_read() {
+    let path = std::env::temp_dir().join("eve-file-csv.csv");
+    let path = path.to_str().unwrap();
+    fs::remove_file(path).ok();
+
+    let (outgoing, incoming) = mpsc::channel();
+    let mut watcher = FileWatcher::new(outgoing);
+    let mut interner = Interner::new();
+    let mut adds = vec![];
+    for &(ix, name) in [(2.0, "age"), (1.0, "name")].iter() {
+        adds.push(vec![interner.string_id("csv-column"), interner.string_id("my-csv"), interner.number_id(ix), interner.string_id(name)]);
+    }
+    for &(row, ix, name, age) in [("ann", 2.0, "Ann, Jr.", Some(31.0)), ("bob", 1.0, "Bob", None)].iter() {
+        adds.push(vec![interner.string_id("csv-cell"), interner.string_id("my-csv"), interner.string_id(row), interner.number_id(ix), interner.string_id("name"), interner.string_id(name)]);
+        if let Some(age) = age {
+            adds.push(vec![interner.string_id("csv-cell"), interner.string_id("my-csv"), interner.string_id(row), interner.number_id(ix), interner.string_id("age"), interner.number_id(age)]);
+        }
+    }
+    adds.push(file_diff(&mut interner, vec!["csv-write", "my-csv", path, ","]).adds.remove(0));
+    watcher.on_diff(&mut interner, WatchDiff { adds, removes: vec![] });
+    let changes = stream_transaction(&incoming);
+    assert_eq!(find_value(&changes, "tag"), Some(s("file/write/done")));
+    assert_eq!(find_value(&changes, "count"), Some(Internable::from_number(2.0)));
+    let mut contents = String::new();
+    fs::File::open(path).unwrap().read_to_string(&mut contents).unwrap();
+    assert_eq!(contents.lines().collect::<Vec<_>>(), vec!["name,age", "Bob,", "\"Ann, Jr.\",31"]);
+
+    let diff = file_diff(&mut interner, vec!["csv-read", "my-csv-read", path, ","]);
+    watcher.on_diff(&mut interner, diff);
+    let changes = stream_transaction(&incoming);
+    assert_eq!(values(&changes, "name"), vec![s("Bob"), s("Ann, Jr.")]);
+    assert_eq!(values(&changes, "age"), vec![Internable::from_number(31.0)]);
+    assert_eq!(find_value(&changes, "count"), Some(s("2")));
+
+    let diff = file_diff(&mut interner, vec!["csv-read", "my-bad-csv", path, "::"]);
+    watcher.on_diff(&mut interner, diff);
+    assert_eq!(find_value(&stream_transaction(&incoming), "tag"), Some(s("file/error")));
+    fs::remove_file(path).ok();
+}
+
+#[test]
+fn file_csv_read_renames_reserved_columns() {
+    let path = std::env::temp_dir().join("eve-file-csv-reserved.csv");
+    let path = path.to_str().unwrap();
+    fs::File::create(path).unwrap().write_all(b"tag,file,ix,name\nperson,people.csv,7,Ann\n").unwrap();
+
+    let (outgoing, incoming) = mpsc::channel();
+    let mut watcher = FileWatcher::new(outgoing);
+    let mut interner = Interner::new();
+    let diff = file_diff(&mut interner, vec!["csv-read", "my-csv-read", path, ","]);
+    watcher.on_diff(&mut interner, diff);
+    let changes = stream_transaction(&incoming);
+    fs::remove_file(path).ok();
+    assert_eq!(values(&changes, "tag"), vec![s("file/csv/row"), s("file/read/done")]);
+    assert_eq!(values(&changes, "ix"), vec![s("1")]);
+    assert_eq!(values(&changes, "csv/tag"), vec![s("person")]);
+    assert_eq!(values(&changes, "csv/file"), vec![s("people.csv")]);
+    assert_eq!(values(&changes, "csv/ix"), vec![Internable::from_number(7.0)]);
+    assert_eq!(values(&changes, "name"), vec![s("Ann")]);
+}
+
+//-------------------------------------------------------------------------
+// Process
+//-------------------------------------------------------------------------
+
+fn process_row(interner:&mut Interner, kind:&str, process:&str, rest:Vec<Interned>) -> Vec<Interned> {
+    let mut row = vec![interner.string_id(kind), interner.string_id(process)];
+    row.extend(rest);
+    row
+}
+
+// Collects process transactions until the exit shows up.
+fn process_until_exit(incoming:&mpsc::Receiver<RunLoopMessage>) -> Vec<RawChange> {
+    let mut all = vec![];
+    loop {
+        let changes = next_transaction(incoming);
+        let done = find_value(&changes, "tag") == Some(s("process/exit"));
+        all.extend(changes);
+        if done { return all; }
+    }
+}
+
+#[test]
+fn process_reports_output_and_exit() {
+    let (outgoing, incoming) = mpsc::channel();
+    let mut watcher = ProcessWatcher::new(outgoing);
+    let mut interner = Interner::new();
+    let (script, empty) = (interner.string_id("echo $GREETING; echo oops >&2; exit 3"), interner.string_id(""));
+    let (one, two, sh) = (interner.number_id(1.0), interner.number_id(2.0), interner.string_id("sh"));
+    let (c, name, hello) = (interner.string_id("-c"), interner.string_id("GREETING"), interner.string_id("hello"));
+    let adds = vec![
+        process_row(&mut interner, "spawn", "my-process", vec![sh, empty]),
+        process_row(&mut interner, "arg", "my-process", vec![two, script]),
+        process_row(&mut interner, "arg", "my-process", vec![one, c]),
+        process_row(&mut interner, "env", "my-process", vec![name, hello]),
+    ];
+    watcher.on_diff(&mut interner, WatchDiff { adds, removes: vec![] });
+
+    let changes = process_until_exit(&incoming);
+    let lines:Vec<(Internable, Internable)> = changes.chunks(5).filter(|record| record[0].v == s("process/output"))
+        .map(|record| (record[2].v.clone(), record[4].v.clone()))
+        .collect();
+    assert!(lines.contains(&(s("stdout"), s("hello"))));
+    assert!(lines.contains(&(s("stderr"), s("oops"))));
+    assert_eq!(find_value(&changes[changes.len() - 3..].to_vec(), "code"), Some(Internable::from_number(3.0)));
+}
+
+#[test]
+fn process_accepts_stdin() {
+    let (outgoing, incoming) = mpsc::channel();
+    let mut watcher = ProcessWatcher::new(outgoing);
+    let mut interner = Interner::new();
+    let (cat, empty, text) = (interner.string_id("cat"), interner.string_id(""), interner.string_id("piped\n"));
+    let process = interner.string_id("my-cat");
+    let adds = vec![
+        process_row(&mut interner, "spawn", "my-cat", vec![cat, empty]),
+        process_row(&mut interner, "write", "my-write", vec![process, text]),
+        process_row(&mut interner, "close", "my-close", vec![process]),
+    ];
+    watcher.on_diff(&mut interner, WatchDiff { adds, removes: vec![] });
+
+    let changes = process_until_exit(&incoming);
+    assert_eq!(find_value(&changes, "text"), Some(s("piped")));
+    assert_eq!(find_value(&changes[changes.len() - 3..].to_vec(), "code"), Some(Internable::from_number(0.0)));
+}
+
+#[test]
+fn process_reports_args_without_a_numeric_index() {
+    let (outgoing, incoming) = mpsc::channel();
+    let mut watcher = ProcessWatcher::new(outgoing);
+    let mut interner = Interner::new();
+    let (first, value) = (interner.string_id("first"), interner.string_id("-c"));
+    let arg = process_row(&mut interner, "arg", "my-process", vec![first, value]);
+    watcher.on_diff(&mut interner, WatchDiff { adds: vec![arg.clone()], removes: vec![] });
+
+    let changes = next_transaction(&incoming);
+    assert_eq!(find_value(&changes, "tag"), Some(s("process/error")));
+    assert_eq!(find_value(&changes, "process"), Some(s("my-process")));
+    watcher.on_diff(&mut interner, WatchDiff { adds: vec![], removes: vec![arg] });
+}
+
+#[test]
+fn process_is_killed_when_removed() {
+    let (outgoing, incoming) = mpsc::channel();
+    let mut watcher = ProcessWatcher::new(outgoing);
+    let mut interner = Interner::new();
+    let (sleep, empty, one, seconds) = (interner.string_id("sleep"), interner.string_id(""), interner.number_id(1.0), interner.string_id("30"));
+    let spawn = process_row(&mut interner, "spawn", "my-sleep", vec![sleep, empty]);
+    let adds = vec![spawn.clone(), process_row(&mut interner, "arg", "my-sleep", vec![one, seconds])];
+    watcher.on_diff(&mut interner, WatchDiff { adds, removes: vec![] });
+    assert!(incoming.recv_timeout(Duration::from_millis(200)).is_err());
+
+    let start = Instant::now();
+    watcher.on_diff(&mut interner, WatchDiff { adds: vec![], removes: vec![spawn] });
+    let changes = process_until_exit(&incoming);
+    assert!(start.elapsed() < Duration::from_secs(5));
+    assert!(find_value(&changes, "signal").is_some());
+}
+
+//-------------------------------------------------------------------------
+// Plugin
+//-------------------------------------------------------------------------
+
+#[test]
+fn plugin_reports_libraries_it_cant_load() {
+    let (outgoing, incoming) = mpsc::channel();
+    assert!(load_plugin("/nonexistent/libeve_plugin.so", outgoing.clone()).is_err());
+
+    let mut watcher = PluginWatcher::new(outgoing);
+    let mut interner = Interner::new();
+    let row = vec![interner.string_id("load"), interner.string_id("my-plugin"), interner.string_id("/nonexistent/libeve_plugin.so")];
+    watcher.on_diff(&mut interner, WatchDiff { adds: vec![row], removes: vec![] });
+    match incoming.recv_timeout(Duration::from_secs(5)) {
+        Ok(RunLoopMessage::Transaction(changes)) => {
+            assert_eq!(find_value(&changes, "tag"), Some(s("plugin/error")));
+            assert_eq!(find_value(&changes, "plugin"), Some(s("my-plugin")));
+        }
+        _ => panic!("Expected a plugin error"),
+    }
+}
+
+// The `watcher_plugin` example, which cargo builds alongside the tests.
+fn example_plugin() -> String {
+    let deps = std::env::current_exe().unwrap();
+    let examples = deps.parent().unwrap().parent().unwrap().join("examples");
+    let name = format!("{}watcher_plugin{}", std::env::consts::DLL_PREFIX, std::env::consts::DLL_SUFFIX);
+    examples.join(name).to_str().unwrap().to_string()
+}
+
+#[test]
+fn plugin_watchers_attach_and_detach_with_their_load_record() {
+    assert!(plugin_version().starts_with(env!("CARGO_PKG_VERSION")));
+    let (outgoing, incoming) = mpsc::channel();
+    let mut watcher = PluginWatcher::new(outgoing);
+    let mut interner = Interner::new();
+    let row = vec![interner.string_id("load"), interner.string_id("my-plugin"), interner.string_id(&example_plugin())];
+    watcher.on_diff(&mut interner, WatchDiff { adds: vec![row.clone()], removes: vec![] });
+
+    let mut program = Program::new("plugin");
+    match incoming.recv_timeout(Duration::from_secs(5)) {
+        Ok(RunLoopMessage::AttachWatcher(plugin_watcher)) => {
+            assert_eq!(plugin_watcher.get_name(), "plugin/nothing");
+            program.attach(plugin_watcher);
+        }
+        Ok(RunLoopMessage::Transaction(changes)) => panic!("Expected a watcher, got {:?}", find_value(&changes, "message")),
+        _ => panic!("Expected a watcher"),
+    }
+    let changes = next_transaction(&incoming);
+    assert_eq!(find_value(&changes, "tag"), Some(s("plugin/loaded")));
+    assert_eq!(find_value(&changes, "watcher"), Some(s("plugin/nothing")));
+
+    watcher.on_diff(&mut interner, WatchDiff { adds: vec![], removes: vec![row] });
+    match incoming.recv_timeout(Duration::from_secs(5)) {
+        Ok(RunLoopMessage::DetachWatcher(name)) => assert!(program.detach(&name)),
+        _ => panic!("Expected the watcher to be detached"),
+    }
+}
+
+//-------------------------------------------------------------------------
+// System
+//-------------------------------------------------------------------------
+
+#[test]
+fn system_timers_tick_without_drifting() {
+    let (outgoing, incoming) = mpsc::channel();
+    let mut watcher = SystemTimerWatcher::new(outgoing);
+    let mut interner = Interner::new();
+    let timer = vec![interner.string_id("my-timer"), interner.number_id(20.0)];
+    let start = Instant::now();
+    watcher.on_diff(&mut interner, WatchDiff { adds: vec![timer.clone()], removes: vec![] });
+    let mut ticks = vec![];
+    while ticks.last().map_or(true, |&tick| tick < 9.0) {
+        let tick = Internable::to_number(&find_value(&next_transaction(&incoming), "tick").unwrap());
+        // Tick `n` is due (n + 1) * 20ms after the timer started and never goes out early.
+        assert!(start.elapsed() >= Duration::from_millis(20 * (tick as u64 + 1)));
+        ticks.push(tick);
+    }
+    let elapsed = start.elapsed();
+    watcher.on_diff(&mut interner, WatchDiff { adds: vec![], removes: vec![timer] });
+
+    // Ticks count up from zero. If the timer fell behind the ones it missed are skipped rather
+    // than sent late, so there are never more of them than the numbering says.
+    assert_eq!(ticks[0], 0.0);
+    assert!(ticks.windows(2).all(|pair| pair[0] < pair[1]));
+    assert!(ticks.len() <= 10);
+    assert!(elapsed < Duration::from_secs(5));
+}
+
+#[test]
+fn system_timeouts_fire_once_unless_removed() {
+    let (outgoing, incoming) = mpsc::channel();
+    let mut watcher = SystemScheduleWatcher::new(outgoing);
+    let mut interner = Interner::new();
+    let (timeout, cancelled) = (interner.string_id("timeout"), interner.string_id("my-cancelled-timeout"));
+    let fires = vec![timeout, interner.string_id("my-timeout"), interner.number_id(50.0)];
+    let never = vec![timeout, cancelled, interner.number_id(100.0)];
+    watcher.on_diff(&mut interner, WatchDiff { adds: vec![fires, never.clone()], removes: vec![] });
+    watcher.on_diff(&mut interner, WatchDiff { adds: vec![], removes: vec![never] });
+
+    let changes = next_transaction(&incoming);
+    assert_eq!(find_value(&changes, "tag"), Some(s("system/timeout/change")));
+    assert_eq!(find_value(&changes, "timeout"), Some(s("my-timeout")));
+    assert!(incoming.recv_timeout(Duration::from_millis(300)).is_err());
+}
+
+#[test]
+fn system_alarms_fire_at_wall_clock_times() {
+    assert!(parse_alarm_time("tomorrow-ish").is_err());
+    let (outgoing, incoming) = mpsc::channel();
+    let mut watcher = SystemScheduleWatcher::new(outgoing);
+    let mut interner = Interner::new();
+    let alarm = interner.string_id("alarm");
+    let past = vec![alarm, interner.string_id("my-alarm"), interner.string_id("2017-08-01T17:30:00Z")];
+    let invalid = vec![alarm, interner.string_id("my-bad-alarm"), interner.string_id("noon")];
+
+    watcher.on_diff(&mut interner, WatchDiff { adds: vec![past], removes: vec![] });
+    let changes = next_transaction(&incoming);
+    assert_eq!(find_value(&changes, "alarm"), Some(s("my-alarm")));
+
+    watcher.on_diff(&mut interner, WatchDiff { adds: vec![invalid], removes: vec![] });
+    let changes = next_transaction(&incoming);
+    assert_eq!(find_value(&changes, "tag"), Some(s("system/error")));
+    assert_eq!(find_value(&changes, "record"), Some(s("my-bad-alarm")));
+}
+
+#[test]
+fn system_startup_records_for_env_and_args() {
+    std::env::set_var("EVE_STARTUP_TEST", "yes");
+    let env = env_changes();
+    let variable = env.iter().find(|change| change.a == s("name") && change.v == s("EVE_STARTUP_TEST")).map(|change| change.e.clone()).unwrap();
+    assert!(env.iter().any(|change| change.e == variable && change.a == s("value") && change.v == s("yes")));
+
+    let args = args_changes(&vec!["first".to_string(), "second".to_string()]);
+    let second = args.iter().find(|change| change.a == s("value") && change.v == s("second")).map(|change| change.e.clone()).unwrap();
+    assert!(args.iter().any(|change| change.e == second && change.a == s("ix") && change.v == Internable::from_number(2.0)));
+    assert!(args.iter().any(|change| change.e == second && change.a == s("tag") && change.v == s("system/args")));
+}
+
+//-------------------------------------------------------------------------
+// Remote
+//-------------------------------------------------------------------------
+
+fn remote_change(from:&str, to:&str, entity:&str) -> RawRemoteChange {
+    RawRemoteChange { e: s(entity), a: s("tag"), v: s("greeting"), _for: s(""), _type: s("add"), from: s(from), to: s(to) }
+}
+
+#[test]
+fn router_reports_unknown_destinations() {
+    let (manager, _manager_incoming) = mpsc::channel();
+    let mut router = Router::new(manager);
+    let (client, incoming) = mpsc::channel();
+    router.register("a", client);
+    router.get_channel().send(RouterMessage::Remote(vec![remote_change("a", "nobody", "hello")])).unwrap();
+
+    match incoming.recv_timeout(Duration::from_secs(5)) {
+        Ok(RunLoopMessage::Transaction(changes)) => {
+            assert_eq!(find_value(&changes, "tag"), Some(s("router/error")));
+            assert_eq!(find_value(&changes, "to"), Some(s("nobody")));
+        }
+        _ => panic!("Expected an error transaction"),
+    }
+}
+
+fn remote_transaction(incoming:&mpsc::Receiver<RunLoopMessage>) -> Vec<RawRemoteChange> {
+    match incoming.recv_timeout(Duration::from_secs(5)) {
+        Ok(RunLoopMessage::RemoteTransaction(changes)) => changes,
+        _ => panic!("Expected a remote transaction"),
+    }
+}
+
+// Router `a` hosting program `a` connected to router `b` hosting program `b`.
+fn connected_routers() -> (Router, Router, mpsc::Receiver<RunLoopMessage>, mpsc::Receiver<RunLoopMessage>) {
+    let address = {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap().to_string()
+    };
+    let (manager, _manager_incoming) = mpsc::channel();
+    let mut a = Router::new(manager.clone());
+    let mut b = Router::new(manager);
+    let (a_client, a_incoming) = mpsc::channel();
+    let (b_client, b_incoming) = mpsc::channel();
+    a.register("a", a_client);
+    b.register("b", b_client);
+    b.listen_for_peers("server-b", &address, "secret");
+    a.connect_peer("server-a", &format!("ws://{}", address), "secret");
+
+    let start = Instant::now();
+    while !a.is_reachable("b") {
+        assert!(start.elapsed() < Duration::from_secs(10), "Peers never connected");
+        thread::sleep(Duration::from_millis(50));
+    }
+    (a, b, a_incoming, b_incoming)
+}
+
+#[test]
+fn router_peers_deliver_remote_changes() {
+    let (a, _b, _a_incoming, b_incoming) = connected_routers();
+    assert!(a.is_reachable("b@server-b"));
+
+    a.get_channel().send(RouterMessage::Remote(vec![remote_change("a", "b", "first"), remote_change("a", "b@server-b", "second")])).unwrap();
+    let mut received = vec![];
+    while received.len() < 2 {
+        received.extend(remote_transaction(&b_incoming));
+    }
+    assert_eq!(received.iter().map(|change| change.e.clone()).collect::<Vec<_>>(), vec![s("first"), s("second")]);
+    assert!(received.iter().all(|change| change.to == s("b") && change.from == s("a@server-a")));
+}
+
+#[test]
+fn router_peers_need_the_shared_secret() {
+    let address = {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap().to_string()
+    };
+    let (manager, _manager_incoming) = mpsc::channel();
+    let a = Router::new(manager.clone());
+    let mut b = Router::new(manager);
+    let (b_client, _b_incoming) = mpsc::channel();
+    b.register("b", b_client);
+    b.listen_for_peers("server-b", &address, "secret");
+    a.connect_peer("server-a", &format!("ws://{}", address), "guess");
+
+    thread::sleep(Duration::from_secs(2));
+    assert!(!a.is_reachable("b"));
+    assert!(!b.is_reachable("a"));
+}
+
+#[test]
+fn router_delivers_peer_changes_in_order_once() {
+    let (manager, _manager_incoming) = mpsc::channel();
+    let mut router = Router::new(manager);
+    let (client, incoming) = mpsc::channel();
+    router.register("b", client);
+    let channel = router.get_channel();
+    let remote = |seq:u64, reset:bool, entities:Vec<&str>| {
+        let changes = entities.iter().map(|entity| remote_change("a@server-a", "b", entity)).collect();
+        RouterMessage::FromPeer("server-a".to_string(), PeerMessage::Remote { from: "a@server-a".to_string(), to: "b".to_string(), seq, reset, changes })
+    };
+
+    channel.send(remote(1, false, vec!["first"])).unwrap();
+    assert_eq!(remote_transaction(&incoming)[0].e, s("first"));
+    // Duplicates and anything after a gap are dropped until the sender resets us.
+    channel.send(remote(1, false, vec!["first"])).unwrap();
+    channel.send(remote(3, false, vec!["third"])).unwrap();
+    channel.send(remote(4, true, vec!["first", "second", "third"])).unwrap();
+    let mut reset:Vec<Internable> = remote_transaction(&incoming).into_iter().map(|change| {
+        assert_eq!(change._type, s("add"));
+        change.e
+    }).collect();
+    reset.sort();
+    assert_eq!(reset, vec![s("second"), s("third")]);
+    assert!(incoming.recv_timeout(Duration::from_millis(100)).is_err());
+
+    // A reset takes back whatever the sender no longer has.
+    channel.send(remote(5, true, vec!["third"])).unwrap();
+    let mut removed:Vec<Internable> = remote_transaction(&incoming).into_iter().map(|change| {
+        assert_eq!(change._type, s("remove"));
+        change.e
+    }).collect();
+    removed.sort();
+    assert_eq!(removed, vec![s("first"), s("second")]);
+}
+
+#[test]
+fn router_forgets_unregistered_programs() {
+    let (manager, _manager_incoming) = mpsc::channel();
+    let mut router = Router::new(manager);
+    let (client, incoming) = mpsc::channel();
+    router.register("b", client);
+    let channel = router.get_channel();
+    let remote = |seq:u64, reset:bool, entities:Vec<&str>| {
+        let changes = entities.iter().map(|entity| remote_change("a@server-a", "b", entity)).collect();
+        RouterMessage::FromPeer("server-a".to_string(), PeerMessage::Remote { from: "a@server-a".to_string(), to: "b".to_string(), seq, reset, changes })
+    };
+    channel.send(remote(1, false, vec!["first"])).unwrap();
+    assert_eq!(remote_transaction(&incoming)[0].e, s("first"));
+
+    // Once `b` is gone so is what it was sent, the next `b` starts from nothing.
+    router.unregister("b");
+    let (client, incoming) = mpsc::channel();
+    router.register("b", client);
+    channel.send(remote(2, false, vec!["second"])).unwrap();
+    channel.send(remote(3, true, vec!["second"])).unwrap();
+    let reset = remote_transaction(&incoming);
+    assert_eq!(reset.len(), 1);
+    assert_eq!((reset[0].e.clone(), reset[0]._type.clone()), (s("second"), s("add")));
+    assert!(incoming.recv_timeout(Duration::from_millis(100)).is_err());
+}
+
+#[test]
+fn router_replays_local_facts_to_restarted_programs() {
+    let (manager, _manager_incoming) = mpsc::channel();
+    let mut router = Router::new(manager);
+    let (a_client, _a_incoming) = mpsc::channel();
+    let (b_client, b_incoming) = mpsc::channel();
+    router.register("a", a_client);
+    router.register("b", b_client);
+    let mut removed = remote_change("a", "b", "first");
+    removed._type = s("remove");
+    router.get_channel().send(RouterMessage::Remote(vec![remote_change("a", "b", "first"), remote_change("a", "b", "second")])).unwrap();
+    router.get_channel().send(RouterMessage::Remote(vec![removed])).unwrap();
+    remote_transaction(&b_incoming);
+    remote_transaction(&b_incoming);
+
+    // Only what `a` still has for `b` is sent to the next `b`.
+    router.unregister("b");
+    let (b_client, b_incoming) = mpsc::channel();
+    router.register("b", b_client);
+    let replayed = remote_transaction(&b_incoming);
+    assert_eq!(replayed.len(), 1);
+    assert_eq!((replayed[0].e.clone(), replayed[0]._type.clone()), (s("second"), s("add")));
+}
+
+#[test]
+fn router_peers_replay_facts_to_restarted_programs() {
+    let (a, mut b, _a_incoming, b_incoming) = connected_routers();
+    a.get_channel().send(RouterMessage::Remote(vec![remote_change("a", "b", "hello")])).unwrap();
+    assert_eq!(remote_transaction(&b_incoming)[0].e, s("hello"));
+
+    // Once `b` starts over it's sent everything `a` currently has for it.
+    b.unregister("b");
+    let (b_client, b_incoming) = mpsc::channel();
+    b.register("b", b_client);
+    let replayed = remote_transaction(&b_incoming);
+    assert_eq!(replayed.len(), 1);
+    assert_eq!((replayed[0].e.clone(), replayed[0].from.clone()), (s("hello"), s("a@server-a")));
+}
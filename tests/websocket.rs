@@ -0,0 +1,93 @@
+extern crate eve;
+extern crate serde_json;
+extern crate ws;
+
+use eve::ops::{Interner, Interned};
+use eve::indexes::WatchDiff;
+use eve::watchers::Watcher;
+use eve::watchers::websocket::*;
+use eve::wire::WireFormat;
+use std::sync::mpsc;
+use std::time::Duration;
+use ws::Message;
+
+fn next_message(incoming:&mpsc::Receiver<Message>) -> serde_json::Value {
+    match incoming.recv_timeout(Duration::from_secs(5)) {
+        Ok(Message::Text(text)) => serde_json::from_str(&text).unwrap(),
+        other => panic!("Expected a text message, got {:?}", other),
+    }
+}
+
+fn watcher(tick:u64, max_pending:usize, overflow:OverflowPolicy) -> (WebsocketClientWatcher, mpsc::Receiver<Message>) {
+    let (outgoing, incoming) = mpsc::channel();
+    let config = SocketConfig { tick: Duration::from_millis(tick), max_pending, overflow };
+    let watcher = WebsocketClientWatcher::with_socket(ClientSocket::new(outgoing, WireFormat::Json, "ws_client_1", config), None);
+    assert_eq!(next_message(&incoming)["type"], "init");
+    (watcher, incoming)
+}
+
+fn row(interner:&mut Interner, id:&str) -> Vec<Interned> {
+    vec![interner.string_id("html/element"), interner.string_id(id)]
+}
+
+fn ids(rows:&serde_json::Value) -> Vec<String> {
+    let mut ids:Vec<String> = rows.as_array().unwrap().iter().map(|row| row[1].as_str().unwrap().to_string()).collect();
+    ids.sort();
+    ids
+}
+
+#[test]
+fn diffs_are_batched_per_tick() {
+    let (mut watcher, incoming) = watcher(100, 100, OverflowPolicy::Merge);
+    let mut interner = Interner::new();
+    let (a, b) = (row(&mut interner, "a"), row(&mut interner, "b"));
+    watcher.on_diff(&mut interner, WatchDiff { adds: vec![a.clone(), b], removes: vec![] });
+    watcher.on_diff(&mut interner, WatchDiff { adds: vec![], removes: vec![a] });
+
+    // The add and remove of `a` cancel out before the tick comes around.
+    let diff = next_message(&incoming);
+    assert_eq!(ids(&diff["adds"]), vec!["b"]);
+    assert_eq!(ids(&diff["removes"]), Vec::<String>::new());
+    assert!(incoming.recv_timeout(Duration::from_millis(300)).is_err());
+}
+
+#[test]
+fn merge_overflow_sends_early() {
+    let (mut watcher, incoming) = watcher(60000, 2, OverflowPolicy::Merge);
+    let mut interner = Interner::new();
+    let rows = vec![row(&mut interner, "a"), row(&mut interner, "b"), row(&mut interner, "c")];
+    watcher.on_diff(&mut interner, WatchDiff { adds: rows, removes: vec![] });
+
+    let diff = next_message(&incoming);
+    assert_eq!(diff["reset"], false);
+    assert_eq!(ids(&diff["adds"]), vec!["a", "b", "c"]);
+}
+
+#[test]
+fn drop_overflow_resyncs() {
+    let (mut watcher, incoming) = watcher(100, 2, OverflowPolicy::Drop);
+    let mut interner = Interner::new();
+    let (a, b, c) = (row(&mut interner, "a"), row(&mut interner, "b"), row(&mut interner, "c"));
+    watcher.on_diff(&mut interner, WatchDiff { adds: vec![a.clone(), b, c], removes: vec![] });
+    watcher.on_diff(&mut interner, WatchDiff { adds: vec![], removes: vec![a] });
+
+    // The pending rows are thrown away and the client gets the full state in their place.
+    let diff = next_message(&incoming);
+    assert_eq!(diff["reset"], true);
+    assert_eq!(ids(&diff["adds"]), vec!["b", "c"]);
+}
+
+#[test]
+fn nested_batches_are_ignored() {
+    let message = serde_json::from_str(r#"{"Batch": {"messages": [
+        {"Explain": {"block": "one"}},
+        {"Batch": {"messages": [{"Explain": {"block": "nested"}}]}},
+        {"Unknown": {}},
+        {"Explain": {"block": "two"}}
+    ]}}"#).unwrap();
+    let blocks:Vec<String> = unbatch("ws_client_1", message).into_iter().map(|message| match message {
+        ClientMessage::Explain { block } => block,
+        other => panic!("Expected an explain message, got {:?}", other),
+    }).collect();
+    assert_eq!(blocks, vec!["one", "two"]);
+}
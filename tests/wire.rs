@@ -0,0 +1,77 @@
+extern crate eve;
+
+use eve::ops::{Internable, JSONInternable};
+use eve::wire::*;
+
+fn string(value:&str) -> JSONInternable {
+    JSONInternable::String(value.to_string())
+}
+
+#[test]
+fn wire_format_defaults_to_json() {
+    assert_eq!(WireFormat::from_name(None), WireFormat::Json);
+    assert_eq!(WireFormat::from_name(Some("json")), WireFormat::Json);
+    assert_eq!(WireFormat::from_name(Some("binary")), WireFormat::Binary);
+}
+
+#[test]
+fn wire_diff_round_trips() {
+    let mut encoder = WireEncoder::new();
+    let mut decoder = WireDecoder::new();
+    let row = vec![string("html/element"), string("div"), Internable::from_number(1.5).into(), JSONInternable::Null];
+    let bytes = encoder.diff("client", &vec![&row], &vec![], false);
+    match decoder.decode(&bytes) {
+        Ok(WireMessage::Diff { client, adds, removes, reset, .. }) => {
+            assert_eq!(client, "client");
+            assert!(removes.is_empty());
+            assert!(!reset);
+            let decoded:Vec<JSONInternable> = adds[0].iter().map(|value| decoder.value(value).unwrap()).collect();
+            assert_eq!(decoded, row);
+        }
+        other => panic!("Expected a diff, got {:?}", other),
+    }
+}
+
+#[test]
+fn wire_strings_are_only_sent_once() {
+    let mut encoder = WireEncoder::new();
+    let mut decoder = WireDecoder::new();
+    let row = vec![string("tag"), string("a fairly long string that repeats")];
+    let first = encoder.diff("client", &vec![&row], &vec![], false);
+    encoder.commit();
+    let second = encoder.diff("client", &vec![], &vec![&row], false);
+    assert!(second.len() < first.len());
+
+    decoder.decode(&first).unwrap();
+    match decoder.decode(&second) {
+        Ok(WireMessage::Diff { strings, removes, .. }) => {
+            assert!(strings.is_empty());
+            assert_eq!(decoder.value(&removes[0][1]), Ok(string("a fairly long string that repeats")));
+        }
+        other => panic!("Expected a diff, got {:?}", other),
+    }
+}
+
+#[test]
+fn wire_strings_are_resent_until_committed() {
+    let mut encoder = WireEncoder::new();
+    let mut decoder = WireDecoder::new();
+    let row = vec![string("tag"), string("div")];
+    // The first send failed, so the client never saw these strings.
+    encoder.diff("client", &vec![&row], &vec![], false);
+    let retry = encoder.diff("client", &vec![&row], &vec![], false);
+    match decoder.decode(&retry) {
+        Ok(WireMessage::Diff { strings, adds, .. }) => {
+            assert_eq!(strings.len(), 2);
+            assert_eq!(decoder.value(&adds[0][1]), Ok(string("div")));
+        }
+        other => panic!("Expected a diff, got {:?}", other),
+    }
+}
+
+#[test]
+fn wire_decoder_rejects_unknown_strings() {
+    let decoder = WireDecoder::new();
+    assert!(decoder.value(&WireValue::String(7)).is_err());
+    assert!(decoder.eav(&(WireValue::Null, WireValue::String(0), WireValue::Number(0))).is_err());
+}
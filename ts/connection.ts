@@ -1,3 +1,5 @@
+import {WireConnection} from "./wire";
+
 export interface Message {
   type:string;
   client:string;
@@ -6,12 +8,19 @@ export interface Message {
 const EMPTY:any[] = [];
 
 export class Connection {
-  _queue:string[] = [];
+  _queue:any[] = [];
+  _scheduled = false;
   connected = false;
 
   handlers:{[type:string]: (data:Message) => void} = {};
+  // Set when the socket was opened with `format=binary`.
+  wire?:WireConnection;
 
-  constructor(public ws:WebSocket) {
+  constructor(public ws:WebSocket, binary = false) {
+    if(binary) {
+      ws.binaryType = "arraybuffer";
+      this.wire = new WireConnection();
+    }
     ws.addEventListener("open", () => this._opened());
     ws.addEventListener("close", (event) => this._closed(event.code, event.reason));
     ws.addEventListener("message", (event) => this._messaged(event.data));
@@ -21,21 +30,34 @@ export class Connection {
     // console.groupCollapsed("Sent");
     // console.log(type, data, client);
     // console.groupEnd();
-    // This... feels weird. Do we actually expect to pack multiple message types in very frequently?
     data.client = client;
-    let payload = JSON.stringify({[type]: data});
-    this._queue.push(payload);
+    this._queue.push({[type]: data});
     this._trySend();
   }
 
   protected _trySend() {
-    if(this.connected) {
-      // @NOTE: this doesn't gracefully handle partial processing of the queue.
-      while(this._queue.length) {
-        let payload = this._queue.shift();
-        this.ws.send(payload);
+    if(!this.connected || this._scheduled) return;
+    this._scheduled = true;
+    // Everything sent within a frame goes out together as a single batch.
+    requestAnimationFrame(() => {
+      this._scheduled = false;
+      if(!this.connected || !this._queue.length) return;
+      let messages = this._queue;
+      this._queue = [];
+      let wire = this.wire;
+      if(wire) {
+        // Transactions go out in the binary format, everything else is still JSON.
+        messages = messages.filter((message) => {
+          if(!message.Transaction) return true;
+          let {client, adds, removes} = message.Transaction;
+          this.ws.send(wire!.encodeTransaction(client, adds || EMPTY, removes || EMPTY));
+          return false;
+        });
+        if(!messages.length) return;
       }
-    }
+      let payload = messages.length === 1 ? messages[0] : {Batch: {messages}};
+      this.ws.send(JSON.stringify(payload));
+    });
   }
 
   protected _opened() {
@@ -48,10 +70,16 @@ export class Connection {
     console.warn("Connection closed.", code, reason);
   }
 
-  protected _messaged = (payload:string) => {
+  protected _messaged = (payload:string|ArrayBuffer) => {
     let parsed:Message;
     try {
-      parsed = JSON.parse(payload);
+      if(typeof payload === "string") {
+        parsed = JSON.parse(payload);
+      } else if(this.wire) {
+        parsed = {type: "diff", ...this.wire.decodeDiff(payload)};
+      } else {
+        throw new Error("Received a binary message without a binary connection");
+      }
     } catch(err) {
       console.error("Received malformed WS message: '" + payload + "'.");
       return;
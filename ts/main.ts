@@ -1,9 +1,10 @@
 import {Program, Library, Diff, RawEAV, RawTuple, libraries} from ".";
 import {Connection, Message} from "./connection";
 
-export interface DiffMessage extends Message { type: "diff"; adds?:RawTuple[]; removes?:RawTuple[]; }
+export interface DiffMessage extends Message { type: "diff"; adds?:RawTuple[]; removes?:RawTuple[]; reset?:boolean }
 export interface LoadBundleMessage extends Message { type: "load-bundle"; bundle: string }
 export interface ErrorMessage extends Message { type:"error"; error:string }
+export interface InitMessage extends Message { type: "init"; session?: string }
 
 interface Bundle { users: string[], css?: HTMLLinkElement, js?: HTMLScriptElement }
 
@@ -73,8 +74,10 @@ class MultiplexedConnection extends Connection {
   panes:{[client:string]: HTMLElement} = {};
 
   handlers = {
-    "init": ({client}:Message) => {
+    "init": ({client, session}:InitMessage) => {
       if(this.programs[client]) throw new Error(`Unable to initialize existing program: '${client}'.`);
+      // Remember the session so that a refresh picks our program back up instead of starting over.
+      if(session && client.indexOf("-editor") === -1) sessionStorage.setItem("eve-session", session);
       let program = this.programs[client] = new RemoteProgram(client, (type: string, diff: any) => this.send(type, diff, client));
       let html = program.attach("html") as libraries.HTML;
       this.addPane(client, html.getContainer());
@@ -84,6 +87,8 @@ class MultiplexedConnection extends Connection {
       program.attach("graph");
     },
     "diff": (diff:DiffMessage) => {
+      // The server fell behind and dropped diffs, so we start over from the full state it sent.
+      if(diff.reset) this.resetProgram(diff.client);
       let program = this.programs[diff.client];
       if(!program) throw new Error(`Unable to handle diff for unitialized program: '${diff.client}'.`);
       program.handleDiff(diff);
@@ -91,6 +96,9 @@ class MultiplexedConnection extends Connection {
     "load-bundle": ({bundle, client}:LoadBundleMessage) => {
       this.loadBundle(bundle, client);
     },
+    "error": ({client, error}:ErrorMessage) => {
+      console.error(`Server rejected a message for '${client}': ${error}`);
+    },
     "crash": ({error}:ErrorMessage) => {
       let message: any = document.createElement("h1");
       let t = "We're sorry, but your Eve program has crashed.";
@@ -132,6 +140,14 @@ class MultiplexedConnection extends Connection {
     }
   }
 
+  resetProgram(client:string) {
+    let pane = this.panes[client];
+    if(pane && pane.parentNode) pane.parentNode.removeChild(pane);
+    delete this.panes[client];
+    delete this.programs[client];
+    this.handlers.init({type: "init", client});
+  }
+
   addPane(name:string, container:HTMLElement) {
     if(this.panes[name] && this.panes[name] !== container) {
       console.warn(`Overwriting container for existing pane '${name}'`);
@@ -142,6 +158,12 @@ class MultiplexedConnection extends Connection {
 }
 
 (window as any)["RemoteProgram"] = RemoteProgram;
-let connection = new MultiplexedConnection(new WebSocket(`ws://${location.hostname}:3012`));
+// If the page was opened with a token, hand it along so the server can authenticate us.
+let token = (location.search.match(/[?&]token=([^&]*)/) || [])[1];
+let session = sessionStorage.getItem("eve-session");
+// Opening the page with `?format=binary` switches diffs and transactions over to the binary format.
+let binary = /[?&]format=binary(&|$)/.test(location.search);
+let params = [token ? `token=${token}` : "", session ? `session=${session}` : "", binary ? "format=binary" : ""].filter((param) => param).join("&");
+let connection = new MultiplexedConnection(new WebSocket(`ws://${location.hostname}:3012${params ? `/?${params}` : ""}`), binary);
 
 console.log(connection);
@@ -0,0 +1,162 @@
+//---------------------------------------------------------------------
+// Binary wire format
+//---------------------------------------------------------------------
+
+// Mirrors the bincode layout of `WireMessage` in src/wire.rs: big endian, enum variants as u32,
+// lengths as u64. Strings are only sent the first time they're seen on a connection, after that
+// they're referred to by id.
+
+type Value = string|number|null;
+type EAV = [Value, Value, Value];
+
+const enum ValueTag { Null = 0, String = 1, Number = 2 }
+const enum MessageTag { Diff = 0, Transaction = 1 }
+
+function utf8Encode(text:string):number[] {
+  let escaped = unescape(encodeURIComponent(text));
+  let bytes:number[] = [];
+  for(let ix = 0; ix < escaped.length; ix++) bytes.push(escaped.charCodeAt(ix));
+  return bytes;
+}
+
+function utf8Decode(bytes:Uint8Array):string {
+  let escaped = "";
+  for(let ix = 0; ix < bytes.length; ix++) escaped += String.fromCharCode(bytes[ix]);
+  return decodeURIComponent(escape(escaped));
+}
+
+class Reader {
+  offset = 0;
+  view:DataView;
+  constructor(public buffer:ArrayBuffer) {
+    this.view = new DataView(buffer);
+  }
+
+  u8() { return this.view.getUint8(this.offset++); }
+  u32() {
+    let value = this.view.getUint32(this.offset);
+    this.offset += 4;
+    return value;
+  }
+  // Lengths never get anywhere near 2^32, so the high word is ignored.
+  u64() {
+    this.offset += 4;
+    return this.u32();
+  }
+  string() {
+    let length = this.u64();
+    let bytes = new Uint8Array(this.buffer, this.offset, length);
+    this.offset += length;
+    return utf8Decode(bytes);
+  }
+  vec<T>(item:() => T):T[] {
+    let length = this.u64();
+    let items:T[] = [];
+    for(let ix = 0; ix < length; ix++) items.push(item());
+    return items;
+  }
+}
+
+class Writer {
+  bytes:number[] = [];
+
+  u8(value:number) { this.bytes.push(value & 0xff); }
+  u32(value:number) {
+    this.u8(value >>> 24);
+    this.u8(value >>> 16);
+    this.u8(value >>> 8);
+    this.u8(value);
+  }
+  u64(value:number) {
+    this.u32(0);
+    this.u32(value);
+  }
+  string(value:string) {
+    let bytes = utf8Encode(value);
+    this.u64(bytes.length);
+    for(let byte of bytes) this.u8(byte);
+  }
+  vec<T>(items:T[], item:(value:T) => void) {
+    this.u64(items.length);
+    for(let value of items) item(value);
+  }
+  buffer() {
+    return new Uint8Array(this.bytes).buffer;
+  }
+}
+
+// Numbers travel as the bits of an f32, just like they're interned on the server.
+let numberView = new DataView(new ArrayBuffer(4));
+function numberToBits(value:number) {
+  numberView.setFloat32(0, value);
+  return numberView.getUint32(0);
+}
+function bitsToNumber(bits:number) {
+  numberView.setUint32(0, bits);
+  return numberView.getFloat32(0);
+}
+
+export interface WireDiff { client:string, adds:Value[][], removes:Value[][], reset:boolean }
+
+// Each connection has its own dictionary in each direction, so a new socket needs a new
+// WireConnection.
+export class WireConnection {
+  protected received:{[id:number]: string} = {};
+  protected sent:{[value:string]: number} = {};
+  protected sentCount = 0;
+
+  decodeDiff(buffer:ArrayBuffer):WireDiff {
+    let reader = new Reader(buffer);
+    let tag = reader.u32();
+    if(tag !== MessageTag.Diff) throw new Error(`Unexpected binary message: ${tag}`);
+    let client = reader.string();
+    for(let [id, value] of reader.vec(() => [reader.u32(), reader.string()] as [number, string])) {
+      this.received[id] = value;
+    }
+    let value = ():Value => {
+      let valueTag = reader.u32();
+      if(valueTag === ValueTag.Null) return null;
+      if(valueTag === ValueTag.Number) return bitsToNumber(reader.u32());
+      let id = reader.u32();
+      if(this.received[id] === undefined) throw new Error(`Unknown string id: ${id}`);
+      return this.received[id];
+    };
+    let adds = reader.vec(() => reader.vec(value));
+    let removes = reader.vec(() => reader.vec(value));
+    let reset = reader.u8() !== 0;
+    return {client, adds, removes, reset};
+  }
+
+  encodeTransaction(client:string, adds:EAV[], removes:EAV[]):ArrayBuffer {
+    // New strings have to be given ids up front since the dictionary goes out ahead of the rows.
+    let fresh:[number, string][] = [];
+    for(let eav of adds.concat(removes)) {
+      for(let value of eav) {
+        if(typeof value === "string" && this.sent[value] === undefined) {
+          let id = this.sent[value] = this.sentCount++;
+          fresh.push([id, value]);
+        }
+      }
+    }
+
+    let writer = new Writer();
+    let value = (value:Value) => {
+      if(typeof value === "string") {
+        writer.u32(ValueTag.String);
+        writer.u32(this.sent[value]);
+      } else if(typeof value === "number") {
+        writer.u32(ValueTag.Number);
+        writer.u32(numberToBits(value));
+      } else {
+        writer.u32(ValueTag.Null);
+      }
+    };
+    let eav = (eav:EAV) => eav.forEach(value);
+    writer.u32(MessageTag.Transaction);
+    writer.string(client);
+    writer.vec(fresh, ([id, string]) => { writer.u32(id); writer.string(string); });
+    writer.vec(adds, eav);
+    writer.vec(removes, eav);
+    return writer.buffer();
+  }
+}